@@ -0,0 +1,22 @@
+// Copyright (C) 2026 Chromatic
+// Licensed under the GNU AGPL v3.0. See LICENSE file for details.
+// Website: https://chromatic.hu
+
+//! Regenerates include/sensitivity.h from the exported functions.
+
+use std::env;
+use std::path::PathBuf;
+
+fn main() {
+    let crate_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").expect("set by cargo"));
+    let config = cbindgen::Config::from_file(crate_dir.join("cbindgen.toml"))
+        .expect("reading cbindgen.toml");
+    cbindgen::Builder::new()
+        .with_crate(&crate_dir)
+        .with_config(config)
+        .generate()
+        .expect("generating the C header")
+        .write_to_file(crate_dir.join("include/sensitivity.h"));
+    println!("cargo:rerun-if-changed=src/lib.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+}
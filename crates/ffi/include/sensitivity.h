@@ -0,0 +1,201 @@
+/* Copyright (C) 2026 Chromatic
+ * Licensed under the GNU AGPL v3.0. See LICENSE file for details.
+ * Website: https://chromatic.hu
+ *
+ * Generated by cbindgen from crates/ffi/src/lib.rs. Do not edit. */
+
+#ifndef SENSITIVITY_H
+#define SENSITIVITY_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Result of every call.
+typedef enum SensStatus {
+  SENS_STATUS_OK = 0,
+  // A required pointer was null or a string was not UTF-8.
+  SENS_STATUS_INVALID_ARGUMENT = 1,
+  SENS_STATUS_CANCELLED = 2,
+  SENS_STATUS_DEADLINE_EXCEEDED = 3,
+  // The wipe a flash needs was declined.
+  SENS_STATUS_WIPE_DECLINED = 4,
+  SENS_STATUS_FAILED = 5,
+  // The library hit an internal error; the session should be closed.
+  SENS_STATUS_PANIC = 6,
+} SensStatus;
+
+typedef enum SensPhase {
+  SENS_PHASE_DOWNLOAD = 0,
+  SENS_PHASE_HASH = 1,
+  SENS_PHASE_VALIDATE = 2,
+  SENS_PHASE_SIDELOAD = 3,
+} SensPhase;
+
+// Whether, and why, a flash erases user data.
+typedef enum SensWipe {
+  SENS_WIPE_NONE = 0,
+  // Xiaomi requires the wipe for this package.
+  SENS_WIPE_SERVER = 1,
+  // The caller asked for the wipe.
+  SENS_WIPE_REQUESTED = 2,
+} SensWipe;
+
+// Cancels an operation running on another thread.
+typedef struct SensCancel SensCancel;
+
+// A validated flash: token and wipe decision.
+typedef struct SensPlan SensPlan;
+
+// An open recovery connection.
+typedef struct SensSession SensSession;
+
+// Identity reported by recovery. Freed with `sens_device_info_free`.
+typedef struct SensDeviceInfo {
+  char *device;
+  char *serial;
+  char *version;
+  char *codebase;
+  char *branch;
+  char *language;
+  char *region;
+  char *romzone;
+} SensDeviceInfo;
+
+// Optional callbacks; any member may be null. `confirm_wipe` returns true
+// to erase user data; without it every wipe is declined.
+typedef struct SensCallbacks {
+  void *user_data;
+  void (*progress)(void *user_data, enum SensPhase phase, uint64_t current, uint64_t total);
+  void (*message)(void *user_data, const char *message);
+  bool (*confirm_wipe)(void *user_data, enum SensWipe reason);
+} SensCallbacks;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// The library version, as a static string.
+const char *sens_version(void);
+
+// The message of the last failed call on this thread, or null. Valid until
+// the next call on this thread.
+const char *sens_last_error(void);
+
+// # Safety
+// `value` is null or a string returned by this library, not yet freed.
+void sens_string_free(char *value);
+
+// Lists Mi Assistant recovery interfaces as a JSON array.
+//
+// # Safety
+// `out_json` is valid for writes; free the result with `sens_string_free`.
+enum SensStatus sens_discover(char **out_json);
+
+// Connects to the recovery at `device_index` among discovered interfaces.
+//
+// # Safety
+// `out` is valid for writes; close the session with `sens_session_close`.
+enum SensStatus sens_session_open(size_t device_index, struct SensSession **out);
+
+// # Safety
+// `session` is null or an open session not used by another thread.
+void sens_session_close(struct SensSession *session);
+
+// Reads the device identity from recovery.
+//
+// # Safety
+// `session` is an open session and `out` is valid for writes; free the
+// result with `sens_device_info_free`.
+enum SensStatus sens_session_device_info(struct SensSession *session, struct SensDeviceInfo **out);
+
+// # Safety
+// `info` is null or was returned by `sens_session_device_info`.
+void sens_device_info_free(struct SensDeviceInfo *info);
+
+struct SensCancel *sens_cancel_new(void);
+
+// Asks operations using `cancel` to stop; safe from any thread.
+//
+// # Safety
+// `cancel` is null or a live `SensCancel`.
+void sens_cancel_request(const struct SensCancel *cancel);
+
+// # Safety
+// `cancel` is null or a `SensCancel` no operation still uses.
+void sens_cancel_free(struct SensCancel *cancel);
+
+// Computes a package MD5 as 32 lowercase hex digits, using the hash cache.
+//
+// # Safety
+// `path` is a NUL-terminated string, `callbacks` and `cancel` are null or
+// valid, and `out_md5` is valid for writes; free it with `sens_string_free`.
+enum SensStatus sens_md5(const char *path,
+                         const struct SensCallbacks *callbacks,
+                         const struct SensCancel *cancel,
+                         char **out_md5);
+
+// Asks `server_url` to approve the package with `md5` for this device.
+//
+// # Safety
+// `session` is an open session, the strings are NUL-terminated,
+// `callbacks` and `cancel` are null or valid, and `out` is valid for
+// writes; free the plan with `sens_plan_free`.
+enum SensStatus sens_validate(struct SensSession *session,
+                              const char *server_url,
+                              const char *md5,
+                              bool force_wipe,
+                              const struct SensCallbacks *callbacks,
+                              const struct SensCancel *cancel,
+                              struct SensPlan **out);
+
+// # Safety
+// `plan` is null or a live plan.
+enum SensWipe sens_plan_wipe(const struct SensPlan *plan);
+
+// The server's answer as JSON: allowed ROMs, message and offered packages.
+// The validation token is not included.
+//
+// # Safety
+// `plan` is a live plan and `out_json` is valid for writes; free the
+// result with `sens_string_free`.
+enum SensStatus sens_plan_response_json(const struct SensPlan *plan, char **out_json);
+
+// # Safety
+// `plan` is null or a live plan.
+void sens_plan_free(struct SensPlan *plan);
+
+// Downloads the LatestRom the server offers this device into the ROM
+// library, reusing an intact stored copy.
+//
+// # Safety
+// As for `sens_validate`; free `out_path` with `sens_string_free`.
+enum SensStatus sens_download_latest(struct SensSession *session,
+                                     const char *server_url,
+                                     const struct SensCallbacks *callbacks,
+                                     const struct SensCancel *cancel,
+                                     char **out_path);
+
+// Sideloads the package at `path` under `plan` after checking its OTA
+// signature; a signer that is not a known release certificate fails unless
+// `allow_unknown_signer` is set. A wipe is asked for through
+// `confirm_wipe`; declining returns `SENS_STATUS_WIPE_DECLINED` before the
+// device is touched. Recovery ends the session afterwards.
+//
+// # Safety
+// `session` and `plan` are live, `path` is NUL-terminated, and `callbacks`
+// and `cancel` are null or valid.
+enum SensStatus sens_sideload(struct SensSession *session,
+                              const struct SensPlan *plan,
+                              const char *path,
+                              bool allow_unknown_signer,
+                              const struct SensCallbacks *callbacks,
+                              const struct SensCancel *cancel);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* SENSITIVITY_H */
@@ -0,0 +1,629 @@
+// Copyright (C) 2026 Chromatic
+// Licensed under the GNU AGPL v3.0. See LICENSE file for details.
+// Website: https://chromatic.hu
+
+//! C API for the Sensitivity library. include/sensitivity.h is generated
+//! from this file.
+//!
+//! Every function returns a `SensStatus`; after a failure,
+//! `sens_last_error()` describes it on the calling thread. Strings and
+//! objects the library returns are freed with the matching `*_free`
+//! function. Callbacks may run on worker threads, so they must be
+//! thread-safe.
+
+use std::cell::RefCell;
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::fmt;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::ptr;
+
+use anyhow::{anyhow, Result};
+use sensitivity::cancel::CancelToken;
+use sensitivity::flash::{
+    check_package, Confirmer, FlashOutcome, FlashPlan, FlashSession, TokenPolicy, WipeReason,
+};
+use sensitivity::library::Library;
+use sensitivity::machine::ErrorCode;
+use sensitivity::mi::{DeviceInfo, MiClient};
+use sensitivity::observer::{Observer, Phase};
+use sensitivity::sideload::FileSource;
+use sensitivity::usb::UsbTransport;
+use sensitivity::util::hash_cache;
+
+/// Result of every call.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SensStatus {
+    Ok = 0,
+    /// A required pointer was null or a string was not UTF-8.
+    InvalidArgument = 1,
+    Cancelled = 2,
+    DeadlineExceeded = 3,
+    /// The wipe a flash needs was declined.
+    WipeDeclined = 4,
+    Failed = 5,
+    /// The library hit an internal error; the session should be closed.
+    Panic = 6,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SensPhase {
+    Download = 0,
+    Hash = 1,
+    Validate = 2,
+    Sideload = 3,
+}
+
+/// Whether, and why, a flash erases user data.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SensWipe {
+    None = 0,
+    /// Xiaomi requires the wipe for this package.
+    Server = 1,
+    /// The caller asked for the wipe.
+    Requested = 2,
+}
+
+/// Optional callbacks; any member may be null. `confirm_wipe` returns true
+/// to erase user data; without it every wipe is declined.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct SensCallbacks {
+    pub user_data: *mut c_void,
+    pub progress:
+        Option<extern "C" fn(user_data: *mut c_void, phase: SensPhase, current: u64, total: u64)>,
+    pub message: Option<extern "C" fn(user_data: *mut c_void, message: *const c_char)>,
+    pub confirm_wipe: Option<extern "C" fn(user_data: *mut c_void, reason: SensWipe) -> bool>,
+}
+
+/// Identity reported by recovery. Freed with `sens_device_info_free`.
+#[repr(C)]
+pub struct SensDeviceInfo {
+    pub device: *mut c_char,
+    pub serial: *mut c_char,
+    pub version: *mut c_char,
+    pub codebase: *mut c_char,
+    pub branch: *mut c_char,
+    pub language: *mut c_char,
+    pub region: *mut c_char,
+    pub romzone: *mut c_char,
+}
+
+/// An open recovery connection.
+pub struct SensSession {
+    client: MiClient,
+}
+
+/// A validated flash: token and wipe decision.
+pub struct SensPlan {
+    plan: FlashPlan,
+}
+
+/// Cancels an operation running on another thread.
+pub struct SensCancel {
+    token: CancelToken,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+#[derive(Debug)]
+struct InvalidArgument(&'static str);
+
+impl fmt::Display for InvalidArgument {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid argument: {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidArgument {}
+
+fn status_of(error: &anyhow::Error) -> SensStatus {
+    if error.is::<InvalidArgument>() {
+        return SensStatus::InvalidArgument;
+    }
+    match ErrorCode::of(error) {
+        ErrorCode::Cancelled => SensStatus::Cancelled,
+        ErrorCode::DeadlineExceeded => SensStatus::DeadlineExceeded,
+        ErrorCode::WipeDeclined => SensStatus::WipeDeclined,
+        ErrorCode::Failed => SensStatus::Failed,
+    }
+}
+
+fn set_last_error(message: String) {
+    let message = CString::new(message.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|slot| *slot.borrow_mut() = Some(message));
+}
+
+/// Runs `call`, turning errors and panics into a status.
+fn guard(call: impl FnOnce() -> Result<()>) -> SensStatus {
+    match catch_unwind(AssertUnwindSafe(call)) {
+        Ok(Ok(())) => {
+            LAST_ERROR.with(|slot| *slot.borrow_mut() = None);
+            SensStatus::Ok
+        }
+        Ok(Err(error)) => {
+            set_last_error(format!("{error:#}"));
+            status_of(&error)
+        }
+        Err(_) => {
+            set_last_error("Internal error in the Sensitivity library".into());
+            SensStatus::Panic
+        }
+    }
+}
+
+fn non_null<T>(pointer: *const T, name: &'static str) -> Result<()> {
+    if pointer.is_null() {
+        return Err(InvalidArgument(name).into());
+    }
+    Ok(())
+}
+
+/// # Safety
+/// `pointer` is null or a NUL-terminated string valid for `'a`.
+unsafe fn string_arg<'a>(pointer: *const c_char, name: &'static str) -> Result<&'a str> {
+    non_null(pointer, name)?;
+    CStr::from_ptr(pointer)
+        .to_str()
+        .map_err(|_| InvalidArgument(name).into())
+}
+
+fn into_c(value: String) -> *mut c_char {
+    CString::new(value.replace('\0', " "))
+        .unwrap_or_default()
+        .into_raw()
+}
+
+/// # Safety
+/// `out` is null or valid for writes.
+unsafe fn write_out<T>(out: *mut T, value: T, name: &'static str) -> Result<()> {
+    non_null(out, name)?;
+    out.write(value);
+    Ok(())
+}
+
+struct CallbackObserver {
+    callbacks: Option<SensCallbacks>,
+}
+
+// The header requires thread-safe callbacks and user data.
+unsafe impl Send for CallbackObserver {}
+unsafe impl Sync for CallbackObserver {}
+
+impl CallbackObserver {
+    /// # Safety
+    /// `callbacks` is null or points to a valid `SensCallbacks`.
+    unsafe fn new(callbacks: *const SensCallbacks) -> Self {
+        Self {
+            callbacks: callbacks.as_ref().copied(),
+        }
+    }
+}
+
+impl Observer for CallbackObserver {
+    fn progress(&self, phase: Phase, current: u64, total: u64) {
+        let Some(callbacks) = &self.callbacks else {
+            return;
+        };
+        let phase = match phase {
+            Phase::Download => SensPhase::Download,
+            Phase::Hash => SensPhase::Hash,
+            Phase::Validate => SensPhase::Validate,
+            Phase::Sideload => SensPhase::Sideload,
+        };
+        if let Some(progress) = callbacks.progress {
+            progress(callbacks.user_data, phase, current, total);
+        }
+    }
+
+    fn message(&self, message: &str) {
+        let Some(callbacks) = &self.callbacks else {
+            return;
+        };
+        if let (Some(callback), Ok(message)) = (callbacks.message, CString::new(message)) {
+            callback(callbacks.user_data, message.as_ptr());
+        }
+    }
+}
+
+impl Confirmer for CallbackObserver {
+    fn confirm_wipe(&mut self, reason: WipeReason) -> Result<bool> {
+        let reason = match reason {
+            WipeReason::Server => SensWipe::Server,
+            WipeReason::Requested => SensWipe::Requested,
+        };
+        Ok(self
+            .callbacks
+            .and_then(|callbacks| {
+                callbacks
+                    .confirm_wipe
+                    .map(|confirm| confirm(callbacks.user_data, reason))
+            })
+            .unwrap_or(false))
+    }
+}
+
+/// # Safety
+/// `cancel` is null or a live `SensCancel`.
+unsafe fn cancel_token(cancel: *const SensCancel) -> CancelToken {
+    cancel
+        .as_ref()
+        .map_or_else(CancelToken::new, |cancel| cancel.token.clone())
+}
+
+/// The library version, as a static string.
+#[no_mangle]
+pub extern "C" fn sens_version() -> *const c_char {
+    concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr().cast()
+}
+
+/// The message of the last failed call on this thread, or null. Valid until
+/// the next call on this thread.
+#[no_mangle]
+pub extern "C" fn sens_last_error() -> *const c_char {
+    LAST_ERROR.with(|slot| slot.borrow().as_ref().map_or(ptr::null(), |m| m.as_ptr()))
+}
+
+/// # Safety
+/// `value` is null or a string returned by this library, not yet freed.
+#[no_mangle]
+pub unsafe extern "C" fn sens_string_free(value: *mut c_char) {
+    if !value.is_null() {
+        drop(CString::from_raw(value));
+    }
+}
+
+/// Lists Mi Assistant recovery interfaces as a JSON array.
+///
+/// # Safety
+/// `out_json` is valid for writes; free the result with `sens_string_free`.
+#[no_mangle]
+pub unsafe extern "C" fn sens_discover(out_json: *mut *mut c_char) -> SensStatus {
+    guard(|| {
+        non_null(out_json, "out_json")?;
+        let devices = UsbTransport::discover()?;
+        write_out(
+            out_json,
+            into_c(serde_json::to_string(&devices)?),
+            "out_json",
+        )
+    })
+}
+
+/// Connects to the recovery at `device_index` among discovered interfaces.
+///
+/// # Safety
+/// `out` is valid for writes; close the session with `sens_session_close`.
+#[no_mangle]
+pub unsafe extern "C" fn sens_session_open(
+    device_index: usize,
+    out: *mut *mut SensSession,
+) -> SensStatus {
+    guard(|| {
+        non_null(out, "out")?;
+        let transport = UsbTransport::open(device_index, false)?;
+        let session = SensSession {
+            client: MiClient::new(transport)?,
+        };
+        write_out(out, Box::into_raw(Box::new(session)), "out")
+    })
+}
+
+/// # Safety
+/// `session` is null or an open session not used by another thread.
+#[no_mangle]
+pub unsafe extern "C" fn sens_session_close(session: *mut SensSession) {
+    if !session.is_null() {
+        drop(Box::from_raw(session));
+    }
+}
+
+/// Reads the device identity from recovery.
+///
+/// # Safety
+/// `session` is an open session and `out` is valid for writes; free the
+/// result with `sens_device_info_free`.
+#[no_mangle]
+pub unsafe extern "C" fn sens_session_device_info(
+    session: *mut SensSession,
+    out: *mut *mut SensDeviceInfo,
+) -> SensStatus {
+    guard(|| {
+        non_null(out, "out")?;
+        let session = session.as_mut().ok_or(InvalidArgument("session"))?;
+        let info = session.client.read_all_info()?;
+        let info = SensDeviceInfo {
+            device: into_c(info.device),
+            serial: into_c(info.sn.reveal().to_string()),
+            version: into_c(info.version),
+            codebase: into_c(info.codebase),
+            branch: into_c(info.branch),
+            language: into_c(info.language),
+            region: into_c(info.region),
+            romzone: into_c(info.romzone),
+        };
+        write_out(out, Box::into_raw(Box::new(info)), "out")
+    })
+}
+
+/// # Safety
+/// `info` is null or was returned by `sens_session_device_info`.
+#[no_mangle]
+pub unsafe extern "C" fn sens_device_info_free(info: *mut SensDeviceInfo) {
+    if info.is_null() {
+        return;
+    }
+    let info = Box::from_raw(info);
+    for field in [
+        info.device,
+        info.serial,
+        info.version,
+        info.codebase,
+        info.branch,
+        info.language,
+        info.region,
+        info.romzone,
+    ] {
+        sens_string_free(field);
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn sens_cancel_new() -> *mut SensCancel {
+    Box::into_raw(Box::new(SensCancel {
+        token: CancelToken::new(),
+    }))
+}
+
+/// Asks operations using `cancel` to stop; safe from any thread.
+///
+/// # Safety
+/// `cancel` is null or a live `SensCancel`.
+#[no_mangle]
+pub unsafe extern "C" fn sens_cancel_request(cancel: *const SensCancel) {
+    if let Some(cancel) = cancel.as_ref() {
+        cancel.token.cancel();
+    }
+}
+
+/// # Safety
+/// `cancel` is null or a `SensCancel` no operation still uses.
+#[no_mangle]
+pub unsafe extern "C" fn sens_cancel_free(cancel: *mut SensCancel) {
+    if !cancel.is_null() {
+        drop(Box::from_raw(cancel));
+    }
+}
+
+/// Computes a package MD5 as 32 lowercase hex digits, using the hash cache.
+///
+/// # Safety
+/// `path` is a NUL-terminated string, `callbacks` and `cancel` are null or
+/// valid, and `out_md5` is valid for writes; free it with `sens_string_free`.
+#[no_mangle]
+pub unsafe extern "C" fn sens_md5(
+    path: *const c_char,
+    callbacks: *const SensCallbacks,
+    cancel: *const SensCancel,
+    out_md5: *mut *mut c_char,
+) -> SensStatus {
+    guard(|| {
+        non_null(out_md5, "out_md5")?;
+        let path = PathBuf::from(string_arg(path, "path")?);
+        let observer = CallbackObserver::new(callbacks);
+        let md5 = hash_cache::md5_cached(&path, &observer, &cancel_token(cancel))?;
+        write_out(out_md5, into_c(md5), "out_md5")
+    })
+}
+
+/// Asks `server_url` to approve the package with `md5` for this device.
+///
+/// # Safety
+/// `session` is an open session, the strings are NUL-terminated,
+/// `callbacks` and `cancel` are null or valid, and `out` is valid for
+/// writes; free the plan with `sens_plan_free`.
+#[no_mangle]
+pub unsafe extern "C" fn sens_validate(
+    session: *mut SensSession,
+    server_url: *const c_char,
+    md5: *const c_char,
+    force_wipe: bool,
+    callbacks: *const SensCallbacks,
+    cancel: *const SensCancel,
+    out: *mut *mut SensPlan,
+) -> SensStatus {
+    guard(|| {
+        non_null(out, "out")?;
+        let session = session.as_mut().ok_or(InvalidArgument("session"))?;
+        let server_url = string_arg(server_url, "server_url")?;
+        let flash = FlashSession {
+            identity: session.client.read_all_info()?,
+            md5: string_arg(md5, "md5")?.to_string(),
+            token: TokenPolicy::Validate,
+            force_wipe,
+        };
+        let observer = CallbackObserver::new(callbacks);
+        let plan = flash.plan(server_url, &observer, &cancel_token(cancel))?;
+        write_out(out, Box::into_raw(Box::new(SensPlan { plan })), "out")
+    })
+}
+
+/// # Safety
+/// `plan` is null or a live plan.
+#[no_mangle]
+pub unsafe extern "C" fn sens_plan_wipe(plan: *const SensPlan) -> SensWipe {
+    match plan.as_ref().and_then(|plan| plan.plan.wipe()) {
+        None => SensWipe::None,
+        Some(WipeReason::Server) => SensWipe::Server,
+        Some(WipeReason::Requested) => SensWipe::Requested,
+    }
+}
+
+/// The server's answer as JSON: allowed ROMs, message and offered packages.
+/// The validation token is not included.
+///
+/// # Safety
+/// `plan` is a live plan and `out_json` is valid for writes; free the
+/// result with `sens_string_free`.
+#[no_mangle]
+pub unsafe extern "C" fn sens_plan_response_json(
+    plan: *const SensPlan,
+    out_json: *mut *mut c_char,
+) -> SensStatus {
+    guard(|| {
+        let plan = plan.as_ref().ok_or(InvalidArgument("plan"))?;
+        let response = plan.plan.response.clone().unwrap_or_default();
+        let packages: Vec<_> = response
+            .packages()
+            .map(|(kind, package)| serde_json::json!({ "kind": kind, "package": package }))
+            .collect();
+        let json = serde_json::json!({
+            "allowed": response.pkgrom_validate,
+            "message": response.code_message,
+            "packages": packages,
+        });
+        write_out(out_json, into_c(json.to_string()), "out_json")
+    })
+}
+
+/// # Safety
+/// `plan` is null or a live plan.
+#[no_mangle]
+pub unsafe extern "C" fn sens_plan_free(plan: *mut SensPlan) {
+    if !plan.is_null() {
+        drop(Box::from_raw(plan));
+    }
+}
+
+/// Downloads the LatestRom the server offers this device into the ROM
+/// library, reusing an intact stored copy.
+///
+/// # Safety
+/// As for `sens_validate`; free `out_path` with `sens_string_free`.
+#[no_mangle]
+pub unsafe extern "C" fn sens_download_latest(
+    session: *mut SensSession,
+    server_url: *const c_char,
+    callbacks: *const SensCallbacks,
+    cancel: *const SensCancel,
+    out_path: *mut *mut c_char,
+) -> SensStatus {
+    guard(|| {
+        non_null(out_path, "out_path")?;
+        let session = session.as_mut().ok_or(InvalidArgument("session"))?;
+        let server_url = string_arg(server_url, "server_url")?;
+        let identity: DeviceInfo = session.client.read_all_info()?;
+        let observer = CallbackObserver::new(callbacks);
+        let library = Library::open_default()?;
+        let entry =
+            library.fetch_latest(&identity, server_url, &observer, &cancel_token(cancel))?;
+        let path = library.object_path(&entry.md5).display().to_string();
+        write_out(out_path, into_c(path), "out_path")
+    })
+}
+
+/// Sideloads the package at `path` under `plan` after checking its OTA
+/// signature; a signer that is not a known release certificate fails unless
+/// `allow_unknown_signer` is set. A wipe is asked for through
+/// `confirm_wipe`; declining returns `SENS_STATUS_WIPE_DECLINED` before the
+/// device is touched. Recovery ends the session afterwards.
+///
+/// # Safety
+/// `session` and `plan` are live, `path` is NUL-terminated, and `callbacks`
+/// and `cancel` are null or valid.
+#[no_mangle]
+pub unsafe extern "C" fn sens_sideload(
+    session: *mut SensSession,
+    plan: *const SensPlan,
+    path: *const c_char,
+    allow_unknown_signer: bool,
+    callbacks: *const SensCallbacks,
+    cancel: *const SensCancel,
+) -> SensStatus {
+    guard(|| {
+        let session = session.as_mut().ok_or(InvalidArgument("session"))?;
+        let plan = plan.as_ref().ok_or(InvalidArgument("plan"))?;
+        let path = PathBuf::from(string_arg(path, "path")?);
+        let mut confirmer = CallbackObserver::new(callbacks);
+        let observer = CallbackObserver::new(callbacks);
+        check_package(&path, allow_unknown_signer, &observer)?;
+        let mut source = FileSource::open(&path)?;
+        let outcome = plan.plan.execute(
+            &mut session.client,
+            &mut source,
+            64 * 1024,
+            &mut confirmer,
+            &observer,
+            &cancel_token(cancel),
+        )?;
+        match outcome {
+            FlashOutcome::Completed { .. } => Ok(()),
+            FlashOutcome::WipeDeclined => Err(anyhow!(sensitivity::flash::WipeDeclined)),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    fn last_error() -> String {
+        unsafe { CStr::from_ptr(sens_last_error()) }
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[test]
+    fn null_arguments_report_which_one() {
+        let status = unsafe { sens_session_device_info(ptr::null_mut(), ptr::null_mut()) };
+        assert_eq!(status, SensStatus::InvalidArgument);
+        assert!(last_error().contains("out"));
+        assert!(unsafe { sens_plan_wipe(ptr::null()) } == SensWipe::None);
+    }
+
+    extern "C" fn count(user_data: *mut c_void, phase: SensPhase, current: u64, _total: u64) {
+        if phase == SensPhase::Hash {
+            let seen = unsafe { &*(user_data as *const AtomicU64) };
+            seen.store(current, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn md5_reports_progress_and_honours_cancel() {
+        let directory = tempfile::tempdir().unwrap();
+        // Keep the hash cache out of the developer's data directory.
+        std::env::set_var("XDG_DATA_HOME", directory.path().join("data"));
+        let path = directory.path().join("rom.zip");
+        std::fs::write(&path, b"hello world").unwrap();
+        let path = CString::new(path.to_str().unwrap()).unwrap();
+        let seen = AtomicU64::new(0);
+        let callbacks = SensCallbacks {
+            user_data: &seen as *const AtomicU64 as *mut c_void,
+            progress: Some(count),
+            message: None,
+            confirm_wipe: None,
+        };
+
+        let mut md5 = ptr::null_mut();
+        let status = unsafe { sens_md5(path.as_ptr(), &callbacks, ptr::null(), &mut md5) };
+        assert_eq!(status, SensStatus::Ok);
+        let digest = unsafe { CStr::from_ptr(md5) }.to_str().unwrap().to_owned();
+        unsafe { sens_string_free(md5) };
+        assert_eq!(digest, "5eb63bbbe01eeed093cb22bb8f5acdc3");
+        assert_eq!(seen.load(Ordering::SeqCst), 11);
+
+        let cancel = sens_cancel_new();
+        unsafe { sens_cancel_request(cancel) };
+        std::fs::write(directory.path().join("other.zip"), b"other").unwrap();
+        let other = CString::new(directory.path().join("other.zip").to_str().unwrap()).unwrap();
+        let status = unsafe { sens_md5(other.as_ptr(), ptr::null(), cancel, &mut md5) };
+        unsafe { sens_cancel_free(cancel) };
+        assert_eq!(status, SensStatus::Cancelled);
+    }
+}
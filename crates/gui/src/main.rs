@@ -5,18 +5,40 @@
 
 use std::collections::HashMap;
 use std::path::PathBuf;
-use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::{self, Receiver, Sender};
-use std::sync::Arc;
 use std::thread;
 
 use eframe::egui;
+use sensitivity::cancel::CancelToken;
+use sensitivity::flash::{
+    check_package, FlashOutcome, FlashPlan, FlashSession, PreApproved, TokenPolicy,
+};
 use sensitivity::mi::{DeviceInfo, MiClient};
+use sensitivity::observer::{Observer, Phase};
+use sensitivity::sideload::FileSource;
 use sensitivity::usb::{UsbDeviceInfo, UsbTransport};
-use sensitivity::{sideload, util, validate};
+use sensitivity::validate::PackageKind;
+use sensitivity::{util, validate};
 
 const SERVER_URL: &str = "https://update.miui.com/updates/miotaV3.php";
 
+fn offered_packages(
+    response: &validate::ValidateResult,
+) -> Vec<(PackageKind, validate::RomPackage)> {
+    response
+        .packages()
+        .map(|(kind, package)| (kind, package.clone()))
+        .collect()
+}
+
+/// Catalog key an offered package is shown under.
+fn package_label(kind: PackageKind) -> &'static str {
+    match kind {
+        PackageKind::Latest => "label.latest_rom",
+        PackageKind::Incremental => "label.incremental_rom",
+    }
+}
+
 fn main() -> eframe::Result<()> {
     let options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default()
@@ -37,10 +59,10 @@ enum Message {
     Error(String),
     DeviceInfo(DeviceInfo),
     Roms(String),
+    Packages(Vec<(PackageKind, validate::RomPackage)>),
     Validated {
         path: PathBuf,
-        token: String,
-        erase: bool,
+        plan: Box<FlashPlan>,
         md5: String,
     },
     Progress {
@@ -50,14 +72,31 @@ enum Message {
     Finished(String),
 }
 
+/// Forwards hashing and sideload progress to the UI thread.
+struct GuiObserver(Sender<Message>);
+
+impl Observer for GuiObserver {
+    fn progress(&self, _phase: Phase, current: u64, total: u64) {
+        let _ = self.0.send(Message::Progress {
+            sent: current,
+            total,
+        });
+    }
+}
+
 #[derive(Debug, Clone)]
 struct ValidatedRom {
     path: PathBuf,
-    token: String,
-    erase: bool,
+    plan: FlashPlan,
     md5: String,
 }
 
+impl ValidatedRom {
+    fn erase(&self) -> bool {
+        self.plan.wipe().is_some()
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 enum Language {
     En,
@@ -313,6 +352,7 @@ struct SensitivityApp {
     rom_path: Option<PathBuf>,
     validated: Option<ValidatedRom>,
     rom_listing: String,
+    packages: Vec<(PackageKind, validate::RomPackage)>,
     logs: Vec<String>,
     status: String,
     progress: Option<(u64, u64)>,
@@ -321,7 +361,8 @@ struct SensitivityApp {
     language: Language,
     catalog: HashMap<String, String>,
     receiver: Option<Receiver<Message>>,
-    cancel: Option<Arc<AtomicBool>>,
+    /// Token of the running cancellable task and its button label.
+    cancel: Option<(CancelToken, &'static str)>,
     confirm_flash: bool,
     confirm_format: bool,
 }
@@ -340,6 +381,7 @@ impl SensitivityApp {
             rom_path: persisted.rom_path,
             validated: None,
             rom_listing: String::new(),
+            packages: Vec::new(),
             logs: Vec::new(),
             status: String::new(),
             progress: None,
@@ -447,6 +489,7 @@ impl SensitivityApp {
             let result = (|| -> anyhow::Result<String> {
                 let request = validate::build_request_json(&info, None)?;
                 let response = validate::validate(SERVER_URL, &request)?;
+                let _ = sender.send(Message::Packages(offered_packages(&response)));
                 Ok(response.full_json.unwrap_or_else(|| {
                     response
                         .code_message
@@ -489,28 +532,30 @@ impl SensitivityApp {
             self.log(self.t("log.choose_rom_first"));
             return;
         };
+        let cancel = CancelToken::new();
+        self.cancel = Some((cancel.clone(), "action.cancel"));
         self.start_task(self.t("status.hashing_rom"), move |sender| {
             let result = (|| -> anyhow::Result<ValidatedRom> {
-                let md5 = sensitivity::util::md5::md5_file(&path)?;
-                let request = validate::build_request_json(&info, Some(md5.clone()))?;
-                let response = validate::validate(SERVER_URL, &request)?;
-                let token = response
-                    .validate_token
-                    .filter(|token| !token.is_empty())
-                    .ok_or_else(|| anyhow::anyhow!("error.no_validation_token"))?;
-                Ok(ValidatedRom {
-                    path,
-                    token,
-                    erase: response.pkgrom_erase == Some(1),
-                    md5,
-                })
+                let observer = GuiObserver(sender.clone());
+                check_package(&path, false, &observer)?;
+                let md5 = util::hash_cache::md5_cached(&path, &observer, &cancel)?;
+                let session = FlashSession {
+                    identity: info,
+                    md5: md5.clone(),
+                    token: TokenPolicy::Validate,
+                    force_wipe: false,
+                };
+                let plan = session.plan(SERVER_URL, &observer, &cancel)?;
+                if let Some(response) = &plan.response {
+                    let _ = sender.send(Message::Packages(offered_packages(response)));
+                }
+                Ok(ValidatedRom { path, plan, md5 })
             })();
             match result {
                 Ok(validated) => {
                     let _ = sender.send(Message::Validated {
                         path: validated.path,
-                        token: validated.token,
-                        erase: validated.erase,
+                        plan: Box::new(validated.plan),
                         md5: validated.md5,
                     });
                     let _ = sender.send(Message::Status("status.rom_validated".into()));
@@ -538,26 +583,24 @@ impl SensitivityApp {
         };
         let index = self.selected_device;
         let stop_adb = self.stop_adb;
-        let cancel = Arc::new(AtomicBool::new(false));
-        self.cancel = Some(Arc::clone(&cancel));
+        let cancel = CancelToken::new();
+        self.cancel = Some((cancel.clone(), "action.cancel_flash"));
         self.confirm_flash = false;
         self.start_task(self.t("status.flashing_rom"), move |sender| {
-            let result = (|| -> anyhow::Result<()> {
+            let result = (|| -> anyhow::Result<FlashOutcome> {
                 let mut client = Self::open_client(index, stop_adb)?;
-                sideload::sideload_zip_with_progress(
+                // The confirmation dialog already showed whether this wipes.
+                validated.plan.execute(
                     &mut client,
-                    &validated.path,
+                    &mut FileSource::open(&validated.path)?,
                     64 * 1024,
-                    &validated.token,
-                    validated.erase,
+                    &mut PreApproved,
+                    &GuiObserver(sender.clone()),
                     &cancel,
-                    |sent, total| {
-                        let _ = sender.send(Message::Progress { sent, total });
-                    },
                 )
             })();
             match result {
-                Ok(()) => {
+                Ok(_) => {
                     let _ = sender.send(Message::Finished("status.flash_completed".into()));
                 }
                 Err(error) => {
@@ -638,6 +681,7 @@ impl SensitivityApp {
                     );
                     self.device_info = Some(info);
                     self.validated = None;
+                    self.packages.clear();
                     self.busy = false;
                 }
                 Message::Roms(roms) => {
@@ -648,27 +692,23 @@ impl SensitivityApp {
                     };
                     self.busy = false;
                 }
-                Message::Validated {
-                    path,
-                    token,
-                    erase,
-                    md5,
-                } => {
+                Message::Packages(packages) => self.packages = packages,
+                Message::Validated { path, plan, md5 } => {
                     self.log(
                         self.t("log.validated_file")
                             .replace("{path}", &path.display().to_string())
                             .replace("{md5}", &md5),
                     );
-                    if erase {
+                    if plan.wipe().is_some() {
                         self.log(self.t("log.package_wipe"));
                     }
                     self.validated = Some(ValidatedRom {
                         path,
-                        token,
-                        erase,
+                        plan: *plan,
                         md5,
                     });
                     self.busy = false;
+                    self.cancel = None;
                 }
                 Message::Progress { sent, total } => self.progress = Some((sent, total)),
                 Message::Finished(message) => {
@@ -681,6 +721,35 @@ impl SensitivityApp {
         }
     }
 
+    /// The offered package the validated file matches, if the server listed it.
+    fn validated_package(&self) -> Option<&validate::RomPackage> {
+        let md5 = &self.validated.as_ref()?.md5;
+        self.packages
+            .iter()
+            .map(|(_, package)| package)
+            .find(|package| package.md5.eq_ignore_ascii_case(md5))
+    }
+
+    fn package_details(&self, ui: &mut egui::Ui, package: &validate::RomPackage) {
+        ui.monospace(&package.filename);
+        if let Some(description) = &package.description {
+            ui.label(description);
+        }
+        if let Some(url) = &package.description_url {
+            ui.hyperlink(url);
+        }
+        for section in &package.changelog {
+            egui::CollapsingHeader::new(&section.title)
+                .id_salt((&package.md5, &section.title))
+                .default_open(true)
+                .show(ui, |ui| {
+                    for item in &section.items {
+                        ui.label(format!("• {item}"));
+                    }
+                });
+        }
+    }
+
     fn device_label(device: &UsbDeviceInfo) -> String {
         format!(
             "[{}] {:04x}:{:04x} bus {} address {}",
@@ -797,7 +866,7 @@ impl eframe::App for SensitivityApp {
                 }
                 if let Some(validated) = &self.validated {
                     ui.label(self.t("label.md5").replace("{md5}", &validated.md5));
-                    if validated.erase {
+                    if validated.erase() {
                         ui.colored_label(egui::Color32::RED, self.t("status.validation_wipe"));
                     } else {
                         ui.colored_label(
@@ -815,9 +884,9 @@ impl eframe::App for SensitivityApp {
                 {
                     self.request_flash();
                 }
-                if let Some(cancel) = &self.cancel {
-                    if ui.button(self.t("action.cancel_flash")).clicked() {
-                        cancel.store(true, Ordering::Relaxed);
+                if let Some((cancel, label)) = &self.cancel {
+                    if ui.button(self.t(label)).clicked() {
+                        cancel.cancel();
                         self.status = self.t("status.cancellation_requested");
                     }
                 }
@@ -858,11 +927,12 @@ impl eframe::App for SensitivityApp {
         egui::CentralPanel::default().show(ui, |ui| {
             ui.heading(self.t("section.device_information"));
             if let Some(info) = &self.device_info {
+                let serial = info.sn.to_string();
                 egui::Grid::new("device-info").striped(true).show(ui, |ui| {
                     for (label, value) in [
                         ("label.device", info.device.as_str()),
                         ("label.version", info.version.as_str()),
-                        ("label.serial", info.sn.as_str()),
+                        ("label.serial", serial.as_str()),
                         ("label.codebase", info.codebase.as_str()),
                         ("label.branch", info.branch.as_str()),
                         ("label.language", info.language.as_str()),
@@ -888,6 +958,23 @@ impl eframe::App for SensitivityApp {
                         ui.monospace(&self.rom_listing);
                     }
                 });
+            if !self.packages.is_empty() {
+                ui.separator();
+                ui.heading(self.t("section.offered_packages"));
+                egui::ScrollArea::vertical()
+                    .id_salt("offered-packages")
+                    .max_height(220.0)
+                    .show(ui, |ui| {
+                        for (kind, package) in &self.packages {
+                            ui.strong(format!(
+                                "{}: {}",
+                                self.t(package_label(*kind)),
+                                package.summary()
+                            ));
+                            self.package_details(ui, package);
+                        }
+                    });
+            }
             ui.separator();
             ui.heading(self.t("section.activity"));
             egui::ScrollArea::vertical()
@@ -900,7 +987,7 @@ impl eframe::App for SensitivityApp {
         });
 
         if self.confirm_flash {
-            let erase = self.validated.as_ref().is_some_and(|rom| rom.erase);
+            let erase = self.validated.as_ref().is_some_and(ValidatedRom::erase);
             egui::Window::new(self.t("dialog.confirm_flash"))
                 .collapsible(false)
                 .resizable(false)
@@ -911,6 +998,15 @@ impl eframe::App for SensitivityApp {
                     } else {
                         ui.label(self.t("dialog.flash_question"));
                     }
+                    if let Some(package) = self.validated_package() {
+                        ui.separator();
+                        ui.strong(package.summary());
+                        egui::ScrollArea::vertical()
+                            .id_salt("flash-changelog")
+                            .max_height(240.0)
+                            .show(ui, |ui| self.package_details(ui, package));
+                        ui.separator();
+                    }
                     ui.label(self.t("dialog.keep_connected"));
                     ui.horizontal(|ui| {
                         if ui.button(self.t("action.cancel")).clicked() {
@@ -0,0 +1,86 @@
+# Copyright (C) 2026 Chromatic
+# Licensed under the GNU AGPL v3.0. See LICENSE file for details.
+# Website: https://chromatic.hu
+
+from os import PathLike
+from types import TracebackType
+from typing import Callable, Literal, Optional, Union
+
+__version__: str
+DEFAULT_SERVER_URL: str
+
+Phase = Literal["download", "hash", "validate", "sideload"]
+WipeReason = Literal["server", "requested"]
+Progress = Callable[[Phase, int, int], object]
+
+class SensitivityError(Exception): ...
+class Cancelled(SensitivityError): ...
+class DeadlineExceeded(SensitivityError): ...
+class WipeDeclined(SensitivityError): ...
+
+class UsbDevice:
+    index: int
+    bus: int
+    address: int
+    vendor_id: int
+    product_id: int
+    interface: int
+
+class DeviceInfo:
+    device: str
+    serial: str
+    version: str
+    codebase: str
+    branch: str
+    language: str
+    region: str
+    romzone: str
+
+class Package:
+    label: Literal["latest", "incremental"]
+    filename: str
+    md5: str
+    version: Optional[str]
+    size: Optional[str]
+    codebase: Optional[str]
+
+class Plan:
+    @property
+    def wipe(self) -> Optional[WipeReason]: ...
+    @property
+    def allowed(self) -> list[str]: ...
+    @property
+    def message(self) -> Optional[str]: ...
+    @property
+    def packages(self) -> list[Package]: ...
+
+class Session:
+    def __init__(self, index: int = 0, debug_usb: bool = False) -> None: ...
+    def info(self) -> DeviceInfo: ...
+    def validate(
+        self,
+        md5: str,
+        server_url: str = ...,
+        force_wipe: bool = False,
+        progress: Optional[Progress] = None,
+    ) -> Plan: ...
+    def sideload(
+        self,
+        plan: Plan,
+        path: Union[str, PathLike[str]],
+        progress: Optional[Progress] = None,
+        confirm_wipe: Optional[Callable[[WipeReason], bool]] = None,
+        chunk_size: int = 65536,
+        allow_unknown_signer: bool = False,
+    ) -> None: ...
+    def close(self) -> None: ...
+    def __enter__(self) -> "Session": ...
+    def __exit__(
+        self,
+        kind: Optional[type[BaseException]],
+        value: Optional[BaseException],
+        traceback: Optional[TracebackType],
+    ) -> bool: ...
+
+def discover() -> list[UsbDevice]: ...
+def md5(path: Union[str, PathLike[str]], progress: Optional[Progress] = None) -> str: ...
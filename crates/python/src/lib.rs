@@ -0,0 +1,470 @@
+// Copyright (C) 2026 Chromatic
+// Licensed under the GNU AGPL v3.0. See LICENSE file for details.
+// Website: https://chromatic.hu
+
+//! The `sensitivity` Python module. Long operations release the GIL;
+//! callbacks reacquire it, and an exception raised in one cancels the
+//! operation and is re-raised to the caller. Ctrl-C cancels too.
+
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use pyo3::create_exception;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+use sensitivity::cancel::CancelToken;
+use sensitivity::flash::{
+    check_package, Confirmer, FlashOutcome, FlashPlan, FlashSession, TokenPolicy,
+    WipeDeclined as Declined, WipeReason,
+};
+use sensitivity::machine::ErrorCode;
+use sensitivity::mi::MiClient;
+use sensitivity::observer::{Observer, Phase};
+use sensitivity::sideload::FileSource;
+use sensitivity::usb::UsbTransport;
+use sensitivity::util::hash_cache;
+use sensitivity::validate::{PackageKind, DEFAULT_SERVER_URL};
+
+const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+
+create_exception!(
+    sensitivity,
+    SensitivityError,
+    PyException,
+    "A library operation failed."
+);
+create_exception!(
+    sensitivity,
+    Cancelled,
+    SensitivityError,
+    "The operation was cancelled."
+);
+create_exception!(
+    sensitivity,
+    DeadlineExceeded,
+    SensitivityError,
+    "The operation ran past its deadline."
+);
+create_exception!(
+    sensitivity,
+    WipeDeclined,
+    SensitivityError,
+    "The flash needs a data wipe that was not confirmed."
+);
+
+fn to_py(error: anyhow::Error) -> PyErr {
+    let message = format!("{error:#}");
+    match ErrorCode::of(&error) {
+        ErrorCode::Cancelled => Cancelled::new_err(message),
+        ErrorCode::DeadlineExceeded => DeadlineExceeded::new_err(message),
+        ErrorCode::WipeDeclined => WipeDeclined::new_err(message),
+        ErrorCode::Failed => SensitivityError::new_err(message),
+    }
+}
+
+fn phase_name(phase: Phase) -> &'static str {
+    match phase {
+        Phase::Download => "download",
+        Phase::Hash => "hash",
+        Phase::Validate => "validate",
+        Phase::Sideload => "sideload",
+    }
+}
+
+/// Python callbacks for one operation, plus the first error they raised.
+struct Callbacks {
+    progress: Option<Py<PyAny>>,
+    confirm_wipe: Option<Py<PyAny>>,
+    cancel: CancelToken,
+    error: Mutex<Option<PyErr>>,
+}
+
+impl Callbacks {
+    fn new(progress: Option<Py<PyAny>>, confirm_wipe: Option<Py<PyAny>>) -> Self {
+        Self {
+            progress,
+            confirm_wipe,
+            cancel: CancelToken::new(),
+            error: Mutex::new(None),
+        }
+    }
+
+    fn fail(&self, error: PyErr) {
+        let mut slot = self.error.lock().unwrap_or_else(|e| e.into_inner());
+        slot.get_or_insert(error);
+        self.cancel.cancel();
+    }
+
+    /// The callback's exception if one was raised, else the operation's.
+    fn finish<T>(self, result: anyhow::Result<T>) -> PyResult<T> {
+        let error = self.error.into_inner().unwrap_or_else(|e| e.into_inner());
+        match error {
+            Some(error) => Err(error),
+            None => result.map_err(to_py),
+        }
+    }
+}
+
+impl Observer for Callbacks {
+    fn progress(&self, phase: Phase, current: u64, total: u64) {
+        Python::attach(|py| {
+            if let Err(error) = py.check_signals() {
+                return self.fail(error);
+            }
+            if let Some(progress) = &self.progress {
+                if let Err(error) = progress.call1(py, (phase_name(phase), current, total)) {
+                    self.fail(error);
+                }
+            }
+        });
+    }
+}
+
+struct ConfirmWipe<'a>(&'a Callbacks);
+
+impl Confirmer for ConfirmWipe<'_> {
+    fn confirm_wipe(&mut self, reason: WipeReason) -> anyhow::Result<bool> {
+        let Some(confirm) = &self.0.confirm_wipe else {
+            return Ok(false);
+        };
+        let reason = match reason {
+            WipeReason::Server => "server",
+            WipeReason::Requested => "requested",
+        };
+        Python::attach(|py| {
+            match confirm
+                .call1(py, (reason,))
+                .and_then(|answer| answer.extract::<bool>(py))
+            {
+                Ok(answer) => Ok(answer),
+                Err(error) => {
+                    self.0.fail(error);
+                    Ok(false)
+                }
+            }
+        })
+    }
+}
+
+/// A Mi Assistant recovery interface on the USB bus.
+#[pyclass(module = "sensitivity", frozen, get_all)]
+struct UsbDevice {
+    index: usize,
+    bus: u8,
+    address: u8,
+    vendor_id: u16,
+    product_id: u16,
+    interface: u8,
+}
+
+/// The identity recovery reports.
+#[pyclass(module = "sensitivity", frozen, get_all)]
+struct DeviceInfo {
+    device: String,
+    serial: String,
+    version: String,
+    codebase: String,
+    branch: String,
+    language: String,
+    region: String,
+    romzone: String,
+}
+
+#[pymethods]
+impl DeviceInfo {
+    fn __repr__(&self) -> String {
+        format!(
+            "DeviceInfo(device={:?}, version={:?}, region={:?})",
+            self.device, self.version, self.region
+        )
+    }
+}
+
+/// A ROM the server offers; `label` is `latest` or `incremental`.
+#[pyclass(module = "sensitivity", frozen, get_all)]
+struct Package {
+    label: String,
+    filename: String,
+    md5: String,
+    version: Option<String>,
+    size: Option<String>,
+    codebase: Option<String>,
+}
+
+/// The server's verdict on a package: its token and whether flashing it
+/// wipes data.
+#[pyclass(module = "sensitivity", frozen)]
+struct Plan {
+    plan: FlashPlan,
+}
+
+#[pymethods]
+impl Plan {
+    /// `"server"` or `"requested"` when the flash wipes data, else `None`.
+    #[getter]
+    fn wipe(&self) -> Option<&'static str> {
+        self.plan.wipe().map(|reason| match reason {
+            WipeReason::Server => "server",
+            WipeReason::Requested => "requested",
+        })
+    }
+
+    /// ROMs the server accepts for this device.
+    #[getter]
+    fn allowed(&self) -> Vec<String> {
+        self.plan
+            .response
+            .as_ref()
+            .and_then(|response| response.pkgrom_validate.clone())
+            .unwrap_or_default()
+    }
+
+    #[getter]
+    fn message(&self) -> Option<String> {
+        self.plan
+            .response
+            .as_ref()
+            .and_then(|response| response.code_message.clone())
+    }
+
+    #[getter]
+    fn packages(&self) -> Vec<Package> {
+        let Some(response) = &self.plan.response else {
+            return Vec::new();
+        };
+        response
+            .packages()
+            .map(|(kind, package)| Package {
+                label: match kind {
+                    PackageKind::Latest => "latest",
+                    PackageKind::Incremental => "incremental",
+                }
+                .to_string(),
+                filename: package.filename.clone(),
+                md5: package.md5.clone(),
+                version: package.version.as_ref().map(ToString::to_string),
+                size: package.size.clone(),
+                codebase: package.codebase.clone(),
+            })
+            .collect()
+    }
+}
+
+/// An open recovery connection; also a context manager that closes it.
+#[pyclass(module = "sensitivity")]
+struct Session {
+    client: Mutex<Option<MiClient>>,
+}
+
+impl Session {
+    fn with_client<T: Send>(
+        &self,
+        py: Python<'_>,
+        call: impl FnOnce(&mut MiClient) -> PyResult<T> + Send,
+    ) -> PyResult<T> {
+        let mut client = self.client.lock().unwrap_or_else(|e| e.into_inner());
+        let client = client
+            .as_mut()
+            .ok_or_else(|| SensitivityError::new_err("Session is closed"))?;
+        py.detach(|| call(client))
+    }
+}
+
+#[pymethods]
+impl Session {
+    /// Connects to the recovery at `index` among discovered interfaces.
+    #[new]
+    #[pyo3(signature = (index = 0, debug_usb = false))]
+    fn new(py: Python<'_>, index: usize, debug_usb: bool) -> PyResult<Self> {
+        let client = py
+            .detach(|| MiClient::new(UsbTransport::open(index, debug_usb)?))
+            .map_err(to_py)?;
+        Ok(Self {
+            client: Mutex::new(Some(client)),
+        })
+    }
+
+    fn info(&self, py: Python<'_>) -> PyResult<DeviceInfo> {
+        let info = self.with_client(py, |client| client.read_all_info().map_err(to_py))?;
+        Ok(DeviceInfo {
+            device: info.device,
+            serial: info.sn.reveal().to_string(),
+            version: info.version,
+            codebase: info.codebase,
+            branch: info.branch,
+            language: info.language,
+            region: info.region,
+            romzone: info.romzone,
+        })
+    }
+
+    /// Asks the server whether the package with `md5` may be flashed.
+    #[pyo3(signature = (md5, server_url = DEFAULT_SERVER_URL, force_wipe = false, progress = None))]
+    fn validate(
+        &self,
+        py: Python<'_>,
+        md5: String,
+        server_url: &str,
+        force_wipe: bool,
+        progress: Option<Py<PyAny>>,
+    ) -> PyResult<Plan> {
+        let callbacks = Callbacks::new(progress, None);
+        let result = self.with_client(py, |client| {
+            Ok(client.read_all_info().and_then(|identity| {
+                let session = FlashSession {
+                    identity,
+                    md5,
+                    token: TokenPolicy::Validate,
+                    force_wipe,
+                };
+                session.plan(server_url, &callbacks, &callbacks.cancel)
+            }))
+        })?;
+        Ok(Plan {
+            plan: callbacks.finish(result)?,
+        })
+    }
+
+    /// Streams the package at `path` under `plan` once its OTA signature
+    /// checks out; an unknown signer fails unless `allow_unknown_signer`. A
+    /// wipe calls `confirm_wipe(reason)`; without it, or when it returns
+    /// False, `WipeDeclined` is raised before the device is touched.
+    #[pyo3(signature = (plan, path, progress = None, confirm_wipe = None, chunk_size = DEFAULT_CHUNK_SIZE, allow_unknown_signer = false))]
+    #[allow(clippy::too_many_arguments)]
+    fn sideload(
+        &self,
+        py: Python<'_>,
+        plan: &Plan,
+        path: PathBuf,
+        progress: Option<Py<PyAny>>,
+        confirm_wipe: Option<Py<PyAny>>,
+        chunk_size: usize,
+        allow_unknown_signer: bool,
+    ) -> PyResult<()> {
+        let callbacks = Callbacks::new(progress, confirm_wipe);
+        let result = self.with_client(py, |client| {
+            let checked = check_package(&path, allow_unknown_signer, &callbacks);
+            Ok(checked
+                .and_then(|_| FileSource::open(&path))
+                .and_then(|mut source| {
+                    let outcome = plan.plan.execute(
+                        client,
+                        &mut source,
+                        chunk_size,
+                        &mut ConfirmWipe(&callbacks),
+                        &callbacks,
+                        &callbacks.cancel,
+                    )?;
+                    match outcome {
+                        FlashOutcome::Completed { .. } => Ok(()),
+                        FlashOutcome::WipeDeclined => Err(Declined.into()),
+                    }
+                }))
+        })?;
+        callbacks.finish(result)
+    }
+
+    fn close(&self) {
+        self.client.lock().unwrap_or_else(|e| e.into_inner()).take();
+    }
+
+    fn __enter__(slf: Py<Self>) -> Py<Self> {
+        slf
+    }
+
+    fn __exit__(
+        &self,
+        _kind: Option<Py<PyAny>>,
+        _value: Option<Py<PyAny>>,
+        _traceback: Option<Py<PyAny>>,
+    ) -> bool {
+        self.close();
+        false
+    }
+}
+
+/// Lists Mi Assistant recovery interfaces.
+#[pyfunction]
+fn discover(py: Python<'_>) -> PyResult<Vec<UsbDevice>> {
+    let devices = py.detach(UsbTransport::discover).map_err(to_py)?;
+    Ok(devices
+        .into_iter()
+        .map(|device| UsbDevice {
+            index: device.index,
+            bus: device.bus,
+            address: device.address,
+            vendor_id: device.vendor_id,
+            product_id: device.product_id,
+            interface: device.interface,
+        })
+        .collect())
+}
+
+/// The package MD5 as lowercase hex, using the hash cache.
+#[pyfunction]
+#[pyo3(signature = (path, progress = None))]
+fn md5(py: Python<'_>, path: PathBuf, progress: Option<Py<PyAny>>) -> PyResult<String> {
+    let callbacks = Callbacks::new(progress, None);
+    let result = py.detach(|| hash_cache::md5_cached(&path, &callbacks, &callbacks.cancel));
+    callbacks.finish(result)
+}
+
+#[pymodule(name = "sensitivity")]
+fn sensitivity_python(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    let py = module.py();
+    module.add("__version__", env!("CARGO_PKG_VERSION"))?;
+    module.add("DEFAULT_SERVER_URL", DEFAULT_SERVER_URL)?;
+    module.add("SensitivityError", py.get_type::<SensitivityError>())?;
+    module.add("Cancelled", py.get_type::<Cancelled>())?;
+    module.add("DeadlineExceeded", py.get_type::<DeadlineExceeded>())?;
+    module.add("WipeDeclined", py.get_type::<WipeDeclined>())?;
+    module.add_class::<UsbDevice>()?;
+    module.add_class::<DeviceInfo>()?;
+    module.add_class::<Package>()?;
+    module.add_class::<Plan>()?;
+    module.add_class::<Session>()?;
+    module.add_function(wrap_pyfunction!(discover, module)?)?;
+    module.add_function(wrap_pyfunction!(md5, module)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pyo3::types::PyCFunction;
+    use sensitivity::cancel::Cancelled as CancelReason;
+
+    #[test]
+    fn library_errors_become_typed_exceptions() {
+        Python::initialize();
+        Python::attach(|py| {
+            let cancelled = to_py(anyhow::Error::new(CancelReason::Requested).context("Sideload"));
+            assert!(cancelled.is_instance_of::<Cancelled>(py));
+            assert!(cancelled.is_instance_of::<SensitivityError>(py));
+            let declined = to_py(Declined.into());
+            assert!(declined.is_instance_of::<WipeDeclined>(py));
+            let failed = to_py(anyhow::anyhow!("USB"));
+            assert!(!failed.is_instance_of::<Cancelled>(py));
+        });
+    }
+
+    #[test]
+    fn callback_exceptions_cancel_and_propagate() {
+        Python::initialize();
+        let directory = tempfile::tempdir().unwrap();
+        // Keep the hash cache out of the developer's data directory.
+        std::env::set_var("XDG_DATA_HOME", directory.path().join("data"));
+        let path = directory.path().join("rom.zip");
+        std::fs::write(&path, vec![0u8; 1 << 20]).unwrap();
+
+        Python::attach(|py| {
+            let raise =
+                PyCFunction::new_closure(py, None, None, |_args, _kwargs| -> PyResult<()> {
+                    Err(pyo3::exceptions::PyKeyError::new_err("stop"))
+                })
+                .unwrap();
+            let error = md5(py, path, Some(raise.into_any().unbind())).unwrap_err();
+            assert!(error.is_instance_of::<pyo3::exceptions::PyKeyError>(py));
+        });
+    }
+}
@@ -2,10 +2,12 @@
 // Licensed under the GNU AGPL v3.0. See LICENSE file for details.
 // Website: https://chromatic.hu
 
-use anyhow::{bail, Context, Result};
+use anyhow::{bail, Result};
 use byteorder::{ByteOrder, LittleEndian};
-use std::time::Duration;
+#[cfg(feature = "usb")]
+use {anyhow::Context, std::time::Duration};
 
+#[cfg(feature = "usb")]
 use crate::usb::UsbTransport;
 
 const MAX_PAYLOAD: usize = 1 << 20; // 1 MiB cap for safety
@@ -85,10 +87,12 @@ pub fn decode_header(bytes: &[u8; HEADER_SIZE]) -> Result<AdbHeader> {
     })
 }
 
+#[cfg(feature = "usb")]
 pub struct AdbConnection {
     usb: UsbTransport,
 }
 
+#[cfg(feature = "usb")]
 impl AdbConnection {
     pub fn new(usb: UsbTransport) -> Result<Self> {
         let mut conn = Self { usb };
@@ -261,12 +265,14 @@ impl AdbConnection {
     }
 }
 
+#[cfg(feature = "usb")]
 pub struct AdbStream<'a> {
     conn: &'a mut AdbConnection,
     pub local_id: u32,
     pub remote_id: u32,
 }
 
+#[cfg(feature = "usb")]
 impl<'a> AdbStream<'a> {
     pub fn set_timeout(&mut self, dur: Duration) {
         self.conn.set_timeout(dur);
@@ -335,6 +341,7 @@ impl<'a> AdbStream<'a> {
     }
 }
 
+#[cfg(feature = "usb")]
 pub fn connect(usb: UsbTransport) -> Result<AdbConnection> {
     AdbConnection::new(usb)
 }
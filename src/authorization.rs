@@ -0,0 +1,213 @@
+// Copyright (C) 2026 Chromatic
+// Licensed under the GNU AGPL v3.0. See LICENSE file for details.
+// Website: https://chromatic.hu
+
+//! Flash authorizations: a validation answer obtained on a networked machine
+//! and carried to an offline bench, bound to one phone and one package.
+
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Result};
+use sha2::{Digest, Sha256};
+
+use crate::flash::FlashPlan;
+use crate::mi::DeviceInfo;
+use crate::secret::{self, Serial, Token};
+use crate::validate::ValidateResult;
+
+/// Version of the file layout; older readers refuse newer files.
+pub const FORMAT: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Authorization {
+    pub format: u32,
+    /// Seconds since the Unix epoch.
+    pub created_at: u64,
+    /// Serial the phone reported, before any override; the bench checks it.
+    pub serial: Serial,
+    /// Identity sent to the server.
+    pub identity: DeviceInfo,
+    /// File name of the package, for display.
+    pub package: String,
+    pub md5: String,
+    pub token: Token,
+    /// The server marked the package with `Erase=1`.
+    pub erase: bool,
+    /// The user asked for a wipe when authorizing.
+    pub force_wipe: bool,
+    pub message: Option<String>,
+    /// SHA-256 over the other fields. It catches edits and corruption; it is
+    /// not a signature.
+    pub digest: String,
+}
+
+impl Authorization {
+    /// Records a validation answer for `md5`; fails when the server gave no
+    /// token.
+    pub fn new(
+        serial: &str,
+        identity: DeviceInfo,
+        package: &str,
+        md5: &str,
+        response: &ValidateResult,
+        force_wipe: bool,
+    ) -> Result<Self> {
+        let plan = FlashPlan::from_response(response.clone(), force_wipe)?;
+        let mut authorization = Self {
+            format: FORMAT,
+            created_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_secs()),
+            serial: serial.into(),
+            identity,
+            package: package.to_string(),
+            md5: md5.to_ascii_lowercase(),
+            token: plan.token().clone(),
+            erase: response.pkgrom_erase == Some(1),
+            force_wipe,
+            message: response.code_message.clone(),
+            digest: String::new(),
+        };
+        authorization.digest = authorization.compute_digest();
+        Ok(authorization)
+    }
+
+    /// Writes the bundle with the serial and token in clear; carrying them
+    /// is its purpose.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let json = secret::with_revealed(|| serde_json::to_string_pretty(self))?;
+        fs::write(path, json + "\n").with_context(|| format!("Writing {}", path.display()))
+    }
+
+    /// Reads a bundle and checks its digest.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text =
+            fs::read_to_string(path).with_context(|| format!("Reading {}", path.display()))?;
+        let authorization: Self = serde_json::from_str(&text)
+            .with_context(|| format!("{} is not an authorization bundle", path.display()))?;
+        if authorization.format > FORMAT {
+            bail!(
+                "{} uses authorization format {}; this build reads up to {FORMAT}",
+                path.display(),
+                authorization.format
+            );
+        }
+        if authorization.digest != authorization.compute_digest() {
+            bail!(
+                "{} was modified or damaged after it was created",
+                path.display()
+            );
+        }
+        Ok(authorization)
+    }
+
+    /// Checks that the connected phone and the package are the ones the
+    /// token was issued for.
+    pub fn verify(&self, serial: &str, md5: &str) -> Result<()> {
+        if serial != self.serial.reveal() {
+            bail!(
+                "This authorization is for the phone with serial {}, but {} is connected",
+                self.serial,
+                Serial::new(serial)
+            );
+        }
+        if !md5.eq_ignore_ascii_case(&self.md5) {
+            bail!(
+                "This authorization is for {} (MD5 {}), but the package's MD5 is {md5}",
+                self.package,
+                self.md5
+            );
+        }
+        Ok(())
+    }
+
+    /// The flash this bundle allows.
+    pub fn plan(&self) -> Result<FlashPlan> {
+        FlashPlan::authorized(self.token.clone(), self.erase, self.force_wipe)
+    }
+
+    fn compute_digest(&self) -> String {
+        let unsigned = Self {
+            digest: String::new(),
+            ..self.clone()
+        };
+        let json = secret::with_revealed(|| serde_json::to_vec(&unsigned))
+            .expect("authorizations serialize");
+        hex::encode(Sha256::digest(json))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flash::WipeReason;
+
+    fn authorization() -> Authorization {
+        let response = ValidateResult {
+            validate_token: Some("token".into()),
+            pkgrom_erase: Some(1),
+            code_message: Some("ok".into()),
+            ..ValidateResult::default()
+        };
+        let identity = DeviceInfo {
+            device: "garnet_eea_global".into(),
+            sn: "serial".into(),
+            ..DeviceInfo::default()
+        };
+        Authorization::new(
+            "serial",
+            identity,
+            "rom.zip",
+            &"AB".repeat(16),
+            &response,
+            false,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn bundles_are_bound_to_the_serial_and_package() {
+        let authorization = authorization();
+        authorization.verify("serial", &"ab".repeat(16)).unwrap();
+        assert!(authorization.verify("other", &"ab".repeat(16)).is_err());
+        assert!(authorization.verify("serial", &"cd".repeat(16)).is_err());
+        assert_eq!(
+            authorization.plan().unwrap().wipe(),
+            Some(WipeReason::Server)
+        );
+    }
+
+    #[test]
+    fn edited_bundles_are_rejected() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("rom.auth.json");
+        let original = authorization();
+        original.save(&path).unwrap();
+        assert_eq!(Authorization::load(&path).unwrap(), original);
+
+        let edited = fs::read_to_string(&path)
+            .unwrap()
+            .replace("\"erase\": true", "\"erase\": false");
+        fs::write(&path, edited).unwrap();
+        assert!(Authorization::load(&path).is_err());
+    }
+
+    #[test]
+    fn responses_without_a_token_cannot_be_authorized() {
+        let response = ValidateResult {
+            code_message: Some("not allowed".into()),
+            ..ValidateResult::default()
+        };
+        assert!(Authorization::new(
+            "serial",
+            DeviceInfo::default(),
+            "rom.zip",
+            "00",
+            &response,
+            false
+        )
+        .is_err());
+    }
+}
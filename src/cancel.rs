@@ -0,0 +1,174 @@
+// Copyright (C) 2026 Chromatic
+// Licensed under the GNU AGPL v3.0. See LICENSE file for details.
+// Website: https://chromatic.hu
+
+//! Cooperative cancellation shared by every long-running operation.
+//!
+//! A token is cancelled explicitly, when its deadline passes, or when any
+//! ancestor is cancelled. Child tokens let one step be cancelled or given a
+//! shorter deadline without affecting the rest of the session.
+
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// The error an operation returns when its token was cancelled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cancelled {
+    Requested,
+    DeadlineExceeded,
+}
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Requested => f.write_str("Operation cancelled"),
+            Self::DeadlineExceeded => f.write_str("Operation deadline exceeded"),
+        }
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+#[derive(Debug, Default)]
+struct Inner {
+    cancelled: AtomicBool,
+    deadline: Option<Instant>,
+    parent: Option<CancelToken>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    inner: Arc<Inner>,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_deadline(deadline: Instant) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                deadline: Some(deadline),
+                ..Inner::default()
+            }),
+        }
+    }
+
+    pub fn with_timeout(timeout: Duration) -> Self {
+        Self::with_deadline(Instant::now() + timeout)
+    }
+
+    /// A token cancelled with this one, which can also be cancelled alone.
+    pub fn child(&self) -> Self {
+        self.child_inner(None)
+    }
+
+    /// A child that also expires at `deadline`.
+    pub fn child_with_deadline(&self, deadline: Instant) -> Self {
+        self.child_inner(Some(deadline))
+    }
+
+    pub fn child_with_timeout(&self, timeout: Duration) -> Self {
+        self.child_inner(Some(Instant::now() + timeout))
+    }
+
+    fn child_inner(&self, deadline: Option<Instant>) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                cancelled: AtomicBool::new(false),
+                deadline,
+                parent: Some(self.clone()),
+            }),
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Why the token is cancelled, if it is.
+    pub fn state(&self) -> Option<Cancelled> {
+        if self.inner.cancelled.load(Ordering::Relaxed) {
+            return Some(Cancelled::Requested);
+        }
+        if self
+            .inner
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            return Some(Cancelled::DeadlineExceeded);
+        }
+        self.inner.parent.as_ref().and_then(CancelToken::state)
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.state().is_some()
+    }
+
+    /// Fails with [`Cancelled`] once the token is cancelled.
+    pub fn check(&self) -> Result<(), Cancelled> {
+        self.state().map_or(Ok(()), Err)
+    }
+
+    /// Time left before the nearest deadline of this token or its ancestors.
+    pub fn remaining(&self) -> Option<Duration> {
+        let own = self
+            .inner
+            .deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        let parent = self.inner.parent.as_ref().and_then(CancelToken::remaining);
+        match (own, parent) {
+            (Some(own), Some(parent)) => Some(own.min(parent)),
+            (own, parent) => own.or(parent),
+        }
+    }
+}
+
+/// Whether `error` was caused by cancellation.
+pub fn is_cancelled(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| cause.is::<Cancelled>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn children_follow_their_parent_but_not_the_reverse() {
+        let root = CancelToken::new();
+        let child = root.child();
+        let sibling = root.child();
+
+        child.cancel();
+        assert!(child.is_cancelled());
+        assert!(!root.is_cancelled() && !sibling.is_cancelled());
+
+        root.cancel();
+        assert_eq!(sibling.check(), Err(Cancelled::Requested));
+    }
+
+    #[test]
+    fn deadlines_expire_and_bound_children() {
+        let expired = CancelToken::with_deadline(Instant::now());
+        assert_eq!(expired.check(), Err(Cancelled::DeadlineExceeded));
+        assert_eq!(
+            expired.child_with_timeout(Duration::from_secs(60)).state(),
+            Some(Cancelled::DeadlineExceeded)
+        );
+
+        let root = CancelToken::with_timeout(Duration::from_secs(60));
+        let child = root.child_with_timeout(Duration::from_secs(1));
+        assert!(child.remaining().unwrap() <= Duration::from_secs(1));
+        assert!(!child.is_cancelled());
+    }
+
+    #[test]
+    fn cancellation_is_found_through_context() {
+        let error = anyhow::Error::new(Cancelled::Requested).context("Downloading");
+        assert!(is_cancelled(&error));
+        assert!(!is_cancelled(&anyhow::anyhow!("Downloading")));
+    }
+}
@@ -0,0 +1,690 @@
+// Copyright (C) 2026 Chromatic
+// Licensed under the GNU AGPL v3.0. See LICENSE file for details.
+// Website: https://chromatic.hu
+
+//! Layered settings. Each layer overrides the ones before it: built-in
+//! defaults, the system file, the user file, the nearest project file,
+//! `SENSITIVITY_*` variables, then command-line flags.
+//!
+//! Files are TOML. Besides the keys in [`KEYS`], a `[devices.SERIAL]` table
+//! holds identity presets applied when that device is connected and a
+//! `[profiles.NAME]` table defines a region profile. Unknown keys are
+//! ignored with a warning, so a file written by a newer version still loads.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use toml_edit::{DocumentMut, Item};
+
+use crate::mi::profile::RegionProfile;
+use crate::util::dirs;
+use crate::validate::DEFAULT_SERVER_URL;
+
+pub const FILE_NAME: &str = "config.toml";
+/// Looked up in the working directory and its ancestors.
+pub const PROJECT_FILE: &str = ".sensitivity.toml";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Layer {
+    Default,
+    System,
+    User,
+    Project,
+    Environment,
+    Flag,
+}
+
+impl fmt::Display for Layer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Default => "default",
+            Self::System => "system",
+            Self::User => "user",
+            Self::Project => "project",
+            Self::Environment => "environment",
+            Self::Flag => "flag",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Text,
+    /// Relative paths in a file are taken from the file's directory.
+    Path,
+    /// A positive integer.
+    Count,
+}
+
+/// A top-level setting.
+#[derive(Debug)]
+pub struct Key {
+    pub name: &'static str,
+    pub env: &'static str,
+    pub default: Option<&'static str>,
+    kind: Kind,
+}
+
+pub const KEYS: &[Key] = &[
+    Key {
+        name: "server_url",
+        env: "SENSITIVITY_SERVER_URL",
+        default: Some(DEFAULT_SERVER_URL),
+        kind: Kind::Text,
+    },
+    Key {
+        name: "download_dir",
+        env: "SENSITIVITY_DOWNLOAD_DIR",
+        default: None,
+        kind: Kind::Path,
+    },
+    Key {
+        name: "profile",
+        env: "SENSITIVITY_PROFILE",
+        default: None,
+        kind: Kind::Text,
+    },
+    Key {
+        name: "adb_policy",
+        env: "SENSITIVITY_ADB_POLICY",
+        default: Some("keep"),
+        kind: Kind::Text,
+    },
+    Key {
+        name: "chunk_size",
+        env: "SENSITIVITY_CHUNK_SIZE",
+        default: Some("65536"),
+        kind: Kind::Count,
+    },
+    Key {
+        name: "language",
+        env: "SENSITIVITY_LANG",
+        default: None,
+        kind: Kind::Text,
+    },
+];
+
+/// Fields of a `[devices.SERIAL]` preset.
+pub const PRESET_FIELDS: [&str; 7] = [
+    "profile", "codename", "device", "version", "codebase", "branch", "romzone",
+];
+
+/// Fields of a `[profiles.NAME]` region profile; see [`RegionProfile`].
+pub const PROFILE_FIELDS: [&str; 5] = [
+    "device_name",
+    "region_code",
+    "branch",
+    "romzone",
+    "language",
+];
+
+/// Tables of named entries and the fields each entry may set.
+const TABLES: [(&str, &[&str]); 2] = [("devices", &PRESET_FIELDS), ("profiles", &PROFILE_FIELDS)];
+
+/// Identity settings for one device serial.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DevicePreset {
+    pub profile: Option<String>,
+    pub codename: Option<String>,
+    pub device: Option<String>,
+    pub version: Option<String>,
+    pub codebase: Option<String>,
+    pub branch: Option<String>,
+    pub romzone: Option<String>,
+}
+
+impl DevicePreset {
+    fn field(&mut self, name: &str) -> Option<&mut Option<String>> {
+        Some(match name {
+            "profile" => &mut self.profile,
+            "codename" => &mut self.codename,
+            "device" => &mut self.device,
+            "version" => &mut self.version,
+            "codebase" => &mut self.codebase,
+            "branch" => &mut self.branch,
+            "romzone" => &mut self.romzone,
+            _ => return None,
+        })
+    }
+}
+
+/// An effective value and the layer it came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Value {
+    pub text: String,
+    pub layer: Layer,
+    /// The file that set it, for file layers.
+    pub path: Option<PathBuf>,
+}
+
+impl Value {
+    /// Where the value came from, e.g. `user (~/.config/sensitivity/config.toml)`.
+    pub fn origin(&self) -> String {
+        match &self.path {
+            Some(path) => format!("{} ({})", self.layer, path.display()),
+            None => self.layer.to_string(),
+        }
+    }
+}
+
+/// The setting file of each file layer; files need not exist.
+#[derive(Debug, Clone, Default)]
+pub struct Paths {
+    pub system: Option<PathBuf>,
+    pub user: Option<PathBuf>,
+    pub project: Option<PathBuf>,
+}
+
+impl Paths {
+    /// The platform's files, with the project file searched from `cwd`.
+    pub fn discover(cwd: &Path) -> Self {
+        Self {
+            system: system_file(),
+            user: dirs::config_dir().map(|dir| dir.join(FILE_NAME)),
+            project: cwd
+                .ancestors()
+                .map(|dir| dir.join(PROJECT_FILE))
+                .find(|path| path.is_file()),
+        }
+    }
+
+    pub fn get(&self, layer: Layer) -> Option<&Path> {
+        match layer {
+            Layer::System => self.system.as_deref(),
+            Layer::User => self.user.as_deref(),
+            Layer::Project => self.project.as_deref(),
+            _ => None,
+        }
+    }
+}
+
+fn system_file() -> Option<PathBuf> {
+    #[cfg(windows)]
+    {
+        std::env::var_os("ProgramData")
+            .map(|dir| PathBuf::from(dir).join("sensitivity").join(FILE_NAME))
+    }
+    #[cfg(target_os = "macos")]
+    {
+        Some(PathBuf::from("/Library/Application Support/sensitivity").join(FILE_NAME))
+    }
+    #[cfg(not(any(windows, target_os = "macos")))]
+    {
+        Some(PathBuf::from("/etc/sensitivity").join(FILE_NAME))
+    }
+}
+
+#[derive(Debug)]
+struct Source {
+    layer: Layer,
+    path: Option<PathBuf>,
+    /// Dotted keys such as `chunk_size` or `devices.SERIAL.profile`.
+    values: BTreeMap<String, String>,
+}
+
+/// Every layer, lowest first.
+#[derive(Debug)]
+pub struct Config {
+    paths: Paths,
+    sources: Vec<Source>,
+    warnings: Vec<String>,
+}
+
+impl Config {
+    /// Reads the platform's files and the process environment.
+    pub fn load(cwd: &Path) -> Result<Self> {
+        Self::load_with(Paths::discover(cwd), |name| std::env::var(name).ok())
+    }
+
+    /// Like [`Config::load`], but a file or variable that cannot be used is
+    /// left out and reported in [`Config::warnings`], so `config` can still
+    /// show and repair a broken file.
+    pub fn load_lenient(cwd: &Path) -> Self {
+        Self::load_lenient_with(Paths::discover(cwd), |name| std::env::var(name).ok())
+    }
+
+    pub fn load_with(paths: Paths, env: impl Fn(&str) -> Option<String>) -> Result<Self> {
+        Self::read(paths, env, false)
+    }
+
+    pub fn load_lenient_with(paths: Paths, env: impl Fn(&str) -> Option<String>) -> Self {
+        Self::read(paths, env, true).expect("lenient loading reports problems as warnings")
+    }
+
+    fn read(paths: Paths, env: impl Fn(&str) -> Option<String>, lenient: bool) -> Result<Self> {
+        let mut warnings = Vec::new();
+        let mut fail = |error: anyhow::Error| -> Result<()> {
+            if !lenient {
+                return Err(error);
+            }
+            warnings.push(format!("{error:#}"));
+            Ok(())
+        };
+        let defaults = KEYS
+            .iter()
+            .filter_map(|key| Some((key.name.to_string(), key.default?.to_string())))
+            .collect();
+        let mut sources = vec![Source {
+            layer: Layer::Default,
+            path: None,
+            values: defaults,
+        }];
+        let mut ignored = Vec::new();
+        for layer in [Layer::System, Layer::User, Layer::Project] {
+            if let Some(path) = paths.get(layer) {
+                match read_file(path, &mut ignored) {
+                    Ok(Some(values)) => sources.push(Source {
+                        layer,
+                        path: Some(path.to_path_buf()),
+                        values,
+                    }),
+                    Ok(None) => {}
+                    Err(error) => fail(error)?,
+                }
+            }
+        }
+        let mut values = BTreeMap::new();
+        for key in KEYS {
+            if let Some(text) = env(key.env).filter(|text| !text.is_empty()) {
+                match check(key.kind, &text).with_context(|| format!("Reading {}", key.env)) {
+                    Ok(()) => {
+                        values.insert(key.name.to_string(), text);
+                    }
+                    Err(error) => fail(error)?,
+                }
+            }
+        }
+        warnings.extend(ignored);
+        sources.push(Source {
+            layer: Layer::Environment,
+            path: None,
+            values,
+        });
+        sources.push(Source {
+            layer: Layer::Flag,
+            path: None,
+            values: BTreeMap::new(),
+        });
+        Ok(Self {
+            paths,
+            sources,
+            warnings,
+        })
+    }
+
+    pub fn paths(&self) -> &Paths {
+        &self.paths
+    }
+
+    /// What was ignored while loading: unknown keys and, when loaded
+    /// leniently, files and variables that could not be used.
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    /// Records a value given on the command line.
+    pub fn set_flag(&mut self, key: &str, text: impl Into<String>) {
+        let flags = self
+            .sources
+            .last_mut()
+            .expect("the flag layer is always present");
+        flags.values.insert(key.to_string(), text.into());
+    }
+
+    /// The effective value of a top-level key or a table field such as
+    /// `devices.SERIAL.FIELD`.
+    pub fn get(&self, key: &str) -> Option<Value> {
+        self.sources.iter().rev().find_map(|source| {
+            Some(Value {
+                text: source.values.get(key)?.clone(),
+                layer: source.layer,
+                path: source.path.clone(),
+            })
+        })
+    }
+
+    /// Every known key with its effective value, then every table field
+    /// that any layer sets.
+    pub fn effective(&self) -> Vec<(String, Option<Value>)> {
+        let mut keys: Vec<String> = KEYS.iter().map(|key| key.name.to_string()).collect();
+        let mut fields: Vec<String> = self
+            .sources
+            .iter()
+            .flat_map(|source| source.values.keys())
+            .filter(|key| split_entry(key).is_some())
+            .cloned()
+            .collect();
+        fields.sort();
+        fields.dedup();
+        keys.extend(fields);
+        keys.into_iter()
+            .map(|key| {
+                let value = self.get(&key);
+                (key, value)
+            })
+            .collect()
+    }
+
+    /// Merged presets by serial.
+    pub fn presets(&self) -> BTreeMap<String, DevicePreset> {
+        let mut presets = BTreeMap::<String, DevicePreset>::new();
+        for (key, value) in self.effective() {
+            let (Some(value), Some(("devices", serial, field))) = (value, split_entry(&key)) else {
+                continue;
+            };
+            let preset = presets.entry(serial.to_string()).or_default();
+            if let Some(slot) = preset.field(field) {
+                *slot = Some(value.text);
+            }
+        }
+        presets
+    }
+
+    /// Region profiles defined in any layer, fields merged across layers.
+    pub fn profiles(&self) -> Result<Vec<RegionProfile>> {
+        let mut fields = BTreeMap::<String, BTreeMap<String, Value>>::new();
+        for (key, value) in self.effective() {
+            if let (Some(value), Some(("profiles", name, field))) = (value, split_entry(&key)) {
+                fields
+                    .entry(name.to_string())
+                    .or_default()
+                    .insert(field.to_string(), value);
+            }
+        }
+        fields
+            .iter()
+            .map(|(name, fields)| {
+                let field = |field: &str| fields.get(field).map(|value| value.text.as_str());
+                let required = |field: &str| {
+                    fields
+                        .get(field)
+                        .map(|value| value.text.as_str())
+                        .with_context(|| format!("profiles.{name} needs `{field}`"))
+                };
+                RegionProfile::new(
+                    name,
+                    required("device_name")?,
+                    required("region_code")?,
+                    field("branch"),
+                    field("romzone"),
+                    field("language"),
+                )
+                .with_context(|| {
+                    let origins: Vec<String> = fields.values().map(Value::origin).collect();
+                    format!("Defined in {}", origins.join(", "))
+                })
+            })
+            .collect()
+    }
+}
+
+/// Whether `key` names a top-level setting or a table field.
+pub fn is_key(key: &str) -> bool {
+    KEYS.iter().any(|known| known.name == key) || split_entry(key).is_some()
+}
+
+/// Splits `TABLE.NAME.FIELD` for a known table and field; names may contain
+/// dots.
+fn split_entry(key: &str) -> Option<(&'static str, &str, &str)> {
+    TABLES.iter().find_map(|(table, fields)| {
+        let rest = key.strip_prefix(table)?.strip_prefix('.')?;
+        let (name, field) = rest.rsplit_once('.')?;
+        fields.contains(&field).then_some((*table, name, field))
+    })
+}
+
+fn check(kind: Kind, text: &str) -> Result<()> {
+    if kind == Kind::Count && !text.parse::<usize>().is_ok_and(|count| count > 0) {
+        bail!("Expected a positive whole number, got {text:?}");
+    }
+    Ok(())
+}
+
+/// Reads one settings file; unknown keys are skipped and described in
+/// `warnings`.
+fn read_file(path: &Path, warnings: &mut Vec<String>) -> Result<Option<BTreeMap<String, String>>> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(error) => return Err(error).with_context(|| format!("Reading {}", path.display())),
+    };
+    let document: DocumentMut = text
+        .parse()
+        .with_context(|| format!("Parsing {}", path.display()))?;
+    let base = path.parent().unwrap_or(Path::new("."));
+    let mut values = BTreeMap::new();
+    for (name, item) in document.iter() {
+        if let Some((table, fields)) = TABLES.iter().find(|(table, _)| *table == name) {
+            let Some(entries) = item.as_table_like() else {
+                bail!("{}: `{table}` must be a table", path.display());
+            };
+            for (entry, item) in entries.iter() {
+                let Some(entry_fields) = item.as_table_like() else {
+                    bail!("{}: `{table}.{entry}` must be a table", path.display());
+                };
+                for (field, item) in entry_fields.iter() {
+                    if !fields.contains(&field) {
+                        warnings.push(format!(
+                            "{}: ignoring unknown field `{table}.{entry}.{field}`",
+                            path.display()
+                        ));
+                        continue;
+                    }
+                    let key = format!("{table}.{entry}.{field}");
+                    let text = item_text(item, Kind::Text, base)
+                        .with_context(|| format!("{}: {key}", path.display()))?;
+                    values.insert(key, text);
+                }
+            }
+            continue;
+        }
+        let Some(key) = KEYS.iter().find(|key| key.name == name) else {
+            warnings.push(format!(
+                "{}: ignoring unknown setting `{name}`",
+                path.display()
+            ));
+            continue;
+        };
+        let text = item_text(item, key.kind, base)
+            .with_context(|| format!("{}: {name}", path.display()))?;
+        values.insert(name.to_string(), text);
+    }
+    Ok(Some(values))
+}
+
+fn item_text(item: &Item, kind: Kind, base: &Path) -> Result<String> {
+    let text = match (kind, item.as_integer(), item.as_str()) {
+        (Kind::Count, Some(count), _) => count.to_string(),
+        (Kind::Path, _, Some(path)) => base.join(path).display().to_string(),
+        (_, _, Some(text)) => text.to_string(),
+        _ => bail!("Unexpected value type"),
+    };
+    check(kind, &text)?;
+    Ok(text)
+}
+
+/// Writes `key = text` into the file at `path`, keeping its other contents
+/// and comments.
+pub fn set(path: &Path, key: &str, text: &str) -> Result<()> {
+    let existing = match fs::read_to_string(path) {
+        Ok(existing) => existing,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(error) => return Err(error).with_context(|| format!("Reading {}", path.display())),
+    };
+    let mut document: DocumentMut = existing
+        .parse()
+        .with_context(|| format!("Parsing {}", path.display()))?;
+    if let Some((table, name, field)) = split_entry(key) {
+        let entries = document
+            .entry(table)
+            .or_insert_with(|| {
+                let mut entries = toml_edit::Table::new();
+                entries.set_implicit(true);
+                Item::Table(entries)
+            })
+            .as_table_mut()
+            .with_context(|| format!("`{table}` must be a table"))?;
+        let entry = entries
+            .entry(name)
+            .or_insert_with(toml_edit::table)
+            .as_table_like_mut()
+            .with_context(|| format!("`{table}.{name}` must be a table"))?;
+        entry.insert(field, toml_edit::value(text));
+    } else {
+        let known = KEYS
+            .iter()
+            .find(|known| known.name == key)
+            .with_context(|| format!("Unknown setting `{key}`"))?;
+        check(known.kind, text)?;
+        document[key] = match known.kind {
+            Kind::Count => toml_edit::value(text.parse::<i64>()?),
+            Kind::Text | Kind::Path => toml_edit::value(text),
+        };
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, document.to_string()).with_context(|| format!("Writing {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(directory: &Path) -> Paths {
+        Paths {
+            system: Some(directory.join("system.toml")),
+            user: Some(directory.join("user.toml")),
+            project: Some(directory.join("project").join(PROJECT_FILE)),
+        }
+    }
+
+    #[test]
+    fn later_layers_win_and_report_their_origin() {
+        let directory = tempfile::tempdir().unwrap();
+        let paths = paths(directory.path());
+        fs::write(
+            paths.system.as_ref().unwrap(),
+            "chunk_size = 4096\nprofile = \"eea\"\n",
+        )
+        .unwrap();
+        set(paths.user.as_ref().unwrap(), "chunk_size", "8192").unwrap();
+        set(paths.project.as_ref().unwrap(), "download_dir", "roms").unwrap();
+        let env = |name: &str| (name == "SENSITIVITY_PROFILE").then(|| "global".to_string());
+        let mut config = Config::load_with(paths.clone(), env).unwrap();
+        config.set_flag("server_url", "https://example.com");
+
+        let chunk = config.get("chunk_size").unwrap();
+        assert_eq!((chunk.text.as_str(), chunk.layer), ("8192", Layer::User));
+        assert_eq!(chunk.path.as_deref(), paths.user.as_deref());
+        assert_eq!(config.get("profile").unwrap().layer, Layer::Environment);
+        assert_eq!(config.get("server_url").unwrap().layer, Layer::Flag);
+        assert_eq!(config.get("adb_policy").unwrap().layer, Layer::Default);
+        assert_eq!(
+            PathBuf::from(config.get("download_dir").unwrap().text),
+            directory.path().join("project").join("roms")
+        );
+        assert_eq!(config.get("language"), None);
+    }
+
+    #[test]
+    fn presets_merge_by_serial_and_keep_comments() {
+        let directory = tempfile::tempdir().unwrap();
+        let paths = paths(directory.path());
+        let user = paths.user.clone().unwrap();
+        fs::write(
+            &user,
+            "# bench phones\n[devices.\"a1.b2\"]\ncodename = \"garnet\"\n",
+        )
+        .unwrap();
+        set(&user, "devices.a1.b2.profile", "eea").unwrap();
+        set(
+            paths.project.as_ref().unwrap(),
+            "devices.a1.b2.profile",
+            "in",
+        )
+        .unwrap();
+
+        let presets = Config::load_with(paths, |_| None).unwrap().presets();
+        let preset = &presets["a1.b2"];
+        assert_eq!(preset.codename.as_deref(), Some("garnet"));
+        assert_eq!(preset.profile.as_deref(), Some("in"));
+        assert!(fs::read_to_string(&user)
+            .unwrap()
+            .starts_with("# bench phones"));
+    }
+
+    #[test]
+    fn profiles_are_built_from_merged_fields() {
+        let directory = tempfile::tempdir().unwrap();
+        let paths = paths(directory.path());
+        let user = paths.user.clone().unwrap();
+        set(&user, "profiles.jp.device_name", "{codename}_jp_global").unwrap();
+        set(&user, "profiles.jp.region_code", "JP").unwrap();
+        set(paths.project.as_ref().unwrap(), "profiles.jp.branch", "X").unwrap();
+
+        let profiles = Config::load_with(paths.clone(), |_| None)
+            .unwrap()
+            .profiles()
+            .unwrap();
+        assert_eq!(profiles.len(), 1);
+        assert_eq!(
+            (profiles[0].name.as_str(), profiles[0].branch.as_str()),
+            ("jp", "X")
+        );
+
+        set(&user, "profiles.kr.region_code", "KR").unwrap();
+        assert!(Config::load_with(paths, |_| None)
+            .unwrap()
+            .profiles()
+            .is_err());
+    }
+
+    #[test]
+    fn bad_values_are_rejected_and_unknown_keys_ignored() {
+        let directory = tempfile::tempdir().unwrap();
+        let paths = paths(directory.path());
+        let user = paths.user.clone().unwrap();
+        assert!(set(&user, "chunk_sise", "1").is_err());
+        assert!(set(&user, "chunk_size", "many").is_err());
+        assert!(set(&user, "devices.x.colour", "red").is_err());
+        fs::write(
+            &user,
+            "sever_url = \"https://example.com\"\nchunk_size = 4096\n[devices.x]\ncolour = \"red\"\n",
+        )
+        .unwrap();
+        let config = Config::load_with(paths.clone(), |_| None).unwrap();
+        assert_eq!(config.get("chunk_size").unwrap().text, "4096");
+        assert_eq!(config.warnings().len(), 2);
+        assert!(config
+            .warnings()
+            .iter()
+            .any(|warning| warning.contains("sever_url")));
+
+        fs::write(&user, "chunk_size = \"many\"\n").unwrap();
+        assert!(Config::load_with(paths.clone(), |_| None).is_err());
+        let env = |name: &str| (name == "SENSITIVITY_CHUNK_SIZE").then(|| "0".to_string());
+        fs::remove_file(&user).unwrap();
+        assert!(Config::load_with(paths, env).is_err());
+    }
+
+    #[test]
+    fn lenient_loading_reports_broken_files_instead_of_failing() {
+        let directory = tempfile::tempdir().unwrap();
+        let paths = paths(directory.path());
+        fs::write(paths.system.as_ref().unwrap(), "chunk_size = 4096\n").unwrap();
+        let project = paths.project.clone().unwrap();
+        fs::create_dir_all(project.parent().unwrap()).unwrap();
+        fs::write(&project, "chunk_size = [unterminated\n").unwrap();
+        assert!(Config::load_with(paths.clone(), |_| None).is_err());
+
+        let config = Config::load_lenient_with(paths, |_| None);
+        assert_eq!(config.get("chunk_size").unwrap().layer, Layer::System);
+        assert_eq!(config.warnings().len(), 1);
+        assert!(config.warnings()[0].contains(PROJECT_FILE));
+    }
+}
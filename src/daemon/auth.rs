@@ -0,0 +1,128 @@
+// Copyright (C) 2026 Chromatic
+// Licensed under the GNU AGPL v3.0. See LICENSE file for details.
+// Website: https://chromatic.hu
+
+//! Per-client daemon tokens. Only a SHA-256 of each token is stored, so the
+//! file does not grant access if it leaks; the token itself is shown once.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context, Result};
+use sha2::{Digest, Sha256};
+
+use crate::util::dirs;
+
+pub struct TokenStore {
+    path: PathBuf,
+}
+
+impl TokenStore {
+    pub fn at(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// The store in the per-user data directory.
+    pub fn open_default() -> Result<Self> {
+        let dir = dirs::data_dir().ok_or_else(|| anyhow!("No data directory for daemon tokens"))?;
+        Ok(Self::at(dir.join("daemon-tokens.json")))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn load(&self) -> Result<BTreeMap<String, String>> {
+        match fs::read(&self.path) {
+            Ok(data) => serde_json::from_slice(&data)
+                .with_context(|| format!("Reading {}", self.path.display())),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+            Err(error) => Err(error).with_context(|| format!("Reading {}", self.path.display())),
+        }
+    }
+
+    fn save(&self, tokens: &BTreeMap<String, String>) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let temporary = self.path.with_extension("json.tmp");
+        fs::write(&temporary, serde_json::to_vec_pretty(tokens)?)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&temporary, fs::Permissions::from_mode(0o600))?;
+        }
+        fs::rename(&temporary, &self.path)?;
+        Ok(())
+    }
+
+    /// Creates a token for client `name`, replacing any earlier one.
+    pub fn issue(&self, name: &str) -> Result<String> {
+        if name.is_empty() || name.chars().any(char::is_control) {
+            bail!("Client name must be non-empty printable text");
+        }
+        let mut secret = [0u8; 32];
+        getrandom::fill(&mut secret).map_err(|error| anyhow!("Generating a token: {error}"))?;
+        let token = hex::encode(secret);
+        let mut tokens = self.load()?;
+        tokens.insert(name.to_string(), digest(&token));
+        self.save(&tokens)?;
+        Ok(token)
+    }
+
+    /// Returns whether `name` had a token.
+    pub fn revoke(&self, name: &str) -> Result<bool> {
+        let mut tokens = self.load()?;
+        let removed = tokens.remove(name).is_some();
+        if removed {
+            self.save(&tokens)?;
+        }
+        Ok(removed)
+    }
+
+    pub fn clients(&self) -> Result<Vec<String>> {
+        Ok(self.load()?.into_keys().collect())
+    }
+
+    /// The client that owns `token`. The file is read on every call, so a
+    /// revoked token stops working without restarting the daemon.
+    pub fn authenticate(&self, token: &str) -> Result<Option<String>> {
+        let presented = digest(token);
+        Ok(self.load()?.into_iter().find_map(|(name, stored)| {
+            constant_time_eq(stored.as_bytes(), presented.as_bytes()).then_some(name)
+        }))
+    }
+}
+
+fn digest(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn issued_tokens_authenticate_until_revoked() {
+        let directory = tempfile::tempdir().unwrap();
+        let store = TokenStore::at(directory.path().join("tokens.json"));
+        let token = store.issue("winui").unwrap();
+        store.issue("script").unwrap();
+
+        assert_eq!(
+            store.authenticate(&token).unwrap().as_deref(),
+            Some("winui")
+        );
+        assert_eq!(store.authenticate("guess").unwrap(), None);
+        assert!(!fs::read_to_string(store.path()).unwrap().contains(&token));
+
+        assert!(store.revoke("winui").unwrap());
+        assert_eq!(store.authenticate(&token).unwrap(), None);
+        assert_eq!(store.clients().unwrap(), ["script"]);
+    }
+}
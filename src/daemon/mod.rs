@@ -0,0 +1,1025 @@
+// Copyright (C) 2026 Chromatic
+// Licensed under the GNU AGPL v3.0. See LICENSE file for details.
+// Website: https://chromatic.hu
+
+//! `sensitivity daemon`: one long-lived backend that keeps recovery sessions
+//! open and serves JSON-RPC 2.0, one object per line, over a Unix socket or
+//! loopback TCP.
+//!
+//! Every connection authenticates with a per-client token in its first
+//! request, within ten seconds, or is closed. Downloads
+//! and flashes run as jobs owned by the client that started them; their
+//! progress reaches that client's subscribed connections as `event`
+//! notifications carrying the same events as `--machine` mode, and only that
+//! client may cancel, approve or decline them.
+
+mod auth;
+
+pub use auth::TokenStore;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, TryLockError};
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::cancel::CancelToken;
+use crate::download::MirrorSegment;
+use crate::flash::{check_package, Confirmer, FlashOutcome, FlashSession, TokenPolicy, WipeReason};
+use crate::library::Library;
+use crate::machine::{self, Approval, ErrorCode};
+use crate::mi::MiClient;
+use crate::observer::{Observer, Phase, Silent};
+use crate::sideload::FileSource;
+use crate::usb::UsbTransport;
+use crate::util::hash_cache;
+
+/// Loopback port used when no Unix socket is available.
+pub const DEFAULT_PORT: u16 = 7420;
+
+/// Longest request line read; a longer one closes the connection.
+const MAX_REQUEST_LINE: u64 = 64 * 1024;
+/// How long a new connection may take to send its `auth` request.
+const AUTH_TIMEOUT: Duration = Duration::from_secs(10);
+
+const UNAUTHORIZED: i64 = -32001;
+const BUSY: i64 = -32002;
+const FAILED: i64 = -32000;
+
+/// Where the daemon listens.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Endpoint {
+    #[cfg(unix)]
+    Unix(PathBuf),
+    Tcp(SocketAddr),
+}
+
+impl Endpoint {
+    /// Parses `unix:PATH`, `tcp:ADDR`, a bare socket address or, on Unix, a
+    /// bare path. TCP endpoints must be loopback addresses.
+    pub fn parse(text: &str) -> Result<Self> {
+        let tcp = |address: &str| -> Result<Self> {
+            let address: SocketAddr = address
+                .parse()
+                .with_context(|| format!("Invalid TCP address {address}"))?;
+            if !address.ip().is_loopback() {
+                bail!("The daemon only listens on loopback addresses, not {address}");
+            }
+            Ok(Self::Tcp(address))
+        };
+        if let Some(address) = text.strip_prefix("tcp:") {
+            return tcp(address);
+        }
+        if let Some(path) = text.strip_prefix("unix:") {
+            return Self::unix(Path::new(path));
+        }
+        if text.parse::<SocketAddr>().is_ok() {
+            return tcp(text);
+        }
+        Self::unix(Path::new(text))
+    }
+
+    #[cfg(unix)]
+    fn unix(path: &Path) -> Result<Self> {
+        Ok(Self::Unix(path.to_path_buf()))
+    }
+
+    #[cfg(not(unix))]
+    fn unix(path: &Path) -> Result<Self> {
+        bail!(
+            "Unix sockets are not available here; use tcp:127.0.0.1:{DEFAULT_PORT} instead of {}",
+            path.display()
+        )
+    }
+
+    /// A socket in the runtime directory on Unix, loopback TCP elsewhere.
+    pub fn default_for_platform() -> Result<Self> {
+        #[cfg(unix)]
+        {
+            let dir = crate::util::dirs::runtime_dir()
+                .ok_or_else(|| anyhow!("No runtime directory for the daemon socket"))?;
+            Ok(Self::Unix(dir.join("daemon.sock")))
+        }
+        #[cfg(not(unix))]
+        {
+            Ok(Self::Tcp(SocketAddr::from(([127, 0, 0, 1], DEFAULT_PORT))))
+        }
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(unix)]
+            Self::Unix(path) => write!(f, "unix:{}", path.display()),
+            Self::Tcp(address) => write!(f, "tcp:{address}"),
+        }
+    }
+}
+
+pub struct DaemonOptions {
+    pub server_url: String,
+    pub debug_usb: bool,
+    pub tokens: TokenStore,
+}
+
+trait Connection: Read + Write + Send {
+    fn try_clone_box(&self) -> io::Result<Box<dyn Connection>>;
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+impl Connection for TcpStream {
+    fn try_clone_box(&self) -> io::Result<Box<dyn Connection>> {
+        Ok(Box::new(self.try_clone()?))
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+}
+
+#[cfg(unix)]
+impl Connection for std::os::unix::net::UnixStream {
+    fn try_clone_box(&self) -> io::Result<Box<dyn Connection>> {
+        Ok(Box::new(self.try_clone()?))
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        std::os::unix::net::UnixStream::set_read_timeout(self, timeout)
+    }
+}
+
+enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(std::os::unix::net::UnixListener, PathBuf),
+}
+
+impl Listener {
+    fn accept(&self) -> io::Result<Box<dyn Connection>> {
+        match self {
+            Self::Tcp(listener) => {
+                let (stream, _) = listener.accept()?;
+                stream.set_nonblocking(false)?;
+                Ok(Box::new(stream))
+            }
+            #[cfg(unix)]
+            Self::Unix(listener, _) => {
+                let (stream, _) = listener.accept()?;
+                stream.set_nonblocking(false)?;
+                Ok(Box::new(stream))
+            }
+        }
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Self::Unix(_, path) = self {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+type Outbox = Arc<Mutex<Box<dyn Connection>>>;
+
+struct Job {
+    /// Name of the client that started the job.
+    owner: String,
+    cancel: CancelToken,
+    approval: Approval,
+}
+
+/// A connection that asked for events, and the client it authenticated as.
+struct Subscriber {
+    client: String,
+    outbox: Outbox,
+}
+
+struct Shared {
+    options: DaemonOptions,
+    cancel: CancelToken,
+    sessions: Mutex<HashMap<usize, Arc<Mutex<MiClient>>>>,
+    subscribers: Mutex<HashMap<u64, Subscriber>>,
+    jobs: Mutex<HashMap<u64, Job>>,
+    next_id: AtomicU64,
+}
+
+impl Shared {
+    fn new(options: DaemonOptions, cancel: CancelToken) -> Self {
+        Self {
+            options,
+            cancel,
+            sessions: Mutex::new(HashMap::new()),
+            subscribers: Mutex::new(HashMap::new()),
+            jobs: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(1),
+        }
+    }
+}
+
+pub struct Daemon {
+    listener: Listener,
+    endpoint: Endpoint,
+    shared: Arc<Shared>,
+}
+
+impl Daemon {
+    /// Binds `endpoint`. A Unix socket left behind by a daemon that is no
+    /// longer running is replaced; a live one is an error.
+    pub fn bind(endpoint: &Endpoint, options: DaemonOptions, cancel: CancelToken) -> Result<Self> {
+        let (listener, endpoint) = match endpoint {
+            Endpoint::Tcp(address) => {
+                let listener =
+                    TcpListener::bind(address).with_context(|| format!("Binding {address}"))?;
+                let bound = Endpoint::Tcp(listener.local_addr()?);
+                listener.set_nonblocking(true)?;
+                (Listener::Tcp(listener), bound)
+            }
+            #[cfg(unix)]
+            Endpoint::Unix(path) => {
+                use std::os::unix::fs::PermissionsExt;
+                use std::os::unix::net::{UnixListener, UnixStream};
+                if UnixStream::connect(path).is_ok() {
+                    bail!("A daemon is already listening on {}", path.display());
+                }
+                let _ = std::fs::remove_file(path);
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                let listener = UnixListener::bind(path)
+                    .with_context(|| format!("Binding {}", path.display()))?;
+                std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+                listener.set_nonblocking(true)?;
+                (Listener::Unix(listener, path.clone()), endpoint.clone())
+            }
+        };
+        Ok(Self {
+            listener,
+            endpoint,
+            shared: Arc::new(Shared::new(options, cancel)),
+        })
+    }
+
+    /// The bound endpoint, with the real port when port 0 was requested.
+    pub fn endpoint(&self) -> &Endpoint {
+        &self.endpoint
+    }
+
+    /// Serves connections until the cancel token fires, then cancels jobs.
+    pub fn run(self) -> Result<()> {
+        while !self.shared.cancel.is_cancelled() {
+            match self.listener.accept() {
+                Ok(connection) => {
+                    let shared = Arc::clone(&self.shared);
+                    std::thread::spawn(move || shared.serve(connection));
+                }
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => {
+                    std::thread::sleep(Duration::from_millis(100));
+                }
+                Err(error) => return Err(error).context("Accepting a daemon connection"),
+            }
+        }
+        for job in lock(&self.shared.jobs).values() {
+            job.cancel.cancel();
+        }
+        Ok(())
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|error| error.into_inner())
+}
+
+fn write_line(outbox: &Outbox, message: &Value) -> io::Result<()> {
+    let mut connection = lock(outbox);
+    writeln!(connection, "{message}")?;
+    connection.flush()
+}
+
+struct RpcError {
+    code: i64,
+    message: String,
+    data: Option<Value>,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+}
+
+impl From<anyhow::Error> for RpcError {
+    fn from(error: anyhow::Error) -> Self {
+        Self {
+            code: FAILED,
+            message: format!("{error:#}"),
+            data: Some(json!({ "code": ErrorCode::of(&error) })),
+        }
+    }
+}
+
+fn params<T: for<'de> Deserialize<'de>>(params: Value) -> Result<T, RpcError> {
+    serde_json::from_value(params).map_err(|error| RpcError::new(-32602, error.to_string()))
+}
+
+#[derive(Deserialize)]
+struct AuthParams {
+    token: String,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct DeviceParams {
+    device: usize,
+}
+
+#[derive(Deserialize)]
+struct ValidateParams {
+    #[serde(default)]
+    device: usize,
+    path: Option<PathBuf>,
+    md5: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct FlashParams {
+    #[serde(default)]
+    device: usize,
+    /// Package path, or the MD5 of a library entry.
+    package: String,
+    #[serde(default)]
+    wipe: bool,
+    /// Approve a required wipe in advance.
+    #[serde(default)]
+    yes: bool,
+    /// Flash a package whose signer is not a known release certificate.
+    #[serde(default)]
+    allow_unknown_signer: bool,
+}
+
+#[derive(Deserialize)]
+struct JobParams {
+    job: u64,
+}
+
+impl Shared {
+    fn serve(self: Arc<Self>, connection: Box<dyn Connection>) {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let _span = tracing::info_span!("connection", id).entered();
+        tracing::debug!("client connected");
+        let Ok(writer) = connection.try_clone_box() else {
+            return;
+        };
+        if connection.set_read_timeout(Some(AUTH_TIMEOUT)).is_err() {
+            return;
+        }
+        let outbox: Outbox = Arc::new(Mutex::new(writer));
+        let mut client = None;
+        let mut reader = BufReader::new(connection);
+        let mut line = String::new();
+        loop {
+            line.clear();
+            match (&mut reader).take(MAX_REQUEST_LINE).read_line(&mut line) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {}
+            }
+            if !line.ends_with('\n') && line.len() as u64 == MAX_REQUEST_LINE {
+                let error = RpcError::new(-32600, "Request line too long");
+                let _ = write_line(&outbox, &reply(Value::Null, Err(error)));
+                break;
+            }
+            let authenticated = client.is_some();
+            if !line.trim().is_empty() {
+                if let Some(reply) = self.request(&line, &mut client, id, &outbox) {
+                    if write_line(&outbox, &reply).is_err() {
+                        break;
+                    }
+                }
+            }
+            match (authenticated, &client) {
+                (true, _) => {}
+                (false, Some(_)) => {
+                    if reader.get_ref().set_read_timeout(None).is_err() {
+                        break;
+                    }
+                }
+                (false, None) => {
+                    tracing::debug!("closing a connection that did not authenticate");
+                    break;
+                }
+            }
+        }
+        lock(&self.subscribers).remove(&id);
+        tracing::debug!("client disconnected");
+    }
+
+    /// Answers one request line; notifications get no reply.
+    fn request(
+        self: &Arc<Self>,
+        line: &str,
+        client: &mut Option<String>,
+        connection: u64,
+        outbox: &Outbox,
+    ) -> Option<Value> {
+        let request: Value = match serde_json::from_str(line) {
+            Ok(request) => request,
+            Err(error) => {
+                return Some(reply(
+                    Value::Null,
+                    Err(RpcError::new(-32700, error.to_string())),
+                ))
+            }
+        };
+        let id = request.get("id").cloned();
+        let result = match (request.get("jsonrpc"), request.get("method")) {
+            (Some(version), Some(Value::String(method))) if version == "2.0" => {
+                tracing::debug!(method, "request");
+                let params = request.get("params").cloned().unwrap_or(json!({}));
+                self.dispatch(method, params, client, connection, outbox)
+            }
+            _ => Err(RpcError::new(-32600, "Not a JSON-RPC 2.0 request")),
+        };
+        id.map(|id| reply(id, result))
+    }
+
+    fn dispatch(
+        self: &Arc<Self>,
+        method: &str,
+        params_value: Value,
+        client: &mut Option<String>,
+        connection: u64,
+        outbox: &Outbox,
+    ) -> Result<Value, RpcError> {
+        if method == "auth" {
+            let AuthParams { token } = params(params_value)?;
+            let name = self
+                .options
+                .tokens
+                .authenticate(&token)?
+                .ok_or_else(|| RpcError::new(UNAUTHORIZED, "Unknown token"))?;
+            *client = Some(name.clone());
+            return Ok(json!({ "client": name }));
+        }
+        let Some(name) = client.clone() else {
+            return Err(RpcError::new(UNAUTHORIZED, "Call auth first"));
+        };
+        match method {
+            "ping" => Ok(json!("pong")),
+            "version" => Ok(json!({
+                "version": env!("CARGO_PKG_VERSION"),
+                "protocol": machine::PROTOCOL_VERSION,
+            })),
+            "subscribe" => {
+                let subscriber = Subscriber {
+                    client: name,
+                    outbox: Arc::clone(outbox),
+                };
+                lock(&self.subscribers).insert(connection, subscriber);
+                Ok(json!(true))
+            }
+            "unsubscribe" => Ok(json!(lock(&self.subscribers).remove(&connection).is_some())),
+            "devices" => Ok(json!(UsbTransport::discover()?)),
+            "info" => {
+                let DeviceParams { device } = params(params_value)?;
+                let info = self.with_session(device, MiClient::read_all_info)?;
+                Ok(json!(info))
+            }
+            "close" => {
+                let DeviceParams { device } = params(params_value)?;
+                Ok(json!(lock(&self.sessions).remove(&device).is_some()))
+            }
+            "validate" => self.validate(params(params_value)?),
+            "download" => {
+                let DeviceParams { device } = params(params_value)?;
+                Ok(self.start_job(name, move |shared, job, cancel| {
+                    shared.download(device, job, cancel)
+                }))
+            }
+            "flash" => {
+                let flash: FlashParams = params(params_value)?;
+                Ok(self.start_job(name, move |shared, job, cancel| {
+                    shared.flash(flash, job, cancel)
+                }))
+            }
+            "cancel" | "approve" | "decline" => {
+                let JobParams { job } = params(params_value)?;
+                self.control(method, job, &name)
+            }
+            _ => Err(RpcError::new(-32601, format!("Unknown method {method}"))),
+        }
+    }
+
+    /// Cancels, approves or declines a job started by `client`.
+    fn control(&self, method: &str, job: u64, client: &str) -> Result<Value, RpcError> {
+        let jobs = lock(&self.jobs);
+        let job = jobs
+            .get(&job)
+            .ok_or_else(|| RpcError::new(-32602, "No such job"))?;
+        if job.owner != client {
+            return Err(RpcError::new(
+                UNAUTHORIZED,
+                "The job belongs to another client",
+            ));
+        }
+        Ok(json!(match method {
+            "cancel" => {
+                job.cancel.cancel();
+                true
+            }
+            _ => job.approval.answer(method == "approve"),
+        }))
+    }
+
+    /// The open session for `device`, connecting first if needed. A failed
+    /// call drops the session so the next one reconnects.
+    fn with_session<T>(
+        &self,
+        device: usize,
+        call: impl FnOnce(&mut MiClient) -> Result<T>,
+    ) -> Result<T, RpcError> {
+        let session = {
+            let mut sessions = lock(&self.sessions);
+            match sessions.get(&device) {
+                Some(session) => Arc::clone(session),
+                None => {
+                    let transport = UsbTransport::open(device, self.options.debug_usb)?;
+                    let session = Arc::new(Mutex::new(MiClient::new(transport)?));
+                    tracing::info!(device, "device session opened");
+                    sessions.insert(device, Arc::clone(&session));
+                    session
+                }
+            }
+        };
+        let mut client = match session.try_lock() {
+            Ok(client) => client,
+            Err(TryLockError::Poisoned(error)) => error.into_inner(),
+            Err(TryLockError::WouldBlock) => {
+                return Err(RpcError::new(BUSY, "The device is busy with another job"))
+            }
+        };
+        let result = call(&mut client);
+        if result.is_err() {
+            tracing::info!(device, "device session dropped after a failed call");
+            lock(&self.sessions).remove(&device);
+        }
+        Ok(result?)
+    }
+
+    fn validate(&self, request: ValidateParams) -> Result<Value, RpcError> {
+        let md5 = match (request.md5, &request.path) {
+            (Some(md5), _) => md5,
+            (None, Some(path)) => hash_cache::md5_cached(path, &Silent, &self.cancel)?,
+            (None, None) => return Err(RpcError::new(-32602, "Give path or md5")),
+        };
+        let identity = self.with_session(request.device, MiClient::read_all_info)?;
+        let session = FlashSession {
+            identity,
+            md5: md5.clone(),
+            token: TokenPolicy::Validate,
+            force_wipe: false,
+        };
+        let plan = session.plan(&self.options.server_url, &Silent, &self.cancel)?;
+        let response = plan.response.clone().unwrap_or_default();
+        let packages: Vec<_> = response
+            .packages()
+            .map(|(kind, package)| json!({ "kind": kind, "package": package }))
+            .collect();
+        Ok(json!({
+            "md5": md5,
+            "wipe": plan.wipe(),
+            "allowed": response.pkgrom_validate,
+            "message": response.code_message,
+            "packages": packages,
+        }))
+    }
+
+    /// Runs `work` on its own thread for `owner` and returns its job id at
+    /// once. The outcome arrives as a `completed` or `error` event.
+    fn start_job(
+        self: &Arc<Self>,
+        owner: String,
+        work: impl FnOnce(&Arc<Self>, u64, &CancelToken) -> Result<Value, RpcError> + Send + 'static,
+    ) -> Value {
+        let job = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let cancel = self.cancel.child();
+        lock(&self.jobs).insert(
+            job,
+            Job {
+                owner,
+                cancel: cancel.clone(),
+                approval: Approval::new(),
+            },
+        );
+        let shared = Arc::clone(self);
+        let span = tracing::info_span!("job", job);
+        std::thread::spawn(move || {
+            let _span = span.entered();
+            let event = match work(&shared, job, &cancel) {
+                Ok(result) => {
+                    tracing::info!("job completed");
+                    json!({ "event": "completed", "result": result })
+                }
+                Err(error) => {
+                    tracing::warn!("job failed: {}", error.message);
+                    json!({
+                    "event": "error",
+                    "code": error.data.as_ref().map_or(json!(ErrorCode::Failed), |data| data["code"].clone()),
+                    "message": error.message,
+                    })
+                }
+            };
+            shared.publish(job, event);
+            if let Some(job) = lock(&shared.jobs).remove(&job) {
+                job.approval.close();
+            }
+        });
+        json!({ "job": job })
+    }
+
+    /// Sends a job event to the owner's subscribed connections, dropping
+    /// ones that went away.
+    fn publish(&self, job: u64, mut event: Value) {
+        let Some(owner) = lock(&self.jobs).get(&job).map(|job| job.owner.clone()) else {
+            return;
+        };
+        event["job"] = job.into();
+        let notification = json!({
+            "jsonrpc": "2.0",
+            "method": "event",
+            "params": machine::stamp(event),
+        });
+        lock(&self.subscribers).retain(|_, subscriber| {
+            subscriber.client != owner || write_line(&subscriber.outbox, &notification).is_ok()
+        });
+    }
+
+    fn download(
+        self: &Arc<Self>,
+        device: usize,
+        job: u64,
+        cancel: &CancelToken,
+    ) -> Result<Value, RpcError> {
+        let observer = JobObserver::new(self, job);
+        let identity = self.with_session(device, MiClient::read_all_info)?;
+        let library = Library::open_default()?;
+        let entry = library.fetch_latest(&identity, &self.options.server_url, &observer, cancel)?;
+        Ok(json!({ "path": library.object_path(&entry.md5), "md5": entry.md5 }))
+    }
+
+    fn flash(
+        self: &Arc<Self>,
+        request: FlashParams,
+        job: u64,
+        cancel: &CancelToken,
+    ) -> Result<Value, RpcError> {
+        let observer = JobObserver::new(self, job);
+        let path = resolve_package(&request.package)?;
+        check_package(&path, request.allow_unknown_signer, &observer)?;
+        let md5 = hash_cache::md5_cached(&path, &observer, cancel)?;
+        let approval = lock(&self.jobs)
+            .get(&job)
+            .map(|job| job.approval.clone())
+            .unwrap_or_default();
+        let outcome = self.with_session(request.device, |client| {
+            let session = FlashSession {
+                identity: client.read_all_info()?,
+                md5,
+                token: TokenPolicy::Validate,
+                force_wipe: request.wipe,
+            };
+            let plan = session.plan(&self.options.server_url, &observer, cancel)?;
+            let mut confirmer = JobConfirmer {
+                shared: self,
+                job,
+                approval,
+                yes: request.yes,
+                cancel,
+            };
+            plan.execute(
+                client,
+                &mut FileSource::open(&path)?,
+                64 * 1024,
+                &mut confirmer,
+                &observer,
+                cancel,
+            )
+        })?;
+        // Recovery ends the session after a sideload.
+        lock(&self.sessions).remove(&request.device);
+        Ok(match outcome {
+            FlashOutcome::Completed { wiped } => json!({ "wiped": wiped }),
+            FlashOutcome::WipeDeclined => {
+                return Err(anyhow::Error::new(crate::flash::WipeDeclined).into())
+            }
+        })
+    }
+}
+
+/// A package path, or the library entry whose MD5 starts with `package`.
+fn resolve_package(package: &str) -> Result<PathBuf> {
+    let path = PathBuf::from(package);
+    if path.exists() {
+        return Ok(path);
+    }
+    let library = Library::open_default()?;
+    match library.find(package)? {
+        Some(entry) => Ok(library.object_path(&entry.md5)),
+        None => bail!("No package at {package} and no library entry with that MD5"),
+    }
+}
+
+fn reply(id: Value, result: Result<Value, RpcError>) -> Value {
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(error) => {
+            let mut body = json!({ "code": error.code, "message": error.message });
+            if let Some(data) = error.data {
+                body["data"] = data;
+            }
+            json!({ "jsonrpc": "2.0", "id": id, "error": body })
+        }
+    }
+}
+
+struct JobObserver {
+    shared: Arc<Shared>,
+    job: u64,
+    reported: Mutex<HashMap<Phase, u64>>,
+}
+
+impl JobObserver {
+    fn new(shared: &Arc<Shared>, job: u64) -> Self {
+        Self {
+            shared: Arc::clone(shared),
+            job,
+            reported: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl Observer for JobObserver {
+    fn phase(&self, phase: Phase) {
+        self.shared
+            .publish(self.job, json!({ "event": "phase", "phase": phase }));
+    }
+
+    fn progress(&self, phase: Phase, current: u64, total: u64) {
+        let event = match phase {
+            Phase::Sideload => "progress",
+            Phase::Hash => "hash_progress",
+            Phase::Download => "download_progress",
+            Phase::Validate => return,
+        };
+        // Once per percent for every phase; sockets are not worth a line per block.
+        let percent = current * 100 / total.max(1);
+        if lock(&self.reported).insert(phase, percent) != Some(percent) || current == total {
+            self.shared.publish(
+                self.job,
+                json!({ "event": event, "current": current, "total": total }),
+            );
+        }
+    }
+
+    fn message(&self, message: &str) {
+        self.shared.publish(
+            self.job,
+            json!({ "event": "status", "key": null, "params": {}, "message": message }),
+        );
+    }
+
+    fn mirror_segment(&self, segment: &MirrorSegment) {
+        self.shared.publish(
+            self.job,
+            json!({
+                "event": "mirror_segment",
+                "mirror": segment.mirror,
+                "start": segment.start,
+                "end": segment.end,
+            }),
+        );
+    }
+}
+
+/// Asks subscribers to `approve` or `decline` a wipe, unless the request
+/// approved it in advance.
+struct JobConfirmer<'a> {
+    shared: &'a Shared,
+    job: u64,
+    approval: Approval,
+    yes: bool,
+    cancel: &'a CancelToken,
+}
+
+impl Confirmer for JobConfirmer<'_> {
+    fn confirm_wipe(&mut self, reason: WipeReason) -> Result<bool> {
+        if self.yes {
+            return Ok(true);
+        }
+        self.approval.request();
+        let mut event = machine::message("prompt.erase_warning", &[]);
+        event.insert("event".into(), "confirmation_required".into());
+        event.insert("kind".into(), "data_wipe".into());
+        event.insert("reason".into(), json!(reason));
+        self.shared.publish(self.job, event.into());
+        loop {
+            self.cancel.check()?;
+            if let Some(decision) = self.approval.wait(Duration::from_millis(100)) {
+                return Ok(decision);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Client {
+        reader: BufReader<TcpStream>,
+        writer: TcpStream,
+        next: u64,
+    }
+
+    impl Client {
+        fn connect(endpoint: &Endpoint) -> Self {
+            let Endpoint::Tcp(address) = endpoint else {
+                panic!("expected TCP");
+            };
+            let writer = TcpStream::connect(address).unwrap();
+            Self {
+                reader: BufReader::new(writer.try_clone().unwrap()),
+                writer,
+                next: 0,
+            }
+        }
+
+        fn call(&mut self, method: &str, params: Value) -> Value {
+            self.next += 1;
+            let request =
+                json!({ "jsonrpc": "2.0", "id": self.next, "method": method, "params": params });
+            writeln!(self.writer, "{request}").unwrap();
+            let mut line = String::new();
+            self.reader.read_line(&mut line).unwrap();
+            serde_json::from_str(&line).unwrap()
+        }
+
+        /// Whether the daemon has closed the connection.
+        fn closed(&mut self) -> bool {
+            let mut line = String::new();
+            matches!(self.reader.read_line(&mut line), Ok(0) | Err(_))
+        }
+    }
+
+    fn daemon() -> (Endpoint, String, CancelToken, tempfile::TempDir) {
+        let directory = tempfile::tempdir().unwrap();
+        let tokens = TokenStore::at(directory.path().join("tokens.json"));
+        let token = tokens.issue("test").unwrap();
+        let cancel = CancelToken::new();
+        let daemon = Daemon::bind(
+            &Endpoint::parse("tcp:127.0.0.1:0").unwrap(),
+            DaemonOptions {
+                server_url: "http://127.0.0.1:9".into(),
+                debug_usb: false,
+                tokens,
+            },
+            cancel.clone(),
+        )
+        .unwrap();
+        let endpoint = daemon.endpoint().clone();
+        std::thread::spawn(move || daemon.run());
+        (endpoint, token, cancel, directory)
+    }
+
+    #[test]
+    fn endpoints_stay_on_loopback() {
+        assert_eq!(
+            Endpoint::parse("127.0.0.1:7420").unwrap(),
+            Endpoint::Tcp(SocketAddr::from(([127, 0, 0, 1], 7420)))
+        );
+        assert!(Endpoint::parse("tcp:0.0.0.0:7420").is_err());
+        #[cfg(unix)]
+        assert_eq!(
+            Endpoint::parse("/run/sensitivity.sock").unwrap(),
+            Endpoint::Unix(PathBuf::from("/run/sensitivity.sock"))
+        );
+    }
+
+    #[test]
+    fn requests_need_a_token_first() {
+        let (endpoint, token, cancel, _directory) = daemon();
+        let mut client = Client::connect(&endpoint);
+
+        assert_eq!(
+            client.call("ping", json!({}))["error"]["code"],
+            UNAUTHORIZED
+        );
+        assert!(client.closed());
+        let mut client = Client::connect(&endpoint);
+        assert_eq!(
+            client.call("auth", json!({ "token": "wrong" }))["error"]["code"],
+            UNAUTHORIZED
+        );
+        assert!(client.closed());
+
+        let mut client = Client::connect(&endpoint);
+        assert_eq!(
+            client.call("auth", json!({ "token": token }))["result"]["client"],
+            "test"
+        );
+        assert_eq!(client.call("ping", json!({}))["result"], "pong");
+        assert_eq!(
+            client.call("version", json!({}))["result"]["protocol"],
+            machine::PROTOCOL_VERSION
+        );
+        assert_eq!(client.call("nope", json!({}))["error"]["code"], -32601);
+        assert_eq!(
+            client.call("approve", json!({ "job": 99 }))["error"]["code"],
+            -32602
+        );
+        cancel.cancel();
+    }
+
+    #[test]
+    fn overlong_request_lines_close_the_connection() {
+        let (endpoint, _token, cancel, _directory) = daemon();
+        let mut client = Client::connect(&endpoint);
+        // Exactly the limit, so the daemon reads everything before closing.
+        let line = "x".repeat(MAX_REQUEST_LINE as usize);
+        client.writer.write_all(line.as_bytes()).unwrap();
+
+        let mut reply = String::new();
+        client.reader.read_line(&mut reply).unwrap();
+        assert!(reply.contains("too long"));
+        assert!(client.closed());
+        cancel.cancel();
+    }
+
+    /// A connected socket pair: the daemon's outbox and the client's reader.
+    fn outbox() -> (Outbox, BufReader<TcpStream>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        (
+            Arc::new(Mutex::new(Box::new(server))),
+            BufReader::new(client),
+        )
+    }
+
+    #[test]
+    fn jobs_answer_only_to_the_client_that_started_them() {
+        let directory = tempfile::tempdir().unwrap();
+        let shared = Shared::new(
+            DaemonOptions {
+                server_url: "http://127.0.0.1:9".into(),
+                debug_usb: false,
+                tokens: TokenStore::at(directory.path().join("tokens.json")),
+            },
+            CancelToken::new(),
+        );
+        lock(&shared.jobs).insert(
+            7,
+            Job {
+                owner: "owner".into(),
+                cancel: CancelToken::new(),
+                approval: Approval::new(),
+            },
+        );
+        let (owner_outbox, mut owner) = outbox();
+        let (other_outbox, mut other) = outbox();
+        for (connection, client, outbox) in [(1, "owner", owner_outbox), (2, "other", other_outbox)]
+        {
+            let subscriber = Subscriber {
+                client: client.into(),
+                outbox,
+            };
+            lock(&shared.subscribers).insert(connection, subscriber);
+        }
+
+        assert_eq!(
+            shared.control("approve", 7, "other").err().unwrap().code,
+            UNAUTHORIZED
+        );
+        assert!(shared.control("cancel", 7, "owner").is_ok());
+        shared.publish(7, json!({ "event": "phase", "phase": "sideload" }));
+        drop(shared);
+
+        let mut line = String::new();
+        owner.read_line(&mut line).unwrap();
+        assert!(line.contains("\"job\":7"));
+        line.clear();
+        assert_eq!(other.read_line(&mut line).unwrap(), 0);
+    }
+}
@@ -0,0 +1,166 @@
+// Copyright (C) 2026 Chromatic
+// Licensed under the GNU AGPL v3.0. See LICENSE file for details.
+// Website: https://chromatic.hu
+
+//! Mirror health probing. Each `MirrorList` host is asked for the package
+//! headers; hosts that answer are tried fastest first, https before http, and
+//! unreachable hosts stay at the end as a last resort.
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use reqwest::blocking::Client;
+use reqwest::header::{ACCEPT_RANGES, RANGE};
+use reqwest::StatusCode;
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// One `MirrorList` host and the package URL on it.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct Mirror {
+    pub base: String,
+    /// Package URL, including any signed query.
+    #[serde(skip)]
+    pub url: String,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct MirrorProbe {
+    pub mirror: Mirror,
+    /// Time to the response headers; `None` when the probe failed.
+    pub latency_ms: Option<u64>,
+    /// Whether the host advertised or honoured byte ranges.
+    pub ranges: bool,
+    pub error: Option<String>,
+}
+
+impl MirrorProbe {
+    pub fn available(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Probes one mirror with HEAD, falling back to a one-byte range request for
+/// hosts that refuse HEAD.
+pub fn probe(client: &Client, mirror: &Mirror) -> MirrorProbe {
+    let started = Instant::now();
+    let mut response = client.head(&mirror.url).timeout(PROBE_TIMEOUT).send();
+    let refuses_head = |status| {
+        matches!(
+            status,
+            StatusCode::METHOD_NOT_ALLOWED | StatusCode::FORBIDDEN | StatusCode::NOT_IMPLEMENTED
+        )
+    };
+    if matches!(&response, Ok(head) if refuses_head(head.status())) {
+        response = client
+            .get(&mirror.url)
+            .header(RANGE, "bytes=0-0")
+            .timeout(PROBE_TIMEOUT)
+            .send();
+    }
+    let latency_ms = Some(started.elapsed().as_millis() as u64);
+    match response {
+        Ok(response) if response.status().is_success() => MirrorProbe {
+            mirror: mirror.clone(),
+            latency_ms,
+            ranges: response.status() == StatusCode::PARTIAL_CONTENT
+                || response
+                    .headers()
+                    .get(ACCEPT_RANGES)
+                    .is_some_and(|value| value.as_bytes().eq_ignore_ascii_case(b"bytes")),
+            error: None,
+        },
+        Ok(response) => MirrorProbe {
+            mirror: mirror.clone(),
+            latency_ms: None,
+            ranges: false,
+            error: Some(format!("HTTP {}", response.status())),
+        },
+        Err(error) => MirrorProbe {
+            mirror: mirror.clone(),
+            latency_ms: None,
+            ranges: false,
+            error: Some(error.to_string()),
+        },
+    }
+}
+
+/// Probes all mirrors concurrently and returns them in the order to try.
+pub fn rank_mirrors(client: &Client, mirrors: &[Mirror]) -> Vec<MirrorProbe> {
+    let mut probes: Vec<MirrorProbe> = thread::scope(|scope| {
+        let handles: Vec<_> = mirrors
+            .iter()
+            .map(|mirror| scope.spawn(move || probe(client, mirror)))
+            .collect();
+        handles
+            .into_iter()
+            .zip(mirrors)
+            .map(|(handle, mirror)| {
+                handle.join().unwrap_or_else(|_| MirrorProbe {
+                    mirror: mirror.clone(),
+                    latency_ms: None,
+                    ranges: false,
+                    error: Some("probe panicked".into()),
+                })
+            })
+            .collect()
+    });
+    // Stable sort keeps the server's order among equals.
+    probes.sort_by_key(|probe| {
+        (
+            !probe.available(),
+            !probe.mirror.url.starts_with("https://"),
+            probe.latency_ms.unwrap_or(u64::MAX),
+        )
+    });
+    probes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    fn serve_head() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0u8; 1024];
+            let _ = stream.read(&mut request);
+            stream
+                .write_all(
+                    b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nAccept-Ranges: bytes\r\nConnection: close\r\n\r\n",
+                )
+                .unwrap();
+        });
+        format!("http://{address}")
+    }
+
+    fn mirror(base: String) -> Mirror {
+        Mirror {
+            url: format!("{base}/rom.zip"),
+            base,
+        }
+    }
+
+    #[test]
+    fn reachable_mirrors_are_ranked_before_unreachable_ones() {
+        // A port that was just released refuses connections.
+        let closed = TcpListener::bind("127.0.0.1:0").unwrap();
+        let dead = format!("http://{}", closed.local_addr().unwrap());
+        drop(closed);
+        let live = serve_head();
+
+        let ranked = rank_mirrors(
+            &Client::new(),
+            &[mirror(dead.clone()), mirror(live.clone())],
+        );
+
+        assert_eq!(ranked[0].mirror.base, live);
+        assert!(ranked[0].available() && ranked[0].ranges);
+        assert_eq!(ranked[1].mirror.base, dead);
+        assert!(!ranked[1].available());
+    }
+}
@@ -0,0 +1,830 @@
+// Copyright (C) 2026 Chromatic
+// Licensed under the GNU AGPL v3.0. See LICENSE file for details.
+// Website: https://chromatic.hu
+
+use anyhow::{anyhow, bail, Context, Result};
+use md5::Digest;
+use reqwest::blocking::Client;
+use reqwest::header::{CONTENT_RANGE, ETAG, IF_RANGE, LAST_MODIFIED, RANGE};
+use reqwest::StatusCode;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::cancel::CancelToken;
+use crate::mi::version::RomVersion;
+use crate::observer::{Counter, Observer, Phase, Silent};
+
+pub mod mirror;
+mod rate;
+mod segmented;
+mod stream;
+
+pub use mirror::{rank_mirrors, Mirror, MirrorProbe};
+pub use rate::parse_rate;
+pub use stream::StreamSource;
+
+use rate::RateLimiter;
+
+pub struct LatestInfo {
+    pub filename: String, // may contain ?t=...&s=...
+    pub md5: String,
+    pub version: Option<RomVersion>,
+}
+
+pub fn parse_latest_from_json(json: &str) -> Result<(LatestInfo, Vec<String>)> {
+    let v: serde_json::Value = serde_json::from_str(json)?;
+    let latest = v
+        .get("LatestRom")
+        .or_else(|| v.get("PkgRom"))
+        .ok_or_else(|| anyhow!("LatestRom/PkgRom missing in JSON"))?;
+    let filename = latest
+        .get("filename")
+        .and_then(|x| x.as_str())
+        .ok_or_else(|| anyhow!("filename missing in LatestRom/PkgRom"))?
+        .to_string();
+    let md5 = latest
+        .get("md5")
+        .and_then(|x| x.as_str())
+        .ok_or_else(|| anyhow!("md5 missing in LatestRom/PkgRom"))?
+        .to_string();
+    let version = latest
+        .get("version")
+        .and_then(|x| x.as_str())
+        .and_then(|x| x.parse().ok())
+        .or_else(|| RomVersion::from_package_name(&filename));
+    let mirrors = v
+        .get("MirrorList")
+        .and_then(|x| x.as_array())
+        .map(|arr| {
+            arr.iter()
+                .filter_map(|s| s.as_str().map(|t| t.to_string()))
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    Ok((
+        LatestInfo {
+            filename,
+            md5,
+            version,
+        },
+        mirrors,
+    ))
+}
+
+/// Per-read timeout; a mirror that sends nothing for this long has stalled.
+pub const STALL_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// HTTP client for ROM downloads, with stall detection.
+pub fn http_client() -> Result<Client> {
+    Ok(Client::builder()
+        .user_agent("MiTunes_UserAgent_v3.0")
+        .timeout(STALL_TIMEOUT)
+        .build()?)
+}
+
+/// Package URLs on every mirror, https mirrors first.
+pub fn mirror_urls(mirrors: &[String], filename: &str) -> Vec<Mirror> {
+    let (secure, plain): (Vec<_>, Vec<_>) = mirrors
+        .iter()
+        .partition(|base| base.starts_with("https://"));
+    secure
+        .into_iter()
+        .chain(plain)
+        .map(|base| Mirror {
+            base: base.trim_end_matches('/').to_string(),
+            url: format!(
+                "{}/{}",
+                base.trim_end_matches('/'),
+                filename.trim_start_matches('/')
+            ),
+        })
+        .collect()
+}
+
+pub fn choose_url(mirrors: &[String], filename: &str) -> Option<String> {
+    mirror_urls(mirrors, filename)
+        .into_iter()
+        .next()
+        .map(|mirror| mirror.url)
+}
+
+pub use crate::observer::MirrorSegment;
+
+pub fn download_with_md5(
+    client: &Client,
+    url: &str,
+    dest_dir: &Path,
+    expect_md5: &str,
+) -> Result<PathBuf> {
+    let mirror = Mirror {
+        base: url.to_string(),
+        url: url.to_string(),
+    };
+    download_from_mirrors(
+        client,
+        &[mirror],
+        dest_dir,
+        expect_md5,
+        &Silent,
+        &CancelToken::new(),
+    )
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DownloadOptions {
+    /// Concurrent range requests; 1 downloads as a single resumable stream.
+    pub connections: usize,
+    /// Bytes per range in segmented mode.
+    pub segment_size: u64,
+    /// Cap across all connections, in bytes per second.
+    pub max_bytes_per_second: Option<u64>,
+}
+
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        Self {
+            connections: 1,
+            segment_size: 32 * 1024 * 1024,
+            max_bytes_per_second: None,
+        }
+    }
+}
+
+/// Downloads from the first mirror that works, failing over to the next on
+/// HTTP errors or stalls and continuing from the same offset.
+pub fn download_from_mirrors(
+    client: &Client,
+    mirrors: &[Mirror],
+    dest_dir: &Path,
+    expect_md5: &str,
+    observer: &dyn Observer,
+    cancel: &CancelToken,
+) -> Result<PathBuf> {
+    download_with_options(
+        client,
+        mirrors,
+        dest_dir,
+        expect_md5,
+        &DownloadOptions::default(),
+        observer,
+        cancel,
+    )
+}
+
+/// Like [`download_from_mirrors`], optionally fetching ranges concurrently
+/// across mirrors and capping bandwidth. A resumable partial file from an
+/// earlier single-stream attempt is finished as a single stream; segmented
+/// transfers start over, and fall back to a single stream when no mirror
+/// honours byte ranges. Cancellation keeps a resumable partial file.
+#[tracing::instrument(name = "download", skip_all, fields(md5 = expect_md5))]
+pub fn download_with_options(
+    client: &Client,
+    mirrors: &[Mirror],
+    dest_dir: &Path,
+    expect_md5: &str,
+    options: &DownloadOptions,
+    observer: &dyn Observer,
+    cancel: &CancelToken,
+) -> Result<PathBuf> {
+    if expect_md5.len() != 32
+        || !expect_md5
+            .chars()
+            .all(|character| character.is_ascii_hexdigit())
+    {
+        bail!("Expected MD5 must contain exactly 32 hexadecimal characters");
+    }
+    let first = mirrors
+        .first()
+        .ok_or_else(|| anyhow!("No download mirror available"))?;
+    fs::create_dir_all(dest_dir)
+        .with_context(|| format!("create download directory {}", dest_dir.display()))?;
+    observer.phase(Phase::Download);
+    let mut download =
+        PartialDownload::open(dest_dir, package_file_name(&first.url), expect_md5, cancel)?;
+    tracing::info!(
+        mirrors = mirrors.len(),
+        resume_from = download.offset,
+        "downloading {}",
+        download.dest.display()
+    );
+
+    let progress = Counter::new(observer, Phase::Download);
+    let limiter = options.max_bytes_per_second.map(RateLimiter::new);
+
+    if options.connections > 1 && download.offset == 0 {
+        if let Some(total) = segmented::range_length(client, mirrors) {
+            progress.set_total(total);
+            let partial = download.partial.with_extension("segmented.part");
+            let result = segmented::Segmented {
+                client,
+                mirrors,
+                partial: &partial,
+                total,
+                connections: options.connections,
+                segment_size: options.segment_size,
+                limiter: limiter.as_ref(),
+                progress: &progress,
+                cancel,
+            }
+            .run(&download.dest, expect_md5);
+            return result;
+        }
+    }
+
+    let mut failures = Vec::new();
+    for mirror in mirrors {
+        match download.fetch(client, mirror, &progress, limiter.as_ref(), cancel) {
+            Ok(()) => return download.finalize(),
+            Err(error) if cancel.is_cancelled() => {
+                return Err(error.context(format!(
+                    "{} bytes kept in {} for resuming",
+                    download.offset,
+                    download.partial.display()
+                )));
+            }
+            Err(error) => {
+                tracing::info!(mirror = %mirror.base, "mirror failed: {error:#}");
+                failures.push(format!("{}: {error:#}", mirror.base));
+            }
+        }
+    }
+    bail!(
+        "Downloading ROM package failed on every mirror; {} bytes kept in {} for resuming\n  {}",
+        download.offset,
+        download.partial.display(),
+        failures.join("\n  ")
+    )
+}
+
+pub fn package_file_name(url: &str) -> &str {
+    // derive file name (strip query)
+    let base = url.split('?').next().unwrap_or(url);
+    let base = base.rsplit('/').next().unwrap_or(base);
+    if base.is_empty() {
+        "download.zip"
+    } else {
+        base
+    }
+}
+
+/// A `.part` file and the running hash of its contents.
+struct PartialDownload {
+    partial: PathBuf,
+    sidecar: PathBuf,
+    dest: PathBuf,
+    md5: String,
+    hasher: md5::Md5,
+    offset: u64,
+    state: Option<PartialState>,
+}
+
+impl PartialDownload {
+    fn open(dest_dir: &Path, base: &str, expect_md5: &str, cancel: &CancelToken) -> Result<Self> {
+        let partial = dest_dir.join(format!(".{base}.part"));
+        let sidecar = dest_dir.join(format!(".{base}.part.json"));
+        // Resume only a partial file recorded for the same expected package.
+        let state = PartialState::load(&sidecar)
+            .filter(|state| state.md5.eq_ignore_ascii_case(expect_md5))
+            .filter(|_| partial.exists());
+        let mut hasher = md5::Md5::new();
+        let mut offset = 0;
+        if state.is_some() {
+            offset = hash_prefix(&partial, &mut hasher, cancel)?;
+        } else {
+            let _ = fs::remove_file(&partial);
+            let _ = fs::remove_file(&sidecar);
+        }
+        Ok(Self {
+            partial,
+            sidecar,
+            dest: dest_dir.join(base),
+            md5: expect_md5.to_lowercase(),
+            hasher,
+            offset,
+            state,
+        })
+    }
+
+    /// Requests the rest of the file from one mirror and appends it.
+    fn fetch(
+        &mut self,
+        client: &Client,
+        mirror: &Mirror,
+        progress: &Counter,
+        limiter: Option<&RateLimiter>,
+        cancel: &CancelToken,
+    ) -> Result<()> {
+        let mut request = client.get(&mirror.url);
+        if self.offset > 0 {
+            request = request.header(RANGE, format!("bytes={}-", self.offset));
+            if let Some(validator) = self.state.as_ref().and_then(PartialState::validator) {
+                request = request.header(IF_RANGE, validator);
+            }
+        }
+        let resp = request
+            .send()
+            .with_context(|| format!("GET {}", mirror.url))?;
+        let status = resp.status();
+        if self.offset > 0 && status == StatusCode::RANGE_NOT_SATISFIABLE {
+            // The kept prefix may already be the whole file.
+            return Ok(());
+        }
+        if !status.is_success() {
+            bail!("Download failed: HTTP {} from {}", status, mirror.url);
+        }
+        if self.offset > 0 && status == StatusCode::PARTIAL_CONTENT {
+            let start = content_range_start(&resp);
+            if start != Some(self.offset) {
+                // Appending would corrupt the file, and restarting would
+                // throw away a good prefix; let another mirror or retry try.
+                bail!(
+                    "{} answered a resume from byte {} with range starting at {}",
+                    mirror.url,
+                    self.offset,
+                    start.map_or_else(|| "an unknown offset".into(), |start| start.to_string())
+                );
+            }
+        } else if self.offset > 0 {
+            // The server ignored the range or the file changed; start over.
+            self.offset = 0;
+            self.hasher = md5::Md5::new();
+        }
+        let header = |name| {
+            resp.headers()
+                .get(name)
+                .and_then(|value: &reqwest::header::HeaderValue| value.to_str().ok())
+                .map(str::to_string)
+        };
+        let previous = self.state.take();
+        let state = PartialState {
+            url: mirror.url.clone(),
+            md5: self.md5.clone(),
+            etag: header(ETAG).or_else(|| previous.as_ref().and_then(|s| s.etag.clone())),
+            last_modified: header(LAST_MODIFIED)
+                .or_else(|| previous.as_ref().and_then(|s| s.last_modified.clone())),
+        };
+        state.save(&self.sidecar)?;
+        self.state = Some(state);
+
+        if let Some(len) = resp.content_length() {
+            progress.set_total(len + self.offset);
+        }
+        progress.set(self.offset);
+        let start = self.offset;
+        let result = self.append(resp, progress, limiter, cancel);
+        if self.offset > start {
+            progress.observer().mirror_segment(&MirrorSegment {
+                mirror: mirror.base.clone(),
+                start,
+                end: self.offset,
+            });
+        }
+        result
+    }
+
+    fn append(
+        &mut self,
+        mut src: impl Read,
+        progress: &Counter,
+        limiter: Option<&RateLimiter>,
+        cancel: &CancelToken,
+    ) -> Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(false)
+            .open(&self.partial)
+            .with_context(|| format!("open {}", self.partial.display()))?;
+        // Drop any bytes a failed write left past the hashed prefix.
+        file.set_len(self.offset)?;
+        file.seek(SeekFrom::Start(self.offset))?;
+        let mut buf = [0u8; 128 * 1024];
+        loop {
+            cancel.check()?;
+            let n = src.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            file.write_all(&buf[..n])?;
+            self.hasher.update(&buf[..n]);
+            self.offset += n as u64;
+            progress.add(n as u64);
+            if let Some(limiter) = limiter {
+                limiter.consume(n as u64);
+            }
+        }
+        file.flush()?;
+        Ok(())
+    }
+
+    fn finalize(self) -> Result<PathBuf> {
+        let got = hex::encode(self.hasher.finalize());
+        if got != self.md5 {
+            let _ = fs::remove_file(&self.partial);
+            let _ = fs::remove_file(&self.sidecar);
+            bail!(
+                "MD5 mismatch after download: got {}, expected {}",
+                got,
+                self.md5
+            );
+        }
+        if self.dest.exists() {
+            fs::remove_file(&self.dest)
+                .with_context(|| format!("replace existing download {}", self.dest.display()))?;
+        }
+        fs::rename(&self.partial, &self.dest)
+            .with_context(|| format!("finalize verified download {}", self.dest.display()))?;
+        let _ = fs::remove_file(&self.sidecar);
+        Ok(self.dest)
+    }
+}
+
+/// What a kept `.part` file was downloaded from, stored next to it.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+struct PartialState {
+    url: String,
+    md5: String,
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+impl PartialState {
+    fn load(path: &Path) -> Option<Self> {
+        serde_json::from_slice(&fs::read(path).ok()?).ok()
+    }
+
+    fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, serde_json::to_vec_pretty(self)?)
+            .with_context(|| format!("write {}", path.display()))
+    }
+
+    /// Strong validator for If-Range; weak ETags are not allowed there.
+    fn validator(&self) -> Option<&str> {
+        self.etag
+            .as_deref()
+            .filter(|etag| !etag.starts_with("W/"))
+            .or(self.last_modified.as_deref())
+    }
+}
+
+fn hash_prefix(path: &Path, hasher: &mut md5::Md5, cancel: &CancelToken) -> Result<u64> {
+    let mut file = File::open(path).with_context(|| format!("open {}", path.display()))?;
+    let mut buf = vec![0u8; 1024 * 1024];
+    let mut total = 0u64;
+    loop {
+        cancel.check()?;
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            return Ok(total);
+        }
+        hasher.update(&buf[..n]);
+        total += n as u64;
+    }
+}
+
+fn content_range_start(resp: &reqwest::blocking::Response) -> Option<u64> {
+    // Content-Range: bytes 100-199/200
+    let value = resp.headers().get(CONTENT_RANGE)?.to_str().ok()?;
+    let range = value.strip_prefix("bytes ")?;
+    range.split('-').next()?.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[derive(Default)]
+    struct Segments(std::sync::Mutex<Vec<MirrorSegment>>);
+
+    impl Observer for Segments {
+        fn mirror_segment(&self, segment: &MirrorSegment) {
+            self.0.lock().unwrap().push(segment.clone());
+        }
+    }
+
+    fn serve_once(body: &'static [u8]) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0u8; 1024];
+            let _ = stream.read(&mut request);
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                body.len()
+            )
+            .unwrap();
+            stream.write_all(body).unwrap();
+        });
+        format!("http://{address}/recovery.zip")
+    }
+
+    /// Serves `body` to each connection; a `Range` request gets 206 when
+    /// `honor_range` is set. The first response can be cut short after
+    /// `truncate_first` bytes. Request heads are returned through the channel.
+    fn serve_ranges(
+        body: &'static [u8],
+        connections: usize,
+        honor_range: bool,
+        truncate_first: Option<usize>,
+    ) -> (String, std::sync::mpsc::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        thread::spawn(move || {
+            for connection in 0..connections {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = [0u8; 2048];
+                let n = stream.read(&mut request).unwrap();
+                let head = String::from_utf8_lossy(&request[..n]).to_lowercase();
+                let range = head
+                    .lines()
+                    .find_map(|line| line.strip_prefix("range: bytes="))
+                    .and_then(|range| range.trim().split_once('-'))
+                    .and_then(|(start, end)| {
+                        let start = start.parse::<usize>().ok()?;
+                        let end = end.parse::<usize>().map_or(body.len(), |end| end + 1);
+                        Some((start, end.min(body.len())))
+                    })
+                    .filter(|_| honor_range);
+                let _ = sender.send(head);
+                let (status, range, data) = match range {
+                    Some((start, end)) => (
+                        "206 Partial Content",
+                        format!(
+                            "Content-Range: bytes {start}-{}/{}\r\n",
+                            end - 1,
+                            body.len()
+                        ),
+                        &body[start..end],
+                    ),
+                    None => ("200 OK", String::new(), body),
+                };
+                write!(
+                    stream,
+                    "HTTP/1.1 {status}\r\nContent-Length: {}\r\nETag: \"v1\"\r\n{range}Connection: close\r\n\r\n",
+                    data.len()
+                )
+                .unwrap();
+                let sent = match truncate_first {
+                    Some(limit) if connection == 0 => &data[..limit],
+                    _ => data,
+                };
+                stream.write_all(sent).unwrap();
+            }
+        });
+        (format!("http://{address}/recovery.zip"), receiver)
+    }
+
+    #[test]
+    fn parses_latest_rom_and_prefers_https_mirror() {
+        let json = r#"{
+            "LatestRom":{"filename":"rom.zip?t=1","md5":"abc"},
+            "MirrorList":["http://mirror-one", "https://mirror-two"]
+        }"#;
+        let (latest, mirrors) = parse_latest_from_json(json).unwrap();
+
+        assert_eq!(latest.filename, "rom.zip?t=1");
+        assert_eq!(latest.md5, "abc");
+        assert!(latest.version.is_none());
+        assert_eq!(
+            choose_url(&mirrors, &latest.filename).unwrap(),
+            "https://mirror-two/rom.zip?t=1"
+        );
+    }
+
+    #[test]
+    fn latest_rom_version_falls_back_to_the_package_name() {
+        let json = r#"{"LatestRom":{
+            "filename":"miui_GARNETGlobal_OS2.0.203.0.VNRMIXM_0123abcd_15.0.zip","md5":"abc"
+        }}"#;
+        let (latest, _) = parse_latest_from_json(json).unwrap();
+        assert_eq!(latest.version.unwrap().to_string(), "OS2.0.203.0.VNRMIXM");
+    }
+
+    #[test]
+    fn verified_download_is_atomically_finalized() {
+        let directory = tempfile::tempdir().unwrap();
+        let url = serve_once(b"hello");
+        let path = download_with_md5(
+            &Client::new(),
+            &url,
+            directory.path(),
+            "5d41402abc4b2a76b9719d911017c592",
+        )
+        .unwrap();
+
+        assert_eq!(fs::read(path).unwrap(), b"hello");
+        assert!(!directory.path().join(".recovery.zip.part").exists());
+    }
+
+    #[test]
+    fn checksum_failure_removes_partial_download() {
+        let directory = tempfile::tempdir().unwrap();
+        let url = serve_once(b"corrupt");
+        let result = download_with_md5(
+            &Client::new(),
+            &url,
+            directory.path(),
+            "5d41402abc4b2a76b9719d911017c592",
+        );
+
+        assert!(result.unwrap_err().to_string().contains("MD5 mismatch"));
+        assert!(!directory.path().join("recovery.zip").exists());
+        assert!(!directory.path().join(".recovery.zip.part").exists());
+    }
+
+    const HELLO_WORLD_MD5: &str = "5eb63bbbe01eeed093cb22bb8f5acdc3";
+
+    #[test]
+    fn interrupted_download_resumes_with_range_and_if_range() {
+        let directory = tempfile::tempdir().unwrap();
+        let (url, requests) = serve_ranges(b"hello world", 2, true, Some(4));
+
+        assert!(
+            download_with_md5(&Client::new(), &url, directory.path(), HELLO_WORLD_MD5).is_err()
+        );
+        assert_eq!(
+            fs::read(directory.path().join(".recovery.zip.part")).unwrap(),
+            b"hell"
+        );
+        assert!(directory.path().join(".recovery.zip.part.json").exists());
+
+        let path =
+            download_with_md5(&Client::new(), &url, directory.path(), HELLO_WORLD_MD5).unwrap();
+        assert_eq!(fs::read(path).unwrap(), b"hello world");
+        assert!(!directory.path().join(".recovery.zip.part.json").exists());
+        let resumed = requests.iter().nth(1).unwrap();
+        assert!(resumed.contains("range: bytes=4-"));
+        assert!(resumed.contains("if-range: \"v1\""));
+    }
+
+    #[test]
+    fn failed_mirror_hands_over_at_the_same_offset() {
+        let directory = tempfile::tempdir().unwrap();
+        let (first, _) = serve_ranges(b"hello world", 1, true, Some(4));
+        let (second, requests) = serve_ranges(b"hello world", 1, true, None);
+        let mirrors: Vec<_> = [first, second]
+            .into_iter()
+            .map(|url| Mirror {
+                base: url.trim_end_matches("/recovery.zip").to_string(),
+                url,
+            })
+            .collect();
+        let segments = Segments::default();
+
+        let path = download_from_mirrors(
+            &Client::new(),
+            &mirrors,
+            directory.path(),
+            HELLO_WORLD_MD5,
+            &segments,
+            &CancelToken::new(),
+        )
+        .unwrap();
+        let segments = segments.0.into_inner().unwrap();
+
+        assert_eq!(fs::read(path).unwrap(), b"hello world");
+        assert!(requests.recv().unwrap().contains("range: bytes=4-"));
+        assert_eq!(
+            segments,
+            [
+                MirrorSegment {
+                    mirror: mirrors[0].base.clone(),
+                    start: 0,
+                    end: 4
+                },
+                MirrorSegment {
+                    mirror: mirrors[1].base.clone(),
+                    start: 4,
+                    end: 11
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn segmented_download_spreads_ranges_over_mirrors() {
+        let directory = tempfile::tempdir().unwrap();
+        // One length probe plus three four-byte ranges, at most, per mirror.
+        let mirrors: Vec<_> = (0..2)
+            .map(|_| {
+                let (url, _) = serve_ranges(b"hello world", 4, true, None);
+                Mirror {
+                    base: url.trim_end_matches("/recovery.zip").to_string(),
+                    url,
+                }
+            })
+            .collect();
+        let options = DownloadOptions {
+            connections: 2,
+            segment_size: 4,
+            max_bytes_per_second: None,
+        };
+        let segments = Segments::default();
+
+        let path = download_with_options(
+            &Client::new(),
+            &mirrors,
+            directory.path(),
+            HELLO_WORLD_MD5,
+            &options,
+            &segments,
+            &CancelToken::new(),
+        )
+        .unwrap();
+        let mut segments = segments.0.into_inner().unwrap();
+
+        assert_eq!(fs::read(path).unwrap(), b"hello world");
+        segments.sort_by_key(|segment| segment.start);
+        let ranges: Vec<_> = segments.iter().map(|s| (s.start, s.end)).collect();
+        assert_eq!(ranges, [(0, 4), (4, 8), (8, 11)]);
+        assert!(!directory
+            .path()
+            .join(".recovery.zip.segmented.part")
+            .exists());
+    }
+
+    #[test]
+    fn cancelled_download_keeps_the_partial_file() {
+        let directory = tempfile::tempdir().unwrap();
+        let url = serve_once(b"hello world");
+        let cancel = CancelToken::new();
+        cancel.cancel();
+
+        let error = download_from_mirrors(
+            &Client::new(),
+            &[Mirror {
+                base: url.clone(),
+                url,
+            }],
+            directory.path(),
+            HELLO_WORLD_MD5,
+            &Silent,
+            &cancel,
+        )
+        .unwrap_err();
+
+        assert!(crate::cancel::is_cancelled(&error));
+        assert!(directory.path().join(".recovery.zip.part.json").exists());
+    }
+
+    #[test]
+    fn server_ignoring_ranges_falls_back_to_a_full_download() {
+        let directory = tempfile::tempdir().unwrap();
+        fs::write(directory.path().join(".recovery.zip.part"), b"junk").unwrap();
+        PartialState {
+            url: "http://old.invalid/recovery.zip".into(),
+            md5: HELLO_WORLD_MD5.into(),
+            etag: None,
+            last_modified: None,
+        }
+        .save(&directory.path().join(".recovery.zip.part.json"))
+        .unwrap();
+        let (url, requests) = serve_ranges(b"hello world", 1, false, None);
+
+        let path =
+            download_with_md5(&Client::new(), &url, directory.path(), HELLO_WORLD_MD5).unwrap();
+        assert_eq!(fs::read(path).unwrap(), b"hello world");
+        assert!(requests.recv().unwrap().contains("range: bytes=4-"));
+    }
+
+    #[test]
+    fn misplaced_partial_content_keeps_the_partial_file() {
+        let directory = tempfile::tempdir().unwrap();
+        fs::write(directory.path().join(".recovery.zip.part"), b"hell").unwrap();
+        PartialState {
+            url: "http://old.invalid/recovery.zip".into(),
+            md5: HELLO_WORLD_MD5.into(),
+            etag: None,
+            last_modified: None,
+        }
+        .save(&directory.path().join(".recovery.zip.part.json"))
+        .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/recovery.zip", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0u8; 1024];
+            let _ = stream.read(&mut request);
+            stream
+                .write_all(
+                    b"HTTP/1.1 206 Partial Content\r\nContent-Length: 11\r\nContent-Range: bytes 0-10/11\r\nConnection: close\r\n\r\nhello world",
+                )
+                .unwrap();
+        });
+
+        let error =
+            download_with_md5(&Client::new(), &url, directory.path(), HELLO_WORLD_MD5).unwrap_err();
+        assert!(format!("{error:#}").contains("starting at 0"));
+        assert_eq!(
+            fs::read(directory.path().join(".recovery.zip.part")).unwrap(),
+            b"hell"
+        );
+    }
+}
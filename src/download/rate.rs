@@ -0,0 +1,79 @@
+// Copyright (C) 2026 Chromatic
+// Licensed under the GNU AGPL v3.0. See LICENSE file for details.
+// Website: https://chromatic.hu
+
+//! Download bandwidth cap shared by every connection of one download.
+
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+
+pub(super) struct RateLimiter {
+    bytes_per_second: u64,
+    state: Mutex<(Instant, u64)>,
+}
+
+impl RateLimiter {
+    pub(super) fn new(bytes_per_second: u64) -> Self {
+        Self {
+            bytes_per_second: bytes_per_second.max(1),
+            state: Mutex::new((Instant::now(), 0)),
+        }
+    }
+
+    /// Records `bytes` and sleeps while the download is ahead of the cap.
+    pub(super) fn consume(&self, bytes: u64) {
+        let wait = {
+            let mut state = self.state.lock().unwrap_or_else(|error| error.into_inner());
+            state.1 += bytes;
+            let due = Duration::from_secs_f64(state.1 as f64 / self.bytes_per_second as f64);
+            due.saturating_sub(state.0.elapsed())
+        };
+        if !wait.is_zero() {
+            thread::sleep(wait);
+        }
+    }
+}
+
+/// Parses a rate such as `500K`, `10M` or `1G` (bytes per second, binary units).
+pub fn parse_rate(text: &str) -> Result<u64> {
+    let text = text.trim();
+    let (number, unit) = text.split_at(
+        text.find(|character: char| !character.is_ascii_digit())
+            .unwrap_or(text.len()),
+    );
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().trim_end_matches("B") {
+        "" => 1,
+        "K" | "KI" => 1 << 10,
+        "M" | "MI" => 1 << 20,
+        "G" | "GI" => 1 << 30,
+        _ => bail!("unrecognized rate unit in {text:?}; use K, M or G"),
+    };
+    let number: u64 = number
+        .parse()
+        .with_context(|| format!("invalid rate {text:?}"))?;
+    if number == 0 {
+        bail!("rate must be greater than zero");
+    }
+    number
+        .checked_mul(multiplier)
+        .with_context(|| format!("rate {text:?} is too large"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rates_accept_binary_unit_suffixes() {
+        assert_eq!(parse_rate("2048").unwrap(), 2048);
+        assert_eq!(parse_rate("500K").unwrap(), 500 * 1024);
+        assert_eq!(parse_rate("10m").unwrap(), 10 * 1024 * 1024);
+        assert_eq!(parse_rate("1GiB").unwrap(), 1 << 30);
+        assert!(parse_rate("0").is_err());
+        assert!(parse_rate("fast").is_err());
+        assert!(parse_rate("5T").is_err());
+    }
+}
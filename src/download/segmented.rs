@@ -0,0 +1,229 @@
+// Copyright (C) 2026 Chromatic
+// Licensed under the GNU AGPL v3.0. See LICENSE file for details.
+// Website: https://chromatic.hu
+
+//! Segmented downloads: the package is split into byte ranges that several
+//! connections fetch concurrently, spread over the mirrors, into a
+//! preallocated part file. The MD5 is checked once every range has landed.
+
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use anyhow::{bail, Context, Result};
+use md5::Digest;
+use reqwest::blocking::Client;
+use reqwest::header::RANGE;
+use reqwest::StatusCode;
+
+use super::rate::RateLimiter;
+use super::{content_range_start, Mirror, MirrorSegment};
+use crate::cancel::CancelToken;
+use crate::observer::Counter;
+
+/// Length of the package, from the first mirror that honours byte ranges.
+pub(super) fn range_length(client: &Client, mirrors: &[Mirror]) -> Option<u64> {
+    mirrors.iter().find_map(|mirror| {
+        let response = client
+            .get(&mirror.url)
+            .header(RANGE, "bytes=0-0")
+            .send()
+            .ok()?;
+        if response.status() != StatusCode::PARTIAL_CONTENT {
+            return None;
+        }
+        // Content-Range: bytes 0-0/6442450944
+        let value = response
+            .headers()
+            .get(reqwest::header::CONTENT_RANGE)?
+            .to_str()
+            .ok()?;
+        value.rsplit('/').next()?.parse().ok()
+    })
+}
+
+/// One segmented transfer into a preallocated part file.
+pub(super) struct Segmented<'a> {
+    pub client: &'a Client,
+    pub mirrors: &'a [Mirror],
+    pub partial: &'a Path,
+    pub total: u64,
+    pub connections: usize,
+    pub segment_size: u64,
+    pub limiter: Option<&'a RateLimiter>,
+    pub progress: &'a Counter<'a>,
+    pub cancel: &'a CancelToken,
+}
+
+impl Segmented<'_> {
+    pub(super) fn run(&self, dest: &Path, expect_md5: &str) -> Result<PathBuf> {
+        File::create(self.partial)
+            .and_then(|file| file.set_len(self.total))
+            .with_context(|| format!("preallocate {}", self.partial.display()))?;
+        let result = self
+            .fetch_all()
+            .and_then(|()| verify(self.partial, expect_md5, self.cancel));
+        if let Err(error) = result {
+            // Holes in a preallocated file cannot be resumed safely.
+            let _ = fs::remove_file(self.partial);
+            return Err(error);
+        }
+        if dest.exists() {
+            fs::remove_file(dest)
+                .with_context(|| format!("replace existing download {}", dest.display()))?;
+        }
+        fs::rename(self.partial, dest)
+            .with_context(|| format!("finalize verified download {}", dest.display()))?;
+        Ok(dest.to_path_buf())
+    }
+
+    fn fetch_all(&self) -> Result<()> {
+        let segment_size = self.segment_size.max(1);
+        let queue: Mutex<VecDeque<(u64, u64)>> = Mutex::new(
+            (0..self.total.div_ceil(segment_size))
+                .map(|index| {
+                    let start = index * segment_size;
+                    (start, (start + segment_size).min(self.total))
+                })
+                .collect(),
+        );
+        let failed = AtomicBool::new(false);
+        let errors = Mutex::new(Vec::new());
+        thread::scope(|scope| {
+            for worker in 0..self.connections.max(1) {
+                let (queue, failed, errors) = (&queue, &failed, &errors);
+                scope.spawn(move || {
+                    let result = self.worker(worker, queue, failed);
+                    if let Err(error) = result {
+                        failed.store(true, Ordering::Relaxed);
+                        errors
+                            .lock()
+                            .unwrap_or_else(|error| error.into_inner())
+                            .push(format!("{error:#}"));
+                    }
+                });
+            }
+        });
+        self.cancel.check()?;
+        let errors = errors
+            .into_inner()
+            .unwrap_or_else(|error| error.into_inner());
+        if !errors.is_empty() {
+            bail!("Segmented download failed: {}", errors.join("; "));
+        }
+        Ok(())
+    }
+
+    fn worker(
+        &self,
+        worker: usize,
+        queue: &Mutex<VecDeque<(u64, u64)>>,
+        failed: &AtomicBool,
+    ) -> Result<()> {
+        // Each worker has its own handle so seeks do not interfere.
+        let mut file = OpenOptions::new()
+            .write(true)
+            .open(self.partial)
+            .with_context(|| format!("open {}", self.partial.display()))?;
+        loop {
+            if failed.load(Ordering::Relaxed) || self.cancel.is_cancelled() {
+                return Ok(());
+            }
+            let Some((start, end)) = queue
+                .lock()
+                .unwrap_or_else(|error| error.into_inner())
+                .pop_front()
+            else {
+                return Ok(());
+            };
+            // Spread workers over mirrors, moving on when one fails.
+            let mut attempts = Vec::new();
+            let served = (0..self.mirrors.len()).find_map(|attempt| {
+                let mirror = &self.mirrors[(worker + attempt) % self.mirrors.len()];
+                match self.fetch_range(mirror, &mut file, start, end) {
+                    Ok(()) => Some(mirror),
+                    Err(_) if self.cancel.is_cancelled() => None,
+                    Err(error) => {
+                        attempts.push(format!("{}: {error:#}", mirror.base));
+                        None
+                    }
+                }
+            });
+            let Some(mirror) = served else {
+                self.cancel.check()?;
+                bail!(
+                    "bytes {start}-{end} failed on every mirror\n  {}",
+                    attempts.join("\n  ")
+                );
+            };
+            self.progress.observer().mirror_segment(&MirrorSegment {
+                mirror: mirror.base.clone(),
+                start,
+                end,
+            });
+        }
+    }
+
+    fn fetch_range(&self, mirror: &Mirror, file: &mut File, start: u64, end: u64) -> Result<()> {
+        let response = self
+            .client
+            .get(&mirror.url)
+            .header(RANGE, format!("bytes={start}-{}", end - 1))
+            .send()
+            .with_context(|| format!("GET {}", mirror.url))?;
+        if response.status() != StatusCode::PARTIAL_CONTENT
+            || content_range_start(&response) != Some(start)
+        {
+            bail!(
+                "HTTP {} without the requested byte range",
+                response.status()
+            );
+        }
+        file.seek(SeekFrom::Start(start))?;
+        let mut body = response.take(end - start);
+        let mut buf = [0u8; 128 * 1024];
+        let mut received = 0u64;
+        let copied = (|| -> Result<()> {
+            loop {
+                self.cancel.check()?;
+                let n = body.read(&mut buf)?;
+                if n == 0 {
+                    break;
+                }
+                file.write_all(&buf[..n])?;
+                received += n as u64;
+                self.progress.add(n as u64);
+                if let Some(limiter) = self.limiter {
+                    limiter.consume(n as u64);
+                }
+            }
+            if received != end - start {
+                bail!("range ended after {received} of {} bytes", end - start);
+            }
+            Ok(())
+        })();
+        if copied.is_err() {
+            // The range is retried elsewhere, so give these bytes back.
+            self.progress.sub(received);
+        }
+        copied
+    }
+}
+
+fn verify(partial: &Path, expect_md5: &str, cancel: &CancelToken) -> Result<()> {
+    let mut hasher = md5::Md5::new();
+    super::hash_prefix(partial, &mut hasher, cancel)?;
+    let got = hex::encode(hasher.finalize());
+    if !got.eq_ignore_ascii_case(expect_md5) {
+        bail!(
+            "MD5 mismatch after download: got {}, expected {}",
+            got,
+            expect_md5
+        );
+    }
+    Ok(())
+}
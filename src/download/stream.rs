@@ -0,0 +1,372 @@
+// Copyright (C) 2026 Chromatic
+// Licensed under the GNU AGPL v3.0. See LICENSE file for details.
+// Website: https://chromatic.hu
+
+//! Sideloading straight from a mirror. Recovery asks for blocks in any order,
+//! so the package is read in fixed pages with HTTP Range requests and the
+//! most recently used pages are kept in memory.
+//!
+//! The MD5 is computed over pages in file order as they are served. Every
+//! page is also remembered by its own digest, and a page fetched again (after
+//! eviction, or to fill the hash when the transfer ends) must match it, so
+//! the final MD5 covers exactly the bytes the device received.
+//!
+//! A package must be known to match before a validation token is requested,
+//! so [`StreamSource::open`] reads the whole object once in order and hashes
+//! it, which also pins every page's digest. Any mirror may serve a page later,
+//! but only with the bytes that were verified. A mirror ETag that is an MD5
+//! only rejects a different package early, before that pass.
+
+use std::collections::HashMap;
+use std::io::Read;
+
+use anyhow::{bail, Context, Result};
+use md5::{Digest, Md5};
+use reqwest::blocking::Client;
+use reqwest::header::{ETAG, RANGE};
+use reqwest::StatusCode;
+
+use super::{content_range_start, segmented, Mirror};
+use crate::sideload::PackageSource;
+
+const PAGE_SIZE: u64 = 1024 * 1024;
+const CACHED_PAGES: usize = 64;
+
+pub struct StreamSource {
+    client: Client,
+    mirrors: Vec<Mirror>,
+    size: u64,
+    expect_md5: String,
+    /// Page index -> (bytes, last use).
+    cache: HashMap<u64, (Vec<u8>, u64)>,
+    clock: u64,
+    hasher: Md5,
+    /// First page not yet hashed.
+    frontier: u64,
+    /// Digest of the first copy of every page fetched.
+    fetched: HashMap<u64, [u8; 16]>,
+}
+
+impl StreamSource {
+    /// Opens a stream over `mirrors`, which must honour byte ranges, once the
+    /// remote package is known to hash to `expect_md5`.
+    pub fn open(client: Client, mirrors: Vec<Mirror>, expect_md5: &str) -> Result<Self> {
+        let size = segmented::range_length(&client, &mirrors)
+            .context("No mirror serves this package with byte ranges")?;
+        if size == 0 {
+            bail!("Mirror reports an empty package");
+        }
+        if let Some(etag) = mirrors.iter().find_map(|mirror| md5_etag(&client, mirror)) {
+            if !etag.eq_ignore_ascii_case(expect_md5) {
+                bail!("Mirror package MD5 {etag} does not match the expected {expect_md5}");
+            }
+        }
+        let mut source = Self {
+            client,
+            mirrors,
+            size,
+            expect_md5: expect_md5.to_ascii_lowercase(),
+            cache: HashMap::new(),
+            clock: 0,
+            hasher: Md5::new(),
+            frontier: 0,
+            fetched: HashMap::new(),
+        };
+        source.precheck()?;
+        Ok(source)
+    }
+
+    /// Hashes the whole remote package in order, remembering each page.
+    fn precheck(&mut self) -> Result<()> {
+        tracing::info!(size = self.size, "hashing the mirror copy before streaming");
+        let mut hasher = Md5::new();
+        for index in 0..self.page_count() {
+            hasher.update(self.fetch_page(index)?);
+        }
+        let got = hex::encode(hasher.finalize());
+        if got != self.expect_md5 {
+            bail!(
+                "Mirror package MD5 {got} does not match the expected {}",
+                self.expect_md5
+            );
+        }
+        Ok(())
+    }
+
+    fn page_count(&self) -> u64 {
+        self.size.div_ceil(PAGE_SIZE)
+    }
+
+    /// Fetches a page, refusing one that differs from an earlier copy.
+    fn fetch_page(&mut self, index: u64) -> Result<Vec<u8>> {
+        let page = self.fetch_range(index)?;
+        let digest: [u8; 16] = Md5::digest(&page).into();
+        if *self.fetched.entry(index).or_insert(digest) != digest {
+            bail!("Mirror served different bytes for page {index} when it was fetched again");
+        }
+        Ok(page)
+    }
+
+    fn fetch_range(&self, index: u64) -> Result<Vec<u8>> {
+        let start = index * PAGE_SIZE;
+        let end = (start + PAGE_SIZE).min(self.size);
+        let mut errors = Vec::new();
+        for mirror in &self.mirrors {
+            match get_range(&self.client, mirror, start, end) {
+                Ok(page) => return Ok(page),
+                Err(error) => errors.push(format!("{}: {error:#}", mirror.base)),
+            }
+        }
+        bail!(
+            "bytes {start}-{end} failed on every mirror\n  {}",
+            errors.join("\n  ")
+        )
+    }
+
+    fn page(&mut self, index: u64) -> Result<&[u8]> {
+        self.clock += 1;
+        if !self.cache.contains_key(&index) {
+            let page = self.fetch_page(index)?;
+            if self.cache.len() >= CACHED_PAGES {
+                self.evict();
+            }
+            self.cache.insert(index, (page, self.clock));
+            self.advance();
+        }
+        let entry = self.cache.get_mut(&index).expect("page was just cached");
+        entry.1 = self.clock;
+        Ok(&entry.0)
+    }
+
+    fn evict(&mut self) {
+        let Some(oldest) = self
+            .cache
+            .iter()
+            .min_by_key(|(_, (_, used))| *used)
+            .map(|(index, _)| *index)
+        else {
+            return;
+        };
+        self.cache.remove(&oldest);
+    }
+
+    /// Hashes cached pages that continue the hashed prefix.
+    fn advance(&mut self) {
+        while let Some((page, _)) = self.cache.get(&self.frontier) {
+            self.hasher.update(page);
+            self.frontier += 1;
+        }
+    }
+}
+
+impl PackageSource for StreamSource {
+    fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        let end = offset + buf.len() as u64;
+        if end > self.size {
+            bail!("read past the end of the package");
+        }
+        let mut position = offset;
+        while position < end {
+            let index = position / PAGE_SIZE;
+            let within = (position % PAGE_SIZE) as usize;
+            let page = self.page(index)?;
+            let n = (page.len() - within).min((end - position) as usize);
+            let at = (position - offset) as usize;
+            buf[at..at + n].copy_from_slice(&page[within..within + n]);
+            position += n as u64;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        while self.frontier < self.page_count() {
+            let index = self.frontier;
+            let page = match self.cache.get(&index) {
+                Some((page, _)) => page.clone(),
+                None => self.fetch_page(index)?,
+            };
+            self.hasher.update(&page);
+            self.frontier += 1;
+        }
+        let got = hex::encode(std::mem::take(&mut self.hasher).finalize());
+        if got != self.expect_md5 {
+            bail!(
+                "MD5 mismatch after streaming: got {got}, expected {}",
+                self.expect_md5
+            );
+        }
+        Ok(())
+    }
+}
+
+fn get_range(client: &Client, mirror: &Mirror, start: u64, end: u64) -> Result<Vec<u8>> {
+    let response = client
+        .get(&mirror.url)
+        .header(RANGE, format!("bytes={start}-{}", end - 1))
+        .send()
+        .with_context(|| format!("GET {}", mirror.url))?;
+    if response.status() != StatusCode::PARTIAL_CONTENT
+        || content_range_start(&response) != Some(start)
+    {
+        bail!(
+            "HTTP {} without the requested byte range",
+            response.status()
+        );
+    }
+    let mut page = Vec::with_capacity((end - start) as usize);
+    response.take(end - start).read_to_end(&mut page)?;
+    if page.len() as u64 != end - start {
+        bail!("range ended after {} of {} bytes", page.len(), end - start);
+    }
+    Ok(page)
+}
+
+/// The mirror's ETag when it has the shape of an MD5 digest.
+fn md5_etag(client: &Client, mirror: &Mirror) -> Option<String> {
+    let response = client.head(&mirror.url).send().ok()?;
+    let etag = response.headers().get(ETAG)?.to_str().ok()?;
+    let etag = etag.trim_start_matches("W/").trim_matches('"');
+    (etag.len() == 32 && etag.chars().all(|c| c.is_ascii_hexdigit())).then(|| etag.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Serves byte ranges of `body` for as many requests as arrive.
+    fn serve(body: Vec<u8>, etag: Option<&'static str>) -> Mirror {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else { return };
+                let mut request = [0u8; 2048];
+                let n = stream.read(&mut request).unwrap_or(0);
+                let request = String::from_utf8_lossy(&request[..n]).to_string();
+                let range = request
+                    .lines()
+                    .find_map(|line| line.strip_prefix("range: bytes="))
+                    .or_else(|| {
+                        request
+                            .lines()
+                            .find_map(|line| line.strip_prefix("Range: bytes="))
+                    });
+                let response = match range.and_then(|range| range.split_once('-')) {
+                    Some((start, end)) => {
+                        let start: usize = start.parse().unwrap();
+                        let end: usize = end.trim().parse().unwrap();
+                        let mut head = format!(
+                            "HTTP/1.1 206 Partial Content\r\nContent-Length: {}\r\nContent-Range: bytes {start}-{end}/{}\r\nConnection: close\r\n\r\n",
+                            end + 1 - start,
+                            body.len()
+                        )
+                        .into_bytes();
+                        head.extend_from_slice(&body[start..=end]);
+                        head
+                    }
+                    None => {
+                        let etag =
+                            etag.map_or_else(String::new, |etag| format!("ETag: \"{etag}\"\r\n"));
+                        format!(
+                            "HTTP/1.1 200 OK\r\nContent-Length: 0\r\n{etag}Connection: close\r\n\r\n"
+                        )
+                        .into_bytes()
+                    }
+                };
+                let _ = stream.write_all(&response);
+            }
+        });
+        Mirror {
+            url: format!("{base}/rom.zip"),
+            base,
+        }
+    }
+
+    fn body() -> Vec<u8> {
+        (0..(PAGE_SIZE * 3 + 100))
+            .map(|index| (index % 251) as u8)
+            .collect()
+    }
+
+    #[test]
+    fn out_of_order_reads_are_verified_against_the_md5() {
+        let body = body();
+        let md5 = hex::encode(Md5::digest(&body));
+        let mut source =
+            StreamSource::open(Client::new(), vec![serve(body.clone(), None)], &md5).unwrap();
+        assert_eq!(source.size(), body.len() as u64);
+
+        // The end of the file first, as recovery reads the ZIP directory.
+        let tail = body.len() as u64 - 50;
+        let mut buf = vec![0u8; 50];
+        source.read_at(tail, &mut buf).unwrap();
+        assert_eq!(buf, body[tail as usize..]);
+        let mut buf = vec![0u8; PAGE_SIZE as usize + 10];
+        source.read_at(PAGE_SIZE - 5, &mut buf).unwrap();
+        assert_eq!(
+            buf,
+            body[(PAGE_SIZE - 5) as usize..(2 * PAGE_SIZE + 5) as usize]
+        );
+
+        source.finish().unwrap();
+    }
+
+    #[test]
+    fn mismatched_bodies_without_an_etag_are_refused_before_streaming() {
+        let error = StreamSource::open(
+            Client::new(),
+            vec![serve(body(), None)],
+            "00000000000000000000000000000000",
+        )
+        .err()
+        .unwrap();
+        assert!(format!("{error:#}").contains("does not match"));
+    }
+
+    #[test]
+    fn matching_etags_do_not_skip_the_precheck() {
+        for etag in ["x", "00000000000000000000000000000000"] {
+            let error = StreamSource::open(
+                Client::new(),
+                vec![serve(body(), Some(etag))],
+                "00000000000000000000000000000000",
+            )
+            .err()
+            .unwrap();
+            assert!(format!("{error:#}").contains("does not match"));
+        }
+    }
+
+    #[test]
+    fn a_mirror_serving_other_bytes_after_the_precheck_is_refused() {
+        let body = body();
+        let md5 = hex::encode(Md5::digest(&body));
+        let mut source =
+            StreamSource::open(Client::new(), vec![serve(body.clone(), None)], &md5).unwrap();
+        let mut changed = body;
+        changed[0] ^= 0xff;
+        source.mirrors = vec![serve(changed, None)];
+        source.cache.clear();
+
+        assert!(source.read_at(0, &mut [0u8; 16]).is_err());
+    }
+
+    #[test]
+    fn md5_shaped_etags_are_checked_before_streaming() {
+        let error = StreamSource::open(
+            Client::new(),
+            vec![serve(body(), Some("5eb63bbbe01eeed093cb22bb8f5acdc3"))],
+            "00000000000000000000000000000000",
+        )
+        .err()
+        .unwrap();
+        assert!(format!("{error:#}").contains("does not match"));
+    }
+}
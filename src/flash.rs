@@ -0,0 +1,359 @@
+// Copyright (C) 2026 Chromatic
+// Licensed under the GNU AGPL v3.0. See LICENSE file for details.
+// Website: https://chromatic.hu
+
+//! The validate → confirm wipe → sideload sequence shared by every front end.
+//!
+//! A [`FlashSession`] describes what to flash and produces a [`FlashPlan`]
+//! once the token is known. Executing the plan asks a [`Confirmer`] before
+//! any wipe and reports each [`FlashStep`], so the CLI, machine mode and GUI
+//! apply the same wipe rules. [`check_package`] is the signature check every
+//! front end runs on a package file before validating or sending it.
+
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+
+use crate::cancel::CancelToken;
+use crate::i18n::{tr, trf};
+use crate::mi::DeviceInfo;
+#[cfg(feature = "usb")]
+use crate::mi::MiClient;
+use crate::observer::Observer;
+use crate::ota::{self, SignatureReport};
+use crate::secret::Token;
+#[cfg(feature = "usb")]
+use crate::sideload::{sideload_source, PackageSource};
+use crate::validate::ValidateResult;
+
+/// Where the sideload token comes from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TokenPolicy {
+    /// Ask the validation server.
+    Validate,
+    /// Use a token obtained elsewhere; the server is not contacted.
+    Manual(Token),
+}
+
+/// Why a flash will erase user data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WipeReason {
+    /// The server marked the package with `Erase=1`.
+    Server,
+    /// The user asked for a wipe.
+    Requested,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlashStep {
+    ConfirmingWipe(WipeReason),
+    Sideloading,
+    Completed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlashOutcome {
+    Completed {
+        wiped: bool,
+    },
+    /// The wipe was declined; the device was not touched.
+    WipeDeclined,
+}
+
+/// The error a front end reports when the user declined a required wipe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WipeDeclined;
+
+impl std::fmt::Display for WipeDeclined {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&tr("error.wipe_cancelled"))
+    }
+}
+
+impl std::error::Error for WipeDeclined {}
+
+/// The front end's part of a flash: it approves wipes and may render steps.
+pub trait Confirmer {
+    fn step(&mut self, _step: FlashStep) {}
+    /// Whether the user accepts erasing their data. Errors abort the flash.
+    fn confirm_wipe(&mut self, reason: WipeReason) -> Result<bool>;
+}
+
+/// Approves every wipe, for front ends that confirmed before starting.
+pub struct PreApproved;
+
+impl Confirmer for PreApproved {
+    fn confirm_wipe(&mut self, _reason: WipeReason) -> Result<bool> {
+        Ok(true)
+    }
+}
+
+/// Verifies the OTA signature of the package at `path`. An unknown signer is
+/// refused unless `allow_unknown_signer` is set; a signer let through or not
+/// checked at all is reported to `observer`.
+pub fn check_package(
+    path: &Path,
+    allow_unknown_signer: bool,
+    observer: &dyn Observer,
+) -> Result<SignatureReport> {
+    let report = ota::verify_package(path).context(tr("error.signature"))?;
+    report.require_known_signer(allow_unknown_signer)?;
+    if !report.trusted {
+        let key = if report.checked {
+            "warning.signature_unknown"
+        } else {
+            "warning.signature_unchecked"
+        };
+        observer.message(&trf(key, &[("{signer}", &report.signer)]));
+    }
+    Ok(report)
+}
+
+/// What to flash onto which device.
+#[derive(Debug, Clone)]
+pub struct FlashSession {
+    pub identity: DeviceInfo,
+    /// MD5 of the package, as sent to the validation server.
+    pub md5: String,
+    pub token: TokenPolicy,
+    /// Wipe even when the server does not require it.
+    pub force_wipe: bool,
+}
+
+impl FlashSession {
+    /// Obtains the token and decides whether the flash wipes.
+    #[tracing::instrument(name = "plan", skip_all, fields(md5 = %self.md5))]
+    pub fn plan(
+        &self,
+        server_url: &str,
+        observer: &dyn Observer,
+        cancel: &CancelToken,
+    ) -> Result<FlashPlan> {
+        match &self.token {
+            TokenPolicy::Manual(token) => {
+                FlashPlan::new(token.clone(), None, self.force_wipe, None)
+            }
+            #[cfg(feature = "validation-net")]
+            TokenPolicy::Validate => {
+                use crate::validate;
+                let request = validate::build_request_json(&self.identity, Some(self.md5.clone()))
+                    .context(tr("error.build_validation"))?;
+                let response = validate::validate_observed(server_url, &request, observer, cancel)
+                    .context(tr("error.validation_http"))?;
+                FlashPlan::from_response(response, self.force_wipe)
+            }
+            #[cfg(not(feature = "validation-net"))]
+            TokenPolicy::Validate => {
+                let _ = (server_url, observer, cancel);
+                bail!("Validation needs the validation-net feature")
+            }
+        }
+    }
+}
+
+/// A validated flash, ready to run against a device.
+#[derive(Debug, Clone)]
+pub struct FlashPlan {
+    token: Token,
+    wipe: Option<WipeReason>,
+    /// The server's answer, absent for a manual token.
+    pub response: Option<ValidateResult>,
+}
+
+impl FlashPlan {
+    fn new(
+        token: Token,
+        server_erase: Option<bool>,
+        force_wipe: bool,
+        response: Option<ValidateResult>,
+    ) -> Result<Self> {
+        if token.is_empty() {
+            bail!("{}", tr("error.no_token"));
+        }
+        let wipe = if server_erase == Some(true) {
+            Some(WipeReason::Server)
+        } else {
+            force_wipe.then_some(WipeReason::Requested)
+        };
+        tracing::info!(?wipe, "flash planned");
+        Ok(Self {
+            token,
+            wipe,
+            response,
+        })
+    }
+
+    /// Plans from a server answer obtained by any means.
+    pub fn from_response(response: ValidateResult, force_wipe: bool) -> Result<Self> {
+        let token = response.validate_token.clone().unwrap_or_default();
+        let erase = response.pkgrom_erase == Some(1);
+        Self::new(token, Some(erase), force_wipe, Some(response))
+    }
+
+    /// Plans from an answer recorded earlier, such as an
+    /// [`Authorization`](crate::authorization::Authorization).
+    pub fn authorized(token: Token, server_erase: bool, force_wipe: bool) -> Result<Self> {
+        Self::new(token, Some(server_erase), force_wipe, None)
+    }
+
+    pub fn token(&self) -> &Token {
+        &self.token
+    }
+
+    /// Why the flash erases user data, if it does.
+    pub fn wipe(&self) -> Option<WipeReason> {
+        self.wipe
+    }
+
+    /// Asks for any wipe, then streams `source` to the device.
+    #[cfg(feature = "usb")]
+    #[tracing::instrument(name = "flash", skip_all, fields(wipe = ?self.wipe))]
+    pub fn execute(
+        &self,
+        client: &mut MiClient,
+        source: &mut dyn PackageSource,
+        chunk_size: usize,
+        confirmer: &mut dyn Confirmer,
+        observer: &dyn Observer,
+        cancel: &CancelToken,
+    ) -> Result<FlashOutcome> {
+        if !self.approve(confirmer)? {
+            tracing::info!("wipe declined");
+            return Ok(FlashOutcome::WipeDeclined);
+        }
+        cancel.check()?;
+        confirmer.step(FlashStep::Sideloading);
+        sideload_source(
+            client,
+            source,
+            chunk_size,
+            &self.token,
+            self.wipe.is_some(),
+            observer,
+            cancel,
+        )
+        .context(tr("error.sideload"))?;
+        confirmer.step(FlashStep::Completed);
+        Ok(FlashOutcome::Completed {
+            wiped: self.wipe.is_some(),
+        })
+    }
+
+    /// Asks `confirmer` about any wipe; true when the flash may proceed.
+    pub fn approve(&self, confirmer: &mut dyn Confirmer) -> Result<bool> {
+        let Some(reason) = self.wipe else {
+            return Ok(true);
+        };
+        confirmer.step(FlashStep::ConfirmingWipe(reason));
+        confirmer.confirm_wipe(reason)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct Recorder {
+        answer: bool,
+        steps: Vec<FlashStep>,
+    }
+
+    impl Confirmer for Recorder {
+        fn step(&mut self, step: FlashStep) {
+            self.steps.push(step);
+        }
+
+        fn confirm_wipe(&mut self, _reason: WipeReason) -> Result<bool> {
+            Ok(self.answer)
+        }
+    }
+
+    #[derive(Default)]
+    struct Messages(std::sync::Mutex<Vec<String>>);
+
+    impl Observer for Messages {
+        fn message(&self, message: &str) {
+            self.0.lock().unwrap().push(message.to_string());
+        }
+    }
+
+    #[test]
+    fn packages_are_checked_before_validation() {
+        let directory = tempfile::tempdir().unwrap();
+        let unsigned = directory.path().join("unsigned.zip");
+        std::fs::write(&unsigned, b"not an OTA package").unwrap();
+        assert!(check_package(&unsigned, true, &Messages::default()).is_err());
+
+        let signed = directory.path().join("signed.zip");
+        std::fs::write(&signed, include_bytes!("ota/testdata/signed-ota.zip")).unwrap();
+        let messages = Messages::default();
+        let report = check_package(&signed, false, &messages).unwrap();
+        assert!(!report.trusted);
+        assert_eq!(messages.0.into_inner().unwrap().len(), 1);
+    }
+
+    fn session(token: TokenPolicy, force_wipe: bool) -> FlashSession {
+        FlashSession {
+            identity: DeviceInfo::default(),
+            md5: "0".repeat(32),
+            token,
+            force_wipe,
+        }
+    }
+
+    #[test]
+    fn server_erase_and_requested_wipes_both_need_confirmation() {
+        let response = ValidateResult {
+            validate_token: Some("token".into()),
+            pkgrom_erase: Some(1),
+            ..ValidateResult::default()
+        };
+        let plan = FlashPlan::from_response(response, false).unwrap();
+        assert_eq!(plan.wipe(), Some(WipeReason::Server));
+
+        let manual = session(TokenPolicy::Manual("token".into()), true)
+            .plan(
+                "http://127.0.0.1:9",
+                &crate::observer::Silent,
+                &CancelToken::new(),
+            )
+            .unwrap();
+        assert_eq!(manual.wipe(), Some(WipeReason::Requested));
+        assert!(manual.response.is_none());
+
+        let mut declined = Recorder::default();
+        assert!(!manual.approve(&mut declined).unwrap());
+        assert_eq!(
+            declined.steps,
+            [FlashStep::ConfirmingWipe(WipeReason::Requested)]
+        );
+    }
+
+    #[test]
+    fn plans_without_a_wipe_skip_the_confirmer() {
+        let response = ValidateResult {
+            validate_token: Some("token".into()),
+            pkgrom_erase: Some(0),
+            ..ValidateResult::default()
+        };
+        let plan = FlashPlan::from_response(response, false).unwrap();
+        assert_eq!(plan.wipe(), None);
+        let mut confirmer = Recorder::default();
+        assert!(plan.approve(&mut confirmer).unwrap());
+        assert!(confirmer.steps.is_empty());
+    }
+
+    #[test]
+    fn a_missing_token_cannot_be_planned() {
+        assert!(FlashPlan::from_response(ValidateResult::default(), false).is_err());
+        assert!(session(TokenPolicy::Manual(Token::default()), false)
+            .plan(
+                "http://127.0.0.1:9",
+                &crate::observer::Silent,
+                &CancelToken::new()
+            )
+            .is_err());
+    }
+}
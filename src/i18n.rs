@@ -16,9 +16,15 @@ macro_rules! define_languages {
             pub fn from_environment() -> Self {
                 let requested = std::env::var("SENSITIVITY_LANG")
                     .ok().or_else(|| std::env::var("LC_ALL").ok())
-                    .or_else(|| std::env::var("LANG").ok()).unwrap_or_default().to_ascii_lowercase();
-                $(if requested.starts_with($prefix) { return Self::$variant; })+
-                Self::English
+                    .or_else(|| std::env::var("LANG").ok()).unwrap_or_default();
+                Self::from_code(&requested).unwrap_or(Self::English)
+            }
+
+            /// Matches a language tag or locale such as `pt-BR` or `hu_HU.UTF-8`.
+            pub fn from_code(requested: &str) -> Option<Self> {
+                let requested = requested.to_ascii_lowercase().replace('_', "-");
+                $(if requested.starts_with($prefix) { return Some(Self::$variant); })+
+                None
             }
 
             pub fn code(self) -> &'static str {
@@ -74,8 +80,18 @@ define_languages!(
     (Norwegian, "nb", "nb"),
 );
 
+static SELECTED: OnceLock<Language> = OnceLock::new();
+
+/// Overrides the environment for the rest of the process; the first call wins.
+pub fn set_language(language: Language) {
+    let _ = SELECTED.set(language);
+}
+
 pub fn language() -> Language {
-    Language::from_environment()
+    SELECTED
+        .get()
+        .copied()
+        .unwrap_or_else(Language::from_environment)
 }
 
 pub fn tr(key: &str) -> String {
@@ -5,14 +5,35 @@
 //! Core implementation for the Sensitivity Xiaomi Recovery tool.
 //!
 //! The library contains USB transport, ADB framing, Xiaomi recovery commands,
-//! validation, download, and sideload behavior. User interaction and argument
-//! parsing live in the `sensitivity` binary.
+//! package verification, validation, download, and sideload behavior. User
+//! interaction and argument parsing live in the `sensitivity` binary.
+//!
+//! Cargo features keep embedders from paying for what they do not use:
+//! `usb` (transport and recovery commands), `validation-net` (the validation
+//! server), `download` (mirrors and streaming) and `progress-bars`. `cli`,
+//! the default, enables them all along with the binary's own dependencies.
 
 pub mod adb;
+pub mod authorization;
+pub mod cancel;
+pub mod config;
+#[cfg(all(feature = "usb", feature = "validation-net", feature = "download"))]
+pub mod daemon;
+#[cfg(feature = "download")]
 pub mod download;
+pub mod flash;
 pub mod i18n;
+pub mod library;
+pub mod machine;
 pub mod mi;
+pub mod observer;
+pub mod ota;
+pub mod payload;
+pub mod probe;
+pub mod secret;
 pub mod sideload;
+pub mod update;
+#[cfg(feature = "usb")]
 pub mod usb;
 pub mod util;
 pub mod validate;
@@ -0,0 +1,525 @@
+// Copyright (C) 2026 Chromatic
+// Licensed under the GNU AGPL v3.0. See LICENSE file for details.
+// Website: https://chromatic.hu
+
+//! Content-addressed store of Recovery ROM packages.
+//!
+//! Packages live at `objects/<md5>.zip` under the library root, so the same
+//! ROM is kept once however it arrived. `index.json` records what each
+//! object is (codename, version, region and original file name) and is
+//! rewritten atomically. Downloads land in `incoming/` first, where an
+//! interrupted transfer can resume, and move into `objects/` only after their
+//! MD5 has been verified.
+
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Result};
+
+use crate::cancel::CancelToken;
+use crate::mi::version::RomVersion;
+use crate::observer::Observer;
+use crate::util::hash_cache::HashCache;
+use crate::util::{self, zip};
+#[cfg(all(feature = "download", feature = "validation-net"))]
+use crate::{download, mi::DeviceInfo, validate};
+
+/// Overrides the library location.
+pub const LIBRARY_ENV: &str = "SENSITIVITY_LIBRARY";
+
+/// Incoming files untouched for this long are treated as abandoned. Anything
+/// newer may belong to a download that is running or will be resumed.
+const STALE_INCOMING: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct LibraryEntry {
+    pub md5: String,
+    pub file_name: String,
+    pub size: u64,
+    pub codename: Option<String>,
+    pub version: Option<String>,
+    pub region: Option<String>,
+    /// Seconds since the Unix epoch.
+    pub added: u64,
+}
+
+/// What is known about a package before it enters the library.
+#[derive(Debug, Clone, Default)]
+pub struct PackageMetadata {
+    pub file_name: String,
+    pub codename: Option<String>,
+    pub version: Option<RomVersion>,
+}
+
+impl PackageMetadata {
+    /// Reads the codename from the OTA metadata and the version from the
+    /// file name, when present.
+    pub fn from_package(path: &Path) -> Self {
+        let file_name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let codename = File::open(path)
+            .ok()
+            .and_then(|mut file| {
+                zip::read_stored(&mut file, "META-INF/com/android/metadata")
+                    .ok()
+                    .flatten()
+            })
+            .and_then(|metadata| {
+                String::from_utf8_lossy(&metadata).lines().find_map(|line| {
+                    line.strip_prefix("pre-device=")
+                        .map(|device| device.split(',').next().unwrap_or(device).to_string())
+                })
+            });
+        Self {
+            version: RomVersion::from_package_name(&file_name),
+            file_name,
+            codename,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize)]
+pub struct GcReport {
+    pub removed_files: usize,
+    pub removed_entries: usize,
+    pub freed_bytes: u64,
+}
+
+pub struct Library {
+    root: PathBuf,
+    hashes: HashCache,
+}
+
+impl Library {
+    /// A library at `root` that shares the per-user hash cache, so a stored
+    /// object verified here is not hashed again when it is flashed.
+    pub fn at(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            hashes: HashCache::open_default(),
+        }
+    }
+
+    /// Uses `hashes` instead of the per-user hash cache.
+    pub fn with_hash_cache(mut self, hashes: HashCache) -> Self {
+        self.hashes = hashes;
+        self
+    }
+
+    /// The library named by `SENSITIVITY_LIBRARY`, or `roms` in the data dir.
+    pub fn open_default() -> Result<Self> {
+        if let Some(root) = std::env::var_os(LIBRARY_ENV) {
+            return Ok(Self::at(root));
+        }
+        let data = util::dirs::data_dir()
+            .context("No per-user data directory; set SENSITIVITY_LIBRARY")?;
+        Ok(Self::at(data.join("roms")))
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Where downloads are written before they are verified and adopted.
+    pub fn incoming_dir(&self) -> PathBuf {
+        self.root.join("incoming")
+    }
+
+    fn objects_dir(&self) -> PathBuf {
+        self.root.join("objects")
+    }
+
+    fn index_path(&self) -> PathBuf {
+        self.root.join("index.json")
+    }
+
+    pub fn object_path(&self, md5: &str) -> PathBuf {
+        self.objects_dir()
+            .join(format!("{}.zip", md5.to_ascii_lowercase()))
+    }
+
+    pub fn entries(&self) -> Result<Vec<LibraryEntry>> {
+        match fs::read(self.index_path()) {
+            Ok(data) => serde_json::from_slice(&data)
+                .with_context(|| format!("Reading {}", self.index_path().display())),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(error) => {
+                Err(error).with_context(|| format!("Reading {}", self.index_path().display()))
+            }
+        }
+    }
+
+    fn save(&self, entries: &[LibraryEntry]) -> Result<()> {
+        fs::create_dir_all(&self.root)
+            .with_context(|| format!("Creating {}", self.root.display()))?;
+        let temporary = self.root.join(".index.json.tmp");
+        fs::write(&temporary, serde_json::to_vec_pretty(entries)?)
+            .with_context(|| format!("Writing {}", temporary.display()))?;
+        fs::rename(&temporary, self.index_path())
+            .with_context(|| format!("Writing {}", self.index_path().display()))
+    }
+
+    /// Finds one entry by full MD5 or an unambiguous prefix of at least 8 digits.
+    pub fn find(&self, md5: &str) -> Result<Option<LibraryEntry>> {
+        let md5 = md5.to_ascii_lowercase();
+        if md5.len() < 8 || !md5.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Ok(None);
+        }
+        let mut matches = self
+            .entries()?
+            .into_iter()
+            .filter(|entry| entry.md5.starts_with(&md5));
+        let found = matches.next();
+        if found.is_some() && matches.next().is_some() {
+            bail!("MD5 prefix {md5} matches more than one library entry");
+        }
+        Ok(found)
+    }
+
+    /// Returns the stored copy of `md5` after checking its size and hash, so
+    /// a damaged object is never flashed. A damaged object is removed.
+    pub fn verified_path(
+        &self,
+        md5: &str,
+        observer: &dyn Observer,
+        cancel: &CancelToken,
+    ) -> Result<Option<PathBuf>> {
+        let Some(entry) = self.find(md5)? else {
+            return Ok(None);
+        };
+        let path = self.object_path(&entry.md5);
+        let intact = fs::metadata(&path).is_ok_and(|meta| meta.len() == entry.size)
+            && match self.hashes.md5(&path, observer, cancel) {
+                Ok(hash) => hash == entry.md5,
+                Err(error) if crate::cancel::is_cancelled(&error) => return Err(error),
+                Err(_) => false,
+            };
+        if !intact {
+            let _ = fs::remove_file(&path);
+            return Ok(None);
+        }
+        Ok(Some(path))
+    }
+
+    /// Asks `server_url` for the LatestRom offered to `identity` and returns
+    /// its library entry, downloading it from the fastest mirrors first when
+    /// no intact copy is stored.
+    #[cfg(all(feature = "download", feature = "validation-net"))]
+    pub fn fetch_latest(
+        &self,
+        identity: &DeviceInfo,
+        server_url: &str,
+        observer: &dyn Observer,
+        cancel: &CancelToken,
+    ) -> Result<LibraryEntry> {
+        let request = validate::build_request_json(identity, None)?;
+        let response = validate::validate_observed(server_url, &request, observer, cancel)?;
+        let json = response
+            .full_json
+            .context("Server response has no LatestRom")?;
+        let (latest, mirrors) = download::parse_latest_from_json(&json)?;
+        if self.verified_path(&latest.md5, observer, cancel)?.is_some() {
+            return self
+                .find(&latest.md5)?
+                .context("Library entry vanished while it was checked");
+        }
+        let mirrors = download::mirror_urls(&mirrors, &latest.filename);
+        let client = download::http_client()?;
+        let ranked: Vec<_> = download::rank_mirrors(&client, &mirrors)
+            .into_iter()
+            .map(|probe| probe.mirror)
+            .collect();
+        let path = download::download_from_mirrors(
+            &client,
+            &ranked,
+            &self.incoming_dir(),
+            &latest.md5,
+            observer,
+            cancel,
+        )?;
+        let metadata = PackageMetadata {
+            file_name: download::package_file_name(&latest.filename).to_string(),
+            codename: identity.device.split('_').next().map(str::to_string),
+            version: latest.version,
+        };
+        self.adopt(&path, &latest.md5, metadata)
+    }
+
+    /// Copies an existing package into the library.
+    pub fn import(
+        &self,
+        path: &Path,
+        metadata: PackageMetadata,
+        observer: &dyn Observer,
+        cancel: &CancelToken,
+    ) -> Result<LibraryEntry> {
+        let md5 = self
+            .hashes
+            .md5(path, observer, cancel)
+            .with_context(|| format!("Hashing {}", path.display()))?;
+        if let Some(entry) = self.find(&md5)? {
+            if self.object_path(&md5).exists() {
+                return Ok(entry);
+            }
+        }
+        fs::create_dir_all(self.incoming_dir())?;
+        let staged = self.incoming_dir().join(format!(".{md5}.import"));
+        fs::copy(path, &staged).with_context(|| format!("Copying {}", path.display()))?;
+        // Hash the copy too, so a file that changed while copying is refused.
+        if util::md5::md5_file(&staged)? != md5 {
+            let _ = fs::remove_file(&staged);
+            bail!("{} changed while it was imported", path.display());
+        }
+        self.adopt(&staged, &md5, metadata)
+    }
+
+    /// Moves a file whose MD5 is already verified into the library.
+    pub fn adopt(&self, path: &Path, md5: &str, metadata: PackageMetadata) -> Result<LibraryEntry> {
+        let md5 = md5.to_ascii_lowercase();
+        fs::create_dir_all(self.objects_dir())?;
+        let object = self.object_path(&md5);
+        let size = fs::metadata(path)?.len();
+        fs::rename(path, &object)
+            .with_context(|| format!("Moving {} into the library", path.display()))?;
+        self.record(&md5, size, metadata)
+    }
+
+    /// Adds a hard link to a verified file that stays where it is. Returns
+    /// `None` when the file is on another filesystem.
+    pub fn link(
+        &self,
+        path: &Path,
+        md5: &str,
+        metadata: PackageMetadata,
+    ) -> Result<Option<LibraryEntry>> {
+        let md5 = md5.to_ascii_lowercase();
+        fs::create_dir_all(self.objects_dir())?;
+        let object = self.object_path(&md5);
+        if !object.exists() && fs::hard_link(path, &object).is_err() {
+            return Ok(None);
+        }
+        let size = fs::metadata(&object)?.len();
+        self.record(&md5, size, metadata).map(Some)
+    }
+
+    fn record(&self, md5: &str, size: u64, metadata: PackageMetadata) -> Result<LibraryEntry> {
+        let entry = LibraryEntry {
+            md5: md5.to_string(),
+            file_name: metadata.file_name,
+            size,
+            codename: metadata.codename,
+            region: metadata
+                .version
+                .as_ref()
+                .map(|version| version.region().to_string()),
+            version: metadata.version.map(|version| version.to_string()),
+            added: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_secs()),
+        };
+        let mut entries = self.entries()?;
+        entries.retain(|existing| existing.md5 != entry.md5);
+        entries.push(entry.clone());
+        self.save(&entries)?;
+        Ok(entry)
+    }
+
+    /// Removes one package and its index entry.
+    pub fn remove(&self, md5: &str) -> Result<LibraryEntry> {
+        let Some(entry) = self.find(md5)? else {
+            bail!("No library entry matches {md5}");
+        };
+        let mut entries = self.entries()?;
+        entries.retain(|existing| existing.md5 != entry.md5);
+        self.save(&entries)?;
+        match fs::remove_file(self.object_path(&entry.md5)) {
+            Err(error) if error.kind() != std::io::ErrorKind::NotFound => {
+                return Err(error).context("Removing the stored package");
+            }
+            _ => {}
+        }
+        Ok(entry)
+    }
+
+    /// Drops index entries without a package, packages without an entry, and
+    /// incoming files not written to for [`STALE_INCOMING`].
+    pub fn gc(&self) -> Result<GcReport> {
+        let mut report = GcReport::default();
+        let mut entries = self.entries()?;
+        let before = entries.len();
+        entries.retain(|entry| self.object_path(&entry.md5).exists());
+        report.removed_entries = before - entries.len();
+        if report.removed_entries > 0 {
+            self.save(&entries)?;
+        }
+        let known: Vec<PathBuf> = entries
+            .iter()
+            .map(|entry| self.object_path(&entry.md5))
+            .collect();
+        for (dir, keep_fresh) in [(self.objects_dir(), false), (self.incoming_dir(), true)] {
+            let Ok(listing) = fs::read_dir(&dir) else {
+                continue;
+            };
+            for item in listing.flatten() {
+                let path = item.path();
+                if !path.is_file() || known.contains(&path) {
+                    continue;
+                }
+                let Ok(meta) = item.metadata() else {
+                    continue;
+                };
+                if keep_fresh && !is_stale(&meta) {
+                    continue;
+                }
+                let size = meta.len();
+                if fs::remove_file(&path).is_ok() {
+                    report.removed_files += 1;
+                    report.freed_bytes += size;
+                }
+            }
+        }
+        Ok(report)
+    }
+}
+
+/// Whether an incoming file was last written longer than [`STALE_INCOMING`]
+/// ago. A file with a future or unknown time is kept.
+fn is_stale(meta: &fs::Metadata) -> bool {
+    meta.modified()
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+        .is_some_and(|age| age > STALE_INCOMING)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::observer::Silent;
+
+    /// A library whose hash cache stays inside `root`.
+    fn library(root: impl Into<PathBuf>) -> Library {
+        let root = root.into();
+        let hashes = HashCache::at(root.join("hash-cache.json"));
+        Library::at(root).with_hash_cache(hashes)
+    }
+
+    fn package(dir: &Path, name: &str, body: &[u8]) -> PathBuf {
+        let path = dir.join(name);
+        fs::write(&path, body).unwrap();
+        path
+    }
+
+    #[test]
+    fn imported_packages_are_stored_once_by_md5() {
+        let work = tempfile::tempdir().unwrap();
+        let library = library(work.path().join("library"));
+        let rom = package(
+            work.path(),
+            "miui_GARNETINGlobal_OS2.0.202.0.VNRINXM_5eb63bbb_15.0.zip",
+            b"hello world",
+        );
+
+        let entry = library
+            .import(
+                &rom,
+                PackageMetadata::from_package(&rom),
+                &Silent,
+                &CancelToken::new(),
+            )
+            .unwrap();
+        library
+            .import(
+                &rom,
+                PackageMetadata::from_package(&rom),
+                &Silent,
+                &CancelToken::new(),
+            )
+            .unwrap();
+
+        assert_eq!(entry.md5, "5eb63bbbe01eeed093cb22bb8f5acdc3");
+        assert_eq!(entry.version.as_deref(), Some("OS2.0.202.0.VNRINXM"));
+        assert_eq!(entry.region.as_deref(), Some("IN"));
+        assert_eq!(library.entries().unwrap().len(), 1);
+        assert!(rom.exists());
+        let stored = library
+            .verified_path("5eb63bbb", &Silent, &CancelToken::new())
+            .unwrap()
+            .unwrap();
+        assert_eq!(fs::read(stored).unwrap(), b"hello world");
+    }
+
+    #[test]
+    fn damaged_objects_are_not_returned() {
+        let work = tempfile::tempdir().unwrap();
+        let library = library(work.path());
+        let rom = package(work.path(), "rom.zip", b"hello world");
+        let entry = library
+            .import(
+                &rom,
+                PackageMetadata::from_package(&rom),
+                &Silent,
+                &CancelToken::new(),
+            )
+            .unwrap();
+        fs::write(library.object_path(&entry.md5), b"hello there").unwrap();
+
+        assert_eq!(
+            library
+                .verified_path(&entry.md5, &Silent, &CancelToken::new())
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn gc_and_remove_keep_index_and_objects_consistent() {
+        let work = tempfile::tempdir().unwrap();
+        let library = library(work.path().join("library"));
+        let first = package(work.path(), "first.zip", b"first");
+        let second = package(work.path(), "second.zip", b"second");
+        let first = library
+            .import(
+                &first,
+                PackageMetadata::from_package(&first),
+                &Silent,
+                &CancelToken::new(),
+            )
+            .unwrap();
+        let second = library
+            .import(
+                &second,
+                PackageMetadata::from_package(&second),
+                &Silent,
+                &CancelToken::new(),
+            )
+            .unwrap();
+        let stale = library.incoming_dir().join(".stale.zip.part");
+        fs::write(&stale, b"partial").unwrap();
+        File::options()
+            .write(true)
+            .open(&stale)
+            .unwrap()
+            .set_modified(SystemTime::now() - STALE_INCOMING * 2)
+            .unwrap();
+        let resuming = library.incoming_dir().join(".rom.zip.part");
+        fs::write(&resuming, b"in progress").unwrap();
+        fs::write(resuming.with_extension("part.json"), b"{}").unwrap();
+        fs::remove_file(library.object_path(&second.md5)).unwrap();
+
+        let report = library.gc().unwrap();
+        assert_eq!(report.removed_entries, 1);
+        assert_eq!(report.removed_files, 1);
+        assert!(!stale.exists());
+        assert!(resuming.exists());
+        assert!(resuming.with_extension("part.json").exists());
+        assert_eq!(library.entries().unwrap(), std::slice::from_ref(&first));
+
+        library.remove(&first.md5).unwrap();
+        assert!(library.entries().unwrap().is_empty());
+        assert!(!library.object_path(&first.md5).exists());
+        assert!(library.remove(&first.md5).is_err());
+    }
+}
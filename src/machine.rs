@@ -0,0 +1,295 @@
+// Copyright (C) 2026 Chromatic
+// Licensed under the GNU AGPL v3.0. See LICENSE file for details.
+// Website: https://chromatic.hu
+
+//! The versioned `--machine` protocol: JSON events on stdout and JSON
+//! control commands on stdin. docs/machine-protocol.schema.json describes
+//! both directions.
+
+use std::io::BufRead;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+use crate::cancel::{CancelToken, Cancelled};
+use crate::flash::WipeDeclined;
+use crate::i18n::trf;
+
+/// Bumped when an existing event or command changes incompatibly. New
+/// events and fields are added without a bump.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Commands a supervisor may send, one JSON object per line.
+pub const CONTROLS: [&str; 4] = ["cancel", "approve", "decline", "ping"];
+
+/// Adds the protocol version and a millisecond Unix timestamp to an event.
+pub fn stamp(mut event: Value) -> Value {
+    if let Value::Object(fields) = &mut event {
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis() as u64);
+        fields.insert("v".into(), PROTOCOL_VERSION.into());
+        fields.insert("ts".into(), ts.into());
+    }
+    event
+}
+
+/// The `key`, `params` and translated `message` fields of a message event.
+/// Placeholders are passed as for [`trf`], braces included.
+pub fn message(key: &str, params: &[(&str, &str)]) -> Map<String, Value> {
+    let mut fields = Map::new();
+    fields.insert("key".into(), key.into());
+    fields.insert(
+        "params".into(),
+        params
+            .iter()
+            .map(|(name, value)| {
+                let name = name.trim_start_matches('{').trim_end_matches('}');
+                (name.to_string(), Value::from(*value))
+            })
+            .collect::<Map<_, _>>()
+            .into(),
+    );
+    fields.insert("message".into(), trf(key, params).into());
+    fields
+}
+
+/// Stable classification of a failure for the `error` event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    Cancelled,
+    DeadlineExceeded,
+    WipeDeclined,
+    Failed,
+}
+
+impl ErrorCode {
+    pub fn of(error: &anyhow::Error) -> Self {
+        for cause in error.chain() {
+            match cause.downcast_ref::<Cancelled>() {
+                Some(Cancelled::Requested) => return Self::Cancelled,
+                Some(Cancelled::DeadlineExceeded) => return Self::DeadlineExceeded,
+                None => {}
+            }
+            if cause.is::<WipeDeclined>() {
+                return Self::WipeDeclined;
+            }
+        }
+        Self::Failed
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+enum Control {
+    Cancel,
+    Approve,
+    Decline,
+    Ping {
+        #[serde(default)]
+        id: Value,
+    },
+}
+
+#[derive(Debug, Default)]
+struct Confirmation {
+    closed: bool,
+    pending: bool,
+    decision: Option<bool>,
+}
+
+/// A yes/no answer a supervisor gives while an operation waits. Answers are
+/// taken only while a confirmation is pending, so a stray command cannot
+/// authorise a later wipe.
+#[derive(Debug, Clone, Default)]
+pub struct Approval {
+    state: Arc<(Mutex<Confirmation>, Condvar)>,
+}
+
+impl Approval {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts accepting an answer.
+    pub fn request(&self) {
+        let mut state = self.lock();
+        state.pending = true;
+        state.decision = None;
+    }
+
+    /// Answers the pending confirmation; false when none is pending.
+    pub fn answer(&self, approve: bool) -> bool {
+        let mut state = self.lock();
+        if !state.pending {
+            return false;
+        }
+        state.decision = Some(approve);
+        self.state.1.notify_all();
+        true
+    }
+
+    /// Marks the answering side as gone.
+    pub fn close(&self) {
+        self.lock().closed = true;
+        self.state.1.notify_all();
+    }
+
+    /// Whether an answer can still arrive.
+    pub fn is_open(&self) -> bool {
+        !self.lock().closed
+    }
+
+    /// Waits up to `timeout` for the answer to a pending confirmation.
+    pub fn wait(&self, timeout: Duration) -> Option<bool> {
+        let state = self.lock();
+        let (mut state, _) = self
+            .state
+            .1
+            .wait_timeout_while(state, timeout, |state| {
+                state.decision.is_none() && !state.closed
+            })
+            .unwrap_or_else(|error| error.into_inner());
+        let decision = state.decision.take();
+        if decision.is_some() {
+            state.pending = false;
+        }
+        decision
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Confirmation> {
+        self.state
+            .0
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+    }
+}
+
+/// Reads control commands on a background thread: `cancel` cancels the
+/// session and `approve` or `decline` answer its [`Approval`].
+pub struct ControlChannel {
+    approval: Approval,
+}
+
+impl ControlChannel {
+    /// Starts reading `input`. `emit` writes replies and complaints about
+    /// malformed commands as events.
+    pub fn spawn(
+        input: impl BufRead + Send + 'static,
+        cancel: CancelToken,
+        emit: fn(Value),
+    ) -> Self {
+        let approval = Approval::new();
+        let shared = approval.clone();
+        std::thread::spawn(move || {
+            for line in input.lines() {
+                let Ok(line) = line else { break };
+                if line.trim().is_empty() {
+                    continue;
+                }
+                match serde_json::from_str::<Control>(&line) {
+                    Ok(Control::Cancel) => cancel.cancel(),
+                    Ok(Control::Ping { id }) => emit(json!({ "event": "pong", "id": id })),
+                    Ok(control) => {
+                        if !shared.answer(matches!(control, Control::Approve)) {
+                            emit(json!({
+                                "event": "control_error",
+                                "code": "nothing_to_confirm",
+                                "command": line,
+                            }));
+                        }
+                    }
+                    Err(error) => emit(json!({
+                        "event": "control_error",
+                        "code": "invalid_command",
+                        "command": line,
+                        "message": error.to_string(),
+                    })),
+                }
+            }
+            shared.close();
+        });
+        Self { approval }
+    }
+
+    pub fn approval(&self) -> &Approval {
+        &self.approval
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Instant;
+
+    static REJECTED: AtomicUsize = AtomicUsize::new(0);
+
+    fn count_rejections(event: Value) {
+        if event["event"] == "control_error" {
+            REJECTED.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn wait_until(condition: impl Fn() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !condition() && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert!(condition());
+    }
+
+    #[test]
+    fn events_carry_version_timestamp_and_message_key() {
+        let mut event = json!({ "event": "status" });
+        event
+            .as_object_mut()
+            .unwrap()
+            .extend(message("status.downloaded", &[("{path}", "/tmp/rom.zip")]));
+        let event = stamp(event);
+        assert_eq!(event["v"], PROTOCOL_VERSION);
+        assert!(event["ts"].as_u64().unwrap() > 0);
+        assert_eq!(event["key"], "status.downloaded");
+        assert_eq!(event["params"]["path"], "/tmp/rom.zip");
+    }
+
+    #[test]
+    fn error_codes_survive_context() {
+        let cancelled = anyhow::Error::new(Cancelled::Requested).context("Sideload");
+        assert_eq!(ErrorCode::of(&cancelled), ErrorCode::Cancelled);
+        let declined = anyhow::Error::new(WipeDeclined).context("Flashing");
+        assert_eq!(ErrorCode::of(&declined), ErrorCode::WipeDeclined);
+        assert_eq!(ErrorCode::of(&anyhow::anyhow!("USB")), ErrorCode::Failed);
+    }
+
+    #[test]
+    fn approvals_only_answer_a_pending_confirmation() {
+        let (reader, mut supervisor) = std::io::pipe().unwrap();
+        let cancel = CancelToken::new();
+        let channel = ControlChannel::spawn(
+            std::io::BufReader::new(reader),
+            cancel.clone(),
+            count_rejections,
+        );
+
+        // Sent before anything asked, so it must not pre-approve the wipe.
+        supervisor
+            .write_all(b"{\"command\":\"approve\"}\n")
+            .unwrap();
+        wait_until(|| REJECTED.load(Ordering::SeqCst) == 1);
+        channel.approval().request();
+        assert_eq!(channel.approval().wait(Duration::from_millis(20)), None);
+
+        supervisor
+            .write_all(b"{\"command\":\"decline\"}\n")
+            .unwrap();
+        assert_eq!(channel.approval().wait(Duration::from_secs(5)), Some(false));
+
+        supervisor.write_all(b"{\"command\":\"cancel\"}\n").unwrap();
+        wait_until(|| cancel.is_cancelled());
+    }
+}
@@ -2,25 +2,43 @@
 // Licensed under the GNU AGPL v3.0. See LICENSE file for details.
 // Website: https://chromatic.hu
 
+use std::collections::{BTreeMap, HashMap};
 use std::io::{self, IsTerminal, Write};
 use std::path::{Path, PathBuf};
 use std::process::ExitCode;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::Mutex;
 use std::time::Duration;
 
 use anyhow::{bail, Context, Result};
-use clap::{ArgAction, CommandFactory, Parser, Subcommand, ValueEnum};
+use clap::parser::ValueSource;
+use clap::{
+    ArgAction, ArgMatches, Args, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum,
+};
 use clap_complete::Shell;
+use indicatif::{ProgressBar, ProgressStyle};
 
+use sensitivity::authorization::Authorization;
+use sensitivity::cancel::CancelToken;
+use sensitivity::config::{self, Config, DevicePreset, Layer};
+use sensitivity::flash::{
+    self, Confirmer, FlashOutcome, FlashPlan, FlashSession, FlashStep, TokenPolicy, WipeDeclined,
+    WipeReason,
+};
+use sensitivity::machine::{self, ControlChannel};
 use sensitivity::mi::profile::{apply_profile, RegionProfile};
-use sensitivity::mi::{DeviceInfo, MiClient};
-use sensitivity::sideload::{sideload_zip, sideload_zip_with_progress};
+use sensitivity::mi::{snapshot::IdentitySnapshot, version::RomVersion, DeviceInfo, MiClient};
+use sensitivity::observer::{Observer, Phase};
+use sensitivity::secret::{self, Redaction};
+use sensitivity::sideload::{FileSource, PackageSource};
 use sensitivity::usb::UsbTransport;
 use sensitivity::{
-    download,
-    i18n::{tr, trf},
-    util, validate,
+    daemon, download,
+    i18n::{self, tr, trf},
+    library::{Library, PackageMetadata},
+    ota, payload,
+    probe::{self, RegionProbe, Wipe},
+    update, util, validate,
 };
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
@@ -31,6 +49,20 @@ enum AdbPolicy {
     Stop,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum LogFormat {
+    Text,
+    /// One JSON object per line, with timestamp, level, fields and spans.
+    Json,
+}
+
+/// Target of user-facing text copied into the log; the console shows it
+/// already, so only `--log-file` records it.
+const UI_LOG: &str = "sensitivity::ui";
+
+/// Span of this invocation; the final error is logged inside it too.
+static SESSION: std::sync::OnceLock<tracing::Span> = std::sync::OnceLock::new();
+
 #[derive(Debug, Parser)]
 #[command(
     name = "sensitivity",
@@ -62,7 +94,7 @@ struct Cli {
     /// Validation server URL
     #[arg(
         long,
-        default_value = "https://update.miui.com/updates/miotaV3.php",
+        default_value = validate::DEFAULT_SERVER_URL,
         global = true,
         hide = true
     )]
@@ -94,9 +126,15 @@ struct Cli {
     #[arg(long, global = true, hide = true)]
     override_romzone: Option<String>,
 
-    /// Apply a region profile: global, eea, in, ru, id, tr, tw, cn
+    /// Apply a region profile: global, eea, in, ru, id, tr, tw, cn, or one
+    /// defined under [profiles.NAME] in the configuration
     #[arg(long, global = true)]
-    profile: Option<RegionProfile>,
+    profile: Option<String>,
+    #[arg(skip)]
+    profile_from_flag: bool,
+    /// Read the device identity from a file saved by `info --save` instead of USB
+    #[arg(long, global = true, value_name = "FILE")]
+    identity: Option<PathBuf>,
     /// Codename to use when building device name from profile (e.g., garnet)
     #[arg(long, global = true)]
     codename: Option<String>,
@@ -105,6 +143,23 @@ struct Cli {
     #[arg(long, global = true, hide = true)]
     md5: Option<String>,
 
+    /// Show serials and validation tokens in output instead of masking them
+    #[arg(long, global = true)]
+    reveal_secrets: bool,
+
+    /// Show more diagnostics on stderr; repeat for more detail
+    #[arg(short, long, action = ArgAction::Count, global = true, conflicts_with = "quiet")]
+    verbose: u8,
+    /// Show fewer diagnostics on stderr; -qq shows none
+    #[arg(short, long, action = ArgAction::Count, global = true)]
+    quiet: u8,
+    /// Append a timestamped debug log to FILE, e.g. to attach to a support request
+    #[arg(long, global = true, value_name = "FILE")]
+    log_file: Option<PathBuf>,
+    /// Format of diagnostics on stderr and in --log-file
+    #[arg(long, value_enum, default_value_t = LogFormat::Text, global = true)]
+    log_format: LogFormat,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -133,21 +188,73 @@ enum Commands {
         /// Emit stable machine-readable JSON
         #[arg(long)]
         json: bool,
+        /// Write an identity file for --identity instead of printing
+        #[arg(long, value_name = "FILE", conflicts_with = "json")]
+        save: Option<PathBuf>,
+        /// Mask all but the last characters of the serial in the saved file
+        #[arg(long, requires = "save")]
+        redact_serial: bool,
+    },
+    /// Check a Recovery ROM package offline, without a device
+    Inspect {
+        path: PathBuf,
+        /// Emit stable machine-readable JSON
+        #[arg(long)]
+        json: bool,
+        /// List the partitions an A/B payload.bin updates
+        #[arg(long)]
+        partitions: bool,
+        /// Check every payload.bin operation hash (reads the whole package)
+        #[arg(long)]
+        verify_payload: bool,
     },
     /// Query the server and list allowed ROMs
     ListAllowedRoms,
+    /// Ask the server what each region profile would be offered
+    ProbeRegions {
+        /// Only these profiles, comma separated (default: every built-in and configured one)
+        #[arg(long, value_delimiter = ',')]
+        profiles: Vec<String>,
+        /// Seconds to wait between validation requests
+        #[arg(long, default_value_t = 2)]
+        interval: u64,
+        /// Emit stable machine-readable JSON
+        #[arg(long)]
+        json: bool,
+    },
     /// Validate and sideload the given Recovery ROM zip
     Flash {
+        /// Package path, or the MD5 (or an 8+ digit prefix) of a library entry
         path: PathBuf,
         /// Skip confirmation prompts
         #[arg(long)]
         yes: bool,
-        /// Provide validation token manually (skip server validation)
+        /// Provide validation token manually (skip server validation; prefer --authorization)
         #[arg(long)]
         token: Option<String>,
         /// Allow/force data wipe (sets sideload-host :1). Useful if using --token without server response.
         #[arg(long, action = ArgAction::SetTrue)]
         wipe: bool,
+        /// Flash with a bundle from `authorize`, checked against the phone and package
+        #[arg(long, value_name = "BUNDLE", conflicts_with_all = ["token", "wipe"])]
+        authorization: Option<PathBuf>,
+        /// Flash a package whose signer is not a known release certificate
+        #[arg(long)]
+        allow_unknown_signer: bool,
+    },
+    /// Validate a package for a phone and save the answer for an offline bench
+    Authorize {
+        /// Package path, or the MD5 (or an 8+ digit prefix) of a library entry
+        path: PathBuf,
+        /// Bundle to write (default: PACKAGE.auth.json in the current directory)
+        #[arg(long, short, value_name = "FILE")]
+        output: Option<PathBuf>,
+        /// Wipe data on the bench even when the server does not require it
+        #[arg(long)]
+        wipe: bool,
+        /// Authorize a package whose signer is not a known release certificate
+        #[arg(long)]
+        allow_unknown_signer: bool,
     },
     /// Erase user data, then reboot
     FormatData {
@@ -157,15 +264,23 @@ enum Commands {
     },
     /// Reboot the device
     Reboot,
+    /// Check whether the server offers a newer ROM (exit 0: up to date, 100: update available)
+    CheckUpdate {
+        /// Emit stable machine-readable JSON
+        #[arg(long)]
+        json: bool,
+    },
     /// Download LatestRom reported by server
     DownloadLatest {
-        /// Directory to save the ROM into (default: current dir)
+        /// Directory to save the ROM into (default: the ROM library)
         #[arg(long)]
         output_dir: Option<PathBuf>,
+        #[command(flatten)]
+        transfer: TransferArgs,
     },
     /// Download LatestRom and flash it (validate+flash)
     FlashFromLatest {
-        /// Directory to save/download the ROM (default: current dir)
+        /// Directory to save/download the ROM (default: the ROM library)
         #[arg(long)]
         output_dir: Option<PathBuf>,
         /// Skip confirmation prompts
@@ -174,30 +289,295 @@ enum Commands {
         /// Allow/force data wipe (sets sideload-host :1). Overrides server Erase=0 when true.
         #[arg(long, action = ArgAction::SetTrue)]
         wipe: bool,
+        /// Sideload straight from a mirror without storing the package
+        #[arg(long, conflicts_with = "output_dir")]
+        stream: bool,
+        #[command(flatten)]
+        transfer: TransferArgs,
+    },
+    /// Copy existing Recovery ROM zips into the local library
+    Import {
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
     },
+    /// Manage the local ROM library
+    Library {
+        #[command(subcommand)]
+        action: LibraryAction,
+    },
+    /// Show, read or change settings and where they come from
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    /// Serve a local JSON-RPC API that keeps recovery sessions open
+    Daemon {
+        /// unix:PATH or tcp:127.0.0.1:PORT (default: a socket in the runtime directory, or TCP port 7420 on Windows)
+        #[arg(long)]
+        listen: Option<String>,
+        #[command(subcommand)]
+        action: Option<DaemonAction>,
+    },
+}
+
+impl Commands {
+    /// Whether the command only sends the device's identity somewhere, so an
+    /// `--identity` file can stand in for the phone.
+    fn needs_identity_only(&self) -> bool {
+        matches!(
+            self,
+            Self::Info { .. }
+                | Self::CheckUpdate { .. }
+                | Self::DownloadLatest { .. }
+                | Self::ListAllowedRoms
+                | Self::ProbeRegions { .. }
+                | Self::Authorize { .. }
+        )
+    }
+}
+
+#[derive(Debug, Subcommand)]
+enum ConfigAction {
+    /// List every effective setting with the layer that set it
+    Show {
+        /// Emit stable machine-readable JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print one effective setting, e.g. chunk_size or devices.SERIAL.profile
+    Get { key: String },
+    /// Write a setting to the user file
+    Set {
+        key: String,
+        value: String,
+        /// Write the system-wide file instead
+        #[arg(long, conflicts_with = "project")]
+        system: bool,
+        /// Write the nearest .sensitivity.toml, creating one here if none exists
+        #[arg(long)]
+        project: bool,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum DaemonAction {
+    /// Manage per-client access tokens
+    Token {
+        #[command(subcommand)]
+        action: TokenAction,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum TokenAction {
+    /// Create a token for a client, replacing its old one, and print it once
+    Add { name: String },
+    /// List clients that hold a token
+    List,
+    /// Delete a client's token
+    Revoke { name: String },
+}
+
+#[derive(Debug, Subcommand)]
+enum LibraryAction {
+    /// List stored packages
+    List {
+        /// Emit stable machine-readable JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Delete a stored package by MD5 or an 8+ digit prefix
+    Remove { md5: String },
+    /// Drop stale index entries, orphaned packages and abandoned downloads
+    Gc,
+}
+
+#[derive(Debug, Args)]
+struct TransferArgs {
+    /// Fetch the ROM in ranges over this many concurrent connections
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..=16))]
+    connections: u16,
+    /// Cap download bandwidth, e.g. 500K or 10M (bytes per second)
+    #[arg(long, value_parser = download::parse_rate)]
+    limit_rate: Option<u64>,
+}
+
+impl From<&TransferArgs> for download::DownloadOptions {
+    fn from(args: &TransferArgs) -> Self {
+        Self {
+            connections: args.connections.into(),
+            max_bytes_per_second: args.limit_rate,
+            ..Self::default()
+        }
+    }
 }
 
 fn main() -> ExitCode {
     let machine = std::env::args_os().any(|argument| argument == "--machine");
+    install_panic_hook(machine);
     match run() {
-        Ok(()) => ExitCode::SUCCESS,
+        Ok(code) => code,
         Err(error) => {
             if machine {
                 emit_machine_event(serde_json::json!({
                     "event": "error",
+                    "code": machine::ErrorCode::of(&error),
                     "message": format!("{error:#}")
                 }));
             }
+            let span = SESSION.get().cloned().unwrap_or_else(tracing::Span::none);
+            span.in_scope(|| tracing::error!(target: UI_LOG, "{error:#}"));
             eprintln!("{}: {error:#}", tr("error.prefix"));
             ExitCode::FAILURE
         }
     }
 }
 
-fn run() -> Result<()> {
-    let cli = Cli::parse();
+/// Reports panics as failures. Secrets reach panic messages only through
+/// their `Debug` and `Display`, so the report follows the redaction policy.
+fn install_panic_hook(machine: bool) {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |panic| {
+        if !machine {
+            default_hook(panic);
+            return;
+        }
+        let message = panic
+            .payload()
+            .downcast_ref::<&str>()
+            .map(|message| (*message).to_string())
+            .or_else(|| panic.payload().downcast_ref::<String>().cloned())
+            .unwrap_or_default();
+        let location = panic
+            .location()
+            .map(|location| format!(" at {}:{}", location.file(), location.line()))
+            .unwrap_or_default();
+        emit_machine_event(serde_json::json!({
+            "event": "error",
+            "code": machine::ErrorCode::Failed,
+            "message": format!("internal error: {message}{location}")
+        }));
+    }));
+}
+
+/// Sends diagnostics to stderr at the `-v`/`-q` level and, with
+/// `--log-file`, everything from debug up to the file. Secrets appear only
+/// through their masking `Debug` and `Display`.
+fn init_logging(cli: &Cli) -> Result<()> {
+    use tracing_subscriber::filter::{LevelFilter, Targets};
+    use tracing_subscriber::prelude::*;
+
+    let level = match i16::from(cli.verbose) - i16::from(cli.quiet) {
+        ..=-2 => LevelFilter::OFF,
+        -1 => LevelFilter::ERROR,
+        0 => LevelFilter::WARN,
+        1 => LevelFilter::INFO,
+        2 => LevelFilter::DEBUG,
+        _ => LevelFilter::TRACE,
+    };
+    let usb = if cli.debug_usb {
+        level.max(LevelFilter::DEBUG)
+    } else {
+        level
+    };
+    let console = log_layer(cli.log_format, io::stderr, false).with_filter(
+        Targets::new()
+            .with_default(level.min(LevelFilter::WARN))
+            .with_target("sensitivity", level)
+            .with_target("sensitivity::usb", usb)
+            .with_target(UI_LOG, LevelFilter::OFF),
+    );
+    let file = match &cli.log_file {
+        Some(path) => {
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .with_context(|| format!("Opening log file {}", path.display()))?;
+            let file_level = level.max(LevelFilter::DEBUG);
+            Some(
+                log_layer(cli.log_format, std::sync::Mutex::new(file), true).with_filter(
+                    Targets::new()
+                        .with_default(LevelFilter::INFO)
+                        .with_target("sensitivity", file_level)
+                        .with_target("sensitivity::usb", usb.max(LevelFilter::INFO)),
+                ),
+            )
+        }
+        None => None,
+    };
+    tracing_subscriber::registry()
+        .with(console)
+        .with(file)
+        .try_init()
+        .context("Installing the log subscriber")
+}
+
+/// A formatter writing to `writer`; files get timestamps and no colour.
+fn log_layer<S, W>(
+    format: LogFormat,
+    writer: W,
+    file: bool,
+) -> Box<dyn tracing_subscriber::Layer<S> + Send + Sync>
+where
+    S: tracing::Subscriber + for<'span> tracing_subscriber::registry::LookupSpan<'span>,
+    W: for<'writer> tracing_subscriber::fmt::MakeWriter<'writer> + Send + Sync + 'static,
+{
+    use std::io::IsTerminal;
+    use tracing_subscriber::Layer;
+
+    let layer = tracing_subscriber::fmt::layer().with_writer(writer);
+    match (format, file) {
+        (LogFormat::Json, _) => layer.json().boxed(),
+        (LogFormat::Text, true) => layer.with_ansi(false).boxed(),
+        (LogFormat::Text, false) => layer
+            .with_ansi(io::stderr().is_terminal())
+            .without_time()
+            .with_target(false)
+            .boxed(),
+    }
+}
+
+fn run() -> Result<ExitCode> {
+    let matches = Cli::command().get_matches();
+    let mut cli = Cli::from_arg_matches(&matches).unwrap_or_else(|error| error.exit());
+    let config = apply_config(&mut cli, &matches)?;
+    if cli.reveal_secrets {
+        secret::set_policy(Redaction::Reveal);
+    }
+    init_logging(&cli)?;
+    let session = SESSION.get_or_init(|| {
+        tracing::info_span!(
+            "session",
+            pid = std::process::id(),
+            command = matches.subcommand_name().unwrap_or_default()
+        )
+    });
+    let _session = session.enter();
+    tracing::info!(
+        version = env!("CARGO_PKG_VERSION"),
+        os = std::env::consts::OS,
+        arch = std::env::consts::ARCH,
+        machine = cli.machine,
+        "started"
+    );
     reset_control_file(cli.cancel_file.as_deref())?;
     reset_control_file(cli.approval_file.as_deref())?;
+    let cancel = install_cancel_handler(cli.cancel_file.as_deref())?;
+    let control = cli.machine.then(|| {
+        emit_machine_event(serde_json::json!({
+            "event": "hello",
+            "protocol": machine::PROTOCOL_VERSION,
+            "version": env!("CARGO_PKG_VERSION"),
+            "controls": machine::CONTROLS
+        }));
+        ControlChannel::spawn(
+            io::BufReader::new(io::stdin()),
+            cancel.clone(),
+            emit_machine_event,
+        )
+    });
+    let observer = CliObserver::new(cli.machine);
     if !cli.server_url.starts_with("https://") && !cli.http {
         bail!(
             "{}",
@@ -205,14 +585,14 @@ fn run() -> Result<()> {
         );
     }
     if cli.http && cli.server_url.starts_with("http://") {
-        eprintln!("{}", trf("warning.http", &[("{url}", &cli.server_url)]));
+        print_warning(&trf("warning.http", &[("{url}", &cli.server_url)]));
     }
 
     let adb_was_running = util::adb_server::is_running(std::time::Duration::from_millis(200));
-    if cli.adb_policy == AdbPolicy::Stop && adb_was_running {
+    if cli.adb_policy == AdbPolicy::Stop && adb_was_running && cli.identity.is_none() {
         util::adb_server::kill_adb_server(std::time::Duration::from_secs(2))
             .context(tr("error.stop_adb"))?;
-        eprintln!("{}", tr("warning.adb_stopped"));
+        print_warning(&tr("warning.adb_stopped"));
     }
 
     // Open USB transport
@@ -226,7 +606,7 @@ fn run() -> Result<()> {
         Commands::Completions { shell } => {
             let mut command = Cli::command();
             clap_complete::generate(*shell, &mut command, "sensitivity", &mut io::stdout());
-            return Ok(());
+            return Ok(ExitCode::SUCCESS);
         }
         Commands::Devices { json } => {
             let devices = UsbTransport::discover().context(tr("error.discover_usb"))?;
@@ -249,7 +629,7 @@ fn run() -> Result<()> {
                     print_usb_device(device);
                 }
             }
-            return Ok(());
+            return Ok(ExitCode::SUCCESS);
         }
         Commands::Doctor => {
             println!(
@@ -308,7 +688,7 @@ fn run() -> Result<()> {
                 Ok(_) => {
                     println!("{}", tr("status.recovery_ready"));
                     println!("{}", tr("status.ready_result"));
-                    return Ok(());
+                    return Ok(ExitCode::SUCCESS);
                 }
                 Err(error) => {
                     println!("{}", tr("status.recovery_unavailable"));
@@ -329,22 +709,79 @@ fn run() -> Result<()> {
                 }
             }
         }
+        Commands::Inspect {
+            path,
+            json,
+            partitions,
+            verify_payload,
+        } => {
+            inspect_package(path, *json, *partitions, *verify_payload)?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        Commands::Import { paths } => {
+            let library = Library::open_default()?;
+            for path in paths {
+                let mut metadata = PackageMetadata::from_package(path);
+                if metadata.codename.is_none() {
+                    metadata.codename.clone_from(&cli.codename);
+                }
+                let entry = library
+                    .import(path, metadata, &observer, &cancel)
+                    .with_context(|| {
+                        trf("error.import", &[("{path}", &path.display().to_string())])
+                    })?;
+                println!(
+                    "{}",
+                    trf(
+                        "status.imported",
+                        &[
+                            ("{path}", &path.display().to_string()),
+                            ("{md5}", &entry.md5)
+                        ]
+                    )
+                );
+            }
+            return Ok(ExitCode::SUCCESS);
+        }
+        Commands::Library { action } => {
+            manage_library(action)?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        Commands::Daemon { listen, action } => {
+            run_daemon(&cli, listen.as_deref(), action.as_ref(), &cancel)?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        Commands::Config { action } => return manage_config(&config, action),
         _ => {}
     }
 
-    let mut client = make_client().map_err(|error| {
-        if adb_was_running
-            && cli.adb_policy == AdbPolicy::Keep
-            && adb_may_own_interface(&error)
-        {
-            error.context(
-                "A local ADB server is running. If it owns this USB interface, retry with --adb-policy stop",
-            )
-        } else {
-            error
+    let mut device = match &cli.identity {
+        Some(path) => {
+            if !cli.command.needs_identity_only() {
+                bail!("{}", tr("error.identity_needs_device"));
+            }
+            let snapshot = IdentitySnapshot::load(path)?;
+            if snapshot.serial_redacted {
+                print_warning(&tr("warning.identity_redacted"));
+            }
+            Device::Snapshot(snapshot)
         }
-    })?;
-    let identity = IdentityOptions::from(&cli);
+        None => Device::Usb(make_client().map_err(|error| {
+            if adb_was_running
+                && cli.adb_policy == AdbPolicy::Keep
+                && adb_may_own_interface(&error)
+            {
+                error.context(
+                    "A local ADB server is running. If it owns this USB interface, retry with --adb-policy stop",
+                )
+            } else {
+                error
+            }
+        })?),
+    };
+    let mut identity = IdentityOptions::from(&cli);
+    identity.presets = config.presets();
+    identity.profiles = config.profiles()?;
 
     match cli.command {
         Commands::Completions { .. } => {
@@ -352,17 +789,36 @@ fn run() -> Result<()> {
         }
         Commands::Devices { .. } => unreachable!("devices returns before USB command dispatch"),
         Commands::Doctor => unreachable!("doctor returns before USB command dispatch"),
+        Commands::Inspect { .. } => unreachable!("inspect returns before USB command dispatch"),
+        Commands::Import { .. } => unreachable!("import returns before USB command dispatch"),
+        Commands::Library { .. } => unreachable!("library returns before USB command dispatch"),
+        Commands::Daemon { .. } => unreachable!("daemon returns before USB command dispatch"),
+        Commands::Config { .. } => unreachable!("config returns before USB command dispatch"),
         Commands::Detect => {
             println!("{}", tr("status.device_detected"));
         }
-        Commands::Info { json } => {
-            let info = client.read_all_info().context(tr("error.fetch_device"))?;
-            if json {
+        Commands::Info {
+            json,
+            save,
+            redact_serial,
+        } => {
+            let info = device.read_all_info()?;
+            if let Some(path) = save {
+                IdentitySnapshot::capture(info, redact_serial).save(&path)?;
+                report_status(
+                    cli.machine,
+                    "status.identity_saved",
+                    &[("{path}", &path.display().to_string())],
+                );
+            } else if json {
                 println!("{}", serde_json::to_string_pretty(&info)?);
             } else {
                 println!("{}", trf("label.device", &[("{value}", &info.device)]));
                 println!("{}", trf("label.version", &[("{value}", &info.version)]));
-                println!("{}", trf("label.serial", &[("{value}", &info.sn)]));
+                println!(
+                    "{}",
+                    trf("label.serial", &[("{value}", &info.sn.to_string())])
+                );
                 println!("{}", trf("label.codebase", &[("{value}", &info.codebase)]));
                 println!("{}", trf("label.branch", &[("{value}", &info.branch)]));
                 println!("{}", trf("label.language", &[("{value}", &info.language)]));
@@ -370,14 +826,39 @@ fn run() -> Result<()> {
                 println!("{}", trf("label.romzone", &[("{value}", &info.romzone)]));
             }
         }
-        Commands::DownloadLatest { output_dir } => {
-            let info = effective_device_info(
-                &identity,
-                client.read_all_info().context(tr("error.fetch_device"))?,
-            )?;
+        Commands::CheckUpdate { json } => {
+            let reported = device.read_all_info()?;
+            let installed = reported.version.clone();
+            let info = effective_device_info(&identity, reported)?;
+            let req_json =
+                validate::build_request_json(&info, None).context(tr("error.build_validation"))?;
+            let resp = validate::validate_observed(&cli.server_url, &req_json, &observer, &cancel)
+                .context(tr("error.validation_http"))?;
+            let response = resp
+                .full_json
+                .as_deref()
+                .ok_or_else(|| anyhow::anyhow!(tr("error.no_full_json")))?;
+            let check =
+                update::check_update(&installed, response).context(tr("error.check_update"))?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&check)?);
+            } else {
+                print_update_check(&check);
+            }
+            return Ok(match check.status {
+                update::UpdateStatus::UpToDate => ExitCode::SUCCESS,
+                update::UpdateStatus::UpdateAvailable => ExitCode::from(UPDATE_AVAILABLE_EXIT),
+                update::UpdateStatus::RegionChange => ExitCode::from(REGION_CHANGE_EXIT),
+            });
+        }
+        Commands::DownloadLatest {
+            output_dir,
+            transfer,
+        } => {
+            let info = effective_device_info(&identity, device.read_all_info()?)?;
             let req_json =
                 validate::build_request_json(&info, None).context(tr("error.build_validation"))?;
-            let resp = validate::validate(&cli.server_url, &req_json)
+            let resp = validate::validate_observed(&cli.server_url, &req_json, &observer, &cancel)
                 .context(tr("error.validation_http"))?;
             let json = resp
                 .full_json
@@ -385,20 +866,26 @@ fn run() -> Result<()> {
                 .ok_or_else(|| anyhow::anyhow!(tr("error.no_full_json")))?;
             let (latest, mirrors) =
                 download::parse_latest_from_json(&json).context(tr("error.parse_latest"))?;
-            let url = download::choose_url(&mirrors, &latest.filename)
-                .ok_or_else(|| anyhow::anyhow!(tr("error.no_mirror")))?;
-            let client_http = reqwest::blocking::Client::builder()
-                .user_agent("MiTunes_UserAgent_v3.0")
-                .build()?;
-            let out_dir = output_dir.unwrap_or_else(|| std::env::current_dir().unwrap());
-            let path = download::download_with_md5(&client_http, &url, &out_dir, &latest.md5)
-                .context(tr("error.download_latest"))?;
+            report_latest_version(cli.machine, &info, latest.version.as_ref());
+            show_latest_package(cli.machine, &resp);
+            let path = fetch_latest(
+                &observer,
+                &cancel,
+                &info,
+                &latest,
+                &mirrors,
+                output_dir.as_deref(),
+                &transfer,
+            )?;
             if cli.machine {
-                emit_machine_event(serde_json::json!({
-                    "event": "downloaded",
-                    "path": path,
-                    "md5_verified": true
-                }));
+                let mut fields = machine::message(
+                    "status.downloaded",
+                    &[("{path}", &path.display().to_string())],
+                );
+                fields.insert("event".into(), "downloaded".into());
+                fields.insert("path".into(), serde_json::json!(path));
+                fields.insert("md5_verified".into(), true.into());
+                emit_machine_event(fields.into());
             } else {
                 println!(
                     "{}",
@@ -413,16 +900,15 @@ fn run() -> Result<()> {
             output_dir,
             yes,
             wipe,
+            stream,
+            transfer,
         } => {
-            emit_status(cli.machine, &tr("status.reading_recovery"));
-            let info = effective_device_info(
-                &identity,
-                client.read_all_info().context(tr("error.fetch_device"))?,
-            )?;
+            emit_status(cli.machine, "status.reading_recovery", &[]);
+            let info = effective_device_info(&identity, device.read_all_info()?)?;
             // Step 1: Get LatestRom info
             let req_json =
                 validate::build_request_json(&info, None).context(tr("error.build_validation"))?;
-            let resp1 = validate::validate(&cli.server_url, &req_json)
+            let resp1 = validate::validate_observed(&cli.server_url, &req_json, &observer, &cancel)
                 .context(tr("error.validation_http"))?;
             let json = resp1
                 .full_json
@@ -430,64 +916,116 @@ fn run() -> Result<()> {
                 .ok_or_else(|| anyhow::anyhow!(tr("error.no_full_json")))?;
             let (latest, mirrors) =
                 download::parse_latest_from_json(&json).context(tr("error.parse_latest"))?;
-            let url = download::choose_url(&mirrors, &latest.filename)
-                .ok_or_else(|| anyhow::anyhow!(tr("error.no_mirror")))?;
-            // Step 2: Download
-            emit_status(cli.machine, &tr("status.downloading"));
-            let client_http = reqwest::blocking::Client::builder()
-                .user_agent("MiTunes_UserAgent_v3.0")
-                .build()?;
-            let out_dir = output_dir.unwrap_or_else(|| std::env::current_dir().unwrap());
-            let local_path = download::download_with_md5(&client_http, &url, &out_dir, &latest.md5)
-                .context(tr("error.download_latest"))?;
+            report_latest_version(cli.machine, &info, latest.version.as_ref());
+            show_latest_package(cli.machine, &resp1);
+            // Step 2: Download, or stream from the mirrors
+            let mut source: Box<dyn PackageSource> = if stream {
+                emit_status(cli.machine, "status.streaming", &[]);
+                let (http, mirrors) = ranked_mirrors(cli.machine, &latest, &mirrors)?;
+                Box::new(
+                    download::StreamSource::open(http, mirrors, &latest.md5)
+                        .context(tr("error.stream"))?,
+                )
+            } else {
+                emit_status(cli.machine, "status.downloading", &[]);
+                let local_path = fetch_latest(
+                    &observer,
+                    &cancel,
+                    &info,
+                    &latest,
+                    &mirrors,
+                    output_dir.as_deref(),
+                    &transfer,
+                )?;
+                Box::new(FileSource::open(&local_path)?)
+            };
             // Step 3: Validate for this MD5 and flash
-            let req_json2 = validate::build_request_json(&info, Some(latest.md5.clone()))
-                .context(tr("error.build_validation"))?;
-            let resp2 = validate::validate(&cli.server_url, &req_json2)
-                .context(tr("error.validation_http"))?;
-            if let Some(msg) = resp2.code_message.as_deref() {
-                println!("{}", trf("status.server_message", &[("{message}", msg)]));
-            }
-            let cancel = install_cancel_handler(cli.cancel_file.as_deref())?;
-            if (resp2.pkgrom_erase == Some(1) || wipe) && !yes {
-                confirm_data_wipe_supervised(cli.machine, cli.approval_file.as_deref(), &cancel)?;
-            }
-            let token = resp2
-                .validate_token
-                .as_deref()
-                .ok_or_else(|| anyhow::anyhow!(tr("error.missing_token")))?
-                .to_string();
-            let allow_wipe = resp2.pkgrom_erase == Some(1) || wipe;
-            emit_status(cli.machine, &tr("status.flashing"));
-            run_sideload(
-                &mut client,
-                &local_path,
+            let session = FlashSession {
+                identity: info,
+                md5: latest.md5.clone(),
+                token: TokenPolicy::Validate,
+                force_wipe: wipe,
+            };
+            let plan = session.plan(&cli.server_url, &observer, &cancel)?;
+            report_plan(&plan);
+            let confirmer = CliConfirmer {
+                machine: cli.machine,
+                yes,
+                approval_file: cli.approval_file.as_deref(),
+                control: control.as_ref(),
+                cancel: &cancel,
+            };
+            run_flash(
+                &plan,
+                device.client()?,
+                source.as_mut(),
                 cli.chunk_size,
-                &token,
-                allow_wipe,
-                &cancel,
-                cli.machine,
-            )
-            .context(tr("error.sideload"))?;
-            emit_completed(cli.machine, &tr("status.flash_completed"));
+                confirmer,
+                &observer,
+            )?;
         }
         Commands::ListAllowedRoms => {
-            let info = effective_device_info(
-                &identity,
-                client.read_all_info().context(tr("error.fetch_device"))?,
-            )?;
+            let info = effective_device_info(&identity, device.read_all_info()?)?;
             let req_json =
                 validate::build_request_json(&info, None).context(tr("error.build_validation"))?;
-            let resp = validate::validate(&cli.server_url, &req_json)
+            let resp = validate::validate_observed(&cli.server_url, &req_json, &observer, &cancel)
                 .context(tr("error.validation_http"))?;
             validate::print_allowed(&resp);
+            validate::print_packages(&resp);
+        }
+        Commands::ProbeRegions {
+            profiles,
+            interval,
+            json,
+        } => {
+            let info = device.read_all_info()?;
+            let profiles = if profiles.is_empty() {
+                RegionProfile::all(&identity.profiles)
+            } else {
+                profiles
+                    .iter()
+                    .map(|name| RegionProfile::resolve(name, &identity.profiles))
+                    .collect::<Result<_>>()?
+            };
+            let codename = identity.codename.clone().or_else(|| {
+                identity
+                    .presets
+                    .get(info.sn.reveal())
+                    .and_then(|preset| preset.codename.clone())
+            });
+            let probes = probe::probe_regions(
+                &info,
+                &profiles,
+                codename.as_deref(),
+                &cli.server_url,
+                Duration::from_secs(interval),
+                &observer,
+                &cancel,
+            )?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&probes)?);
+            } else {
+                print_probes(&probes);
+            }
         }
         Commands::Flash {
             path,
             yes,
             token,
             wipe,
+            authorization,
+            allow_unknown_signer,
         } => {
+            let authorization = authorization
+                .map(|bundle| Authorization::load(&bundle))
+                .transpose()?;
+            if authorization.is_some() && cli.md5.is_some() {
+                bail!("{}", tr("error.authorization_md5"));
+            }
+            if token.is_some() {
+                print_warning(&tr("warning.manual_token"));
+            }
+            let path = resolve_package(&observer, &cancel, path)?;
             if !path.exists() {
                 bail!(
                     "{}",
@@ -497,187 +1035,664 @@ fn run() -> Result<()> {
                     )
                 );
             }
-            emit_status(cli.machine, &tr("status.reading_recovery"));
-            let info = effective_device_info(
-                &identity,
-                client.read_all_info().context(tr("error.fetch_device"))?,
-            )?;
-            emit_status(cli.machine, &tr("status.checking_package"));
-            let computed_md5 = util::md5::md5_file(&path).context(tr("error.compute_md5"))?;
-            // An explicit one-session override is retained for protocol debugging.
-            let used_md5 = if let Some(m) = &cli.md5 {
-                m.clone()
+            emit_status(cli.machine, "status.reading_recovery", &[]);
+            let reported = device.read_all_info()?;
+            let serial = reported.sn.clone();
+            let info = match &authorization {
+                Some(authorization) => authorization.identity.clone(),
+                None => effective_device_info(&identity, reported)?,
+            };
+            emit_status(cli.machine, "status.checking_package", &[]);
+            check_package_signature(cli.machine, &path, allow_unknown_signer)?;
+            warn_if_downgrade(&info, &path);
+            let computed_md5 = util::hash_cache::md5_cached(&path, &observer, &cancel)
+                .context(tr("error.compute_md5"))?;
+            let plan = if let Some(authorization) = &authorization {
+                authorization.verify(serial.reveal(), &computed_md5)?;
+                report_authorization(cli.machine, authorization);
+                authorization.plan()?
             } else {
-                computed_md5.clone()
+                // An explicit one-session override is retained for protocol debugging.
+                let used_md5 = if let Some(m) = &cli.md5 {
+                    m.clone()
+                } else {
+                    computed_md5.clone()
+                };
+                if used_md5.len() != 32 || !used_md5.chars().all(|c| c.is_ascii_hexdigit()) {
+                    bail!("{}", tr("error.md5_length"));
+                }
+                if used_md5.to_lowercase() != computed_md5 {
+                    print_warning(&trf(
+                        "warning.md5_override",
+                        &[("{override}", &used_md5), ("{computed}", &computed_md5)],
+                    ));
+                }
+                let session = FlashSession {
+                    identity: info,
+                    md5: used_md5,
+                    token: token.map_or(TokenPolicy::Validate, |token| {
+                        TokenPolicy::Manual(token.into())
+                    }),
+                    force_wipe: wipe,
+                };
+                let plan = session.plan(&cli.server_url, &observer, &cancel)?;
+                report_plan(&plan);
+                plan
             };
-            if used_md5.len() != 32 || !used_md5.chars().all(|c| c.is_ascii_hexdigit()) {
-                bail!("{}", tr("error.md5_length"));
-            }
-            if used_md5.to_lowercase() != computed_md5 {
-                eprintln!(
+            let mut source = FileSource::open(&path)?;
+            let confirmer = CliConfirmer {
+                machine: cli.machine,
+                yes,
+                approval_file: cli.approval_file.as_deref(),
+                control: control.as_ref(),
+                cancel: &cancel,
+            };
+            run_flash(
+                &plan,
+                device.client()?,
+                &mut source,
+                cli.chunk_size,
+                confirmer,
+                &observer,
+            )?;
+        }
+        Commands::Authorize {
+            path,
+            output,
+            wipe,
+            allow_unknown_signer,
+        } => {
+            let path = resolve_package(&observer, &cancel, path)?;
+            if !path.exists() {
+                bail!(
                     "{}",
                     trf(
-                        "warning.md5_override",
-                        &[("{override}", &used_md5), ("{computed}", &computed_md5)]
+                        "error.zip_not_found",
+                        &[("{path}", &path.display().to_string())]
                     )
                 );
             }
-            let req_json = validate::build_request_json(&info, Some(used_md5.clone()))
-                .context(tr("error.build_validation"))?;
-            let mut resp = validate::ValidateResult::default();
-            // Preserve whether CLI provided a token before shadowing it
-            let cli_token_provided = token.is_some();
-            let token_string = match token {
-                Some(t) => t,
-                None => {
-                    let r = validate::validate(&cli.server_url, &req_json)
-                        .context(tr("error.validation_http"))?;
-                    if let Some(msg) = r.code_message.as_deref() {
-                        println!("{}", trf("status.server_message", &[("{message}", msg)]));
-                    }
-                    let t = match r.validate_token.as_deref() {
-                        Some(t) if !t.is_empty() => t.to_string(),
-                        _ => bail!("{}", tr("error.no_token")),
-                    };
-                    resp = r;
-                    t
-                }
-            };
-            if let Some(v) = &resp.pkgrom_validate {
-                if v.is_empty() {
-                    eprintln!("No allowed ROMs reported by server (Validate array empty). Proceeding may fail.");
-                }
-            }
-            let cancel = install_cancel_handler(cli.cancel_file.as_deref())?;
-            if (resp.pkgrom_erase == Some(1) || (cli_token_provided && wipe)) && !yes {
-                confirm_data_wipe_supervised(cli.machine, cli.approval_file.as_deref(), &cancel)?;
+            if device.serial_redacted() {
+                bail!("{}", tr("error.authorization_redacted"));
             }
-            let allow_wipe = if cli_token_provided {
-                wipe
-            } else {
-                resp.pkgrom_erase == Some(1) || wipe
-            };
-            emit_status(cli.machine, &tr("status.flashing"));
-            run_sideload(
-                &mut client,
-                &path,
-                cli.chunk_size,
-                &token_string,
-                allow_wipe,
-                &cancel,
+            let reported = device.read_all_info()?;
+            let serial = reported.sn.clone();
+            let info = effective_device_info(&identity, reported)?;
+            check_package_signature(cli.machine, &path, allow_unknown_signer)?;
+            let md5 = util::hash_cache::md5_cached(&path, &observer, &cancel)
+                .context(tr("error.compute_md5"))?;
+            let request = validate::build_request_json(&info, Some(md5.clone()))
+                .context(tr("error.build_validation"))?;
+            let response =
+                validate::validate_observed(&cli.server_url, &request, &observer, &cancel)
+                    .context(tr("error.validation_http"))?;
+            let package = path.file_name().map_or_else(
+                || path.display().to_string(),
+                |name| name.to_string_lossy().into_owned(),
+            );
+            let authorization =
+                Authorization::new(serial.reveal(), info, &package, &md5, &response, wipe)?;
+            let output = output.unwrap_or_else(|| PathBuf::from(format!("{package}.auth.json")));
+            authorization.save(&output)?;
+            report_authorization(cli.machine, &authorization);
+            report_status(
                 cli.machine,
-            )
-            .context(tr("error.sideload"))?;
-            emit_completed(cli.machine, &tr("status.flash_completed"));
+                "status.authorization_saved",
+                &[("{path}", &output.display().to_string())],
+            );
         }
         Commands::FormatData { yes } => {
             if !yes {
                 confirm_data_wipe()?;
             }
-            client
+            device
+                .client()?
                 .simple_command("format-data:")
                 .context("format-data:")?;
-            client.simple_command("reboot:").context("reboot:")?;
+            device
+                .client()?
+                .simple_command("reboot:")
+                .context("reboot:")?;
         }
         Commands::Reboot => {
-            client.simple_command("reboot:").context("reboot:")?;
+            device
+                .client()?
+                .simple_command("reboot:")
+                .context("reboot:")?;
         }
     }
 
-    Ok(())
+    Ok(ExitCode::SUCCESS)
 }
 
-fn reset_control_file(path: Option<&Path>) -> Result<()> {
-    if let Some(path) = path {
-        match std::fs::remove_file(path) {
-            Ok(()) => {}
-            Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+/// Exit status of `check-update` when a newer ROM is offered; errors exit 1.
+const UPDATE_AVAILABLE_EXIT: u8 = 100;
+const REGION_CHANGE_EXIT: u8 = 101;
+
+fn print_update_check(check: &update::UpdateCheck) {
+    println!(
+        "{}",
+        trf(
+            "label.installed",
+            &[("{value}", &check.installed.to_string())]
+        )
+    );
+    for (label, package) in [
+        ("label.latest", &check.latest),
+        ("label.incremental", &check.incremental),
+    ] {
+        if let Some(package) = package {
+            println!("{}", trf(label, &[("{value}", &package.summary())]));
+        }
+    }
+    match (check.status, check.target()) {
+        (update::UpdateStatus::UpdateAvailable, Some(target)) => println!(
+            "{}",
+            trf(
+                "status.update_available",
+                &[
+                    ("{installed}", &check.installed.to_string()),
+                    ("{latest}", &target.to_string()),
+                ]
+            )
+        ),
+        (update::UpdateStatus::RegionChange, Some(target)) => println!(
+            "{}",
+            trf(
+                "status.region_change_offered",
+                &[
+                    ("{installed}", &check.installed.to_string()),
+                    ("{offered}", &target.to_string()),
+                ]
+            )
+        ),
+        _ => println!("{}", tr("status.up_to_date")),
+    }
+    if let Some(package) = check
+        .incremental
+        .iter()
+        .chain(&check.latest)
+        .find(|package| !package.changelog.is_empty())
+    {
+        validate::print_changelog(&package.changelog);
+    }
+}
+
+fn inspect_package(path: &Path, json: bool, partitions: bool, verify_payload: bool) -> Result<()> {
+    if !path.exists() {
+        bail!(
+            "{}",
+            trf(
+                "error.zip_not_found",
+                &[("{path}", &path.display().to_string())]
+            )
+        );
+    }
+    let size = std::fs::metadata(path)
+        .with_context(|| format!("Reading {}", path.display()))?
+        .len();
+    let signature = ota::verify_package(path);
+    let mut document = serde_json::json!({
+        "path": path,
+        "size": size,
+        "signature": match &signature {
+            Ok(report) => serde_json::json!({
+                "status": match (report.trusted, report.checked) {
+                    (true, _) => "trusted",
+                    (false, true) => "unknown_signer",
+                    (false, false) => "unchecked_signer",
+                },
+                "digest": report.digest,
+                "signer": report.signer,
+            }),
+            Err(error) => serde_json::json!({
+                "status": "invalid",
+                "error": format!("{error:#}"),
+            }),
+        },
+    });
+    if !json {
+        println!(
+            "{}",
+            trf("label.package", &[("{value}", &path.display().to_string())])
+        );
+        println!("{}", trf("label.size", &[("{value}", &size.to_string())]));
+        let state = match &signature {
+            Ok(report) => {
+                let key = match (report.trusted, report.checked) {
+                    (true, _) => "label.signature_verified",
+                    (false, true) => "label.signature_unknown_signer",
+                    (false, false) => "label.signature_unchecked",
+                };
+                trf(
+                    key,
+                    &[
+                        ("{digest}", &report.digest.to_string()),
+                        ("{signer}", &report.signer),
+                    ],
+                )
+            }
+            Err(_) => tr("label.signature_invalid"),
+        };
+        println!("{}", trf("label.signature", &[("{value}", &state)]));
+    }
+
+    // Payload problems are recorded like the signature status, so the JSON
+    // document is printed before the command fails.
+    let mut payload_error = None;
+    if partitions || verify_payload {
+        match payload::open(path).context(tr("error.payload")) {
+            Ok(payload) => {
+                let verification = match (&payload, verify_payload) {
+                    (Some(payload), true) => {
+                        if !json {
+                            eprintln!("{}", tr("status.verifying_payload"));
+                        }
+                        match payload::verify(path, payload).context(tr("error.payload")) {
+                            Ok(verification) => Some(verification),
+                            Err(error) => {
+                                payload_error = Some(error);
+                                None
+                            }
+                        }
+                    }
+                    _ => None,
+                };
+                document["payload"] = match &payload {
+                    Some(payload) => serde_json::json!({
+                        "version": payload.header.version,
+                        "minor_version": payload.manifest.minor_version,
+                        "block_size": payload.manifest.block_size,
+                        "kind": payload.manifest.kind(),
+                        "security_patch_level": payload.manifest.security_patch_level,
+                        "partitions": payload.summaries(),
+                        "verification": match &payload_error {
+                            Some(error) => serde_json::json!({
+                                "status": "invalid",
+                                "error": format!("{error:#}"),
+                            }),
+                            None => serde_json::json!(verification),
+                        },
+                    }),
+                    None => serde_json::Value::Null,
+                };
+                if !json {
+                    print_payload(payload.as_ref(), verification.as_ref());
+                }
+            }
             Err(error) => {
-                return Err(error)
-                    .with_context(|| format!("Resetting control file {}", path.display()));
+                document["payload"] = serde_json::json!({
+                    "status": "invalid",
+                    "error": format!("{error:#}"),
+                });
+                payload_error = Some(error);
             }
         }
     }
-    Ok(())
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&document)?);
+    }
+    if let Some(error) = payload_error {
+        return Err(error);
+    }
+    signature.map(|_| ()).context(tr("error.signature"))
 }
 
-fn emit_machine_event(event: serde_json::Value) {
-    println!("{event}");
-    io::stdout().flush().ok();
+fn update_kind_label(kind: payload::UpdateKind) -> String {
+    tr(match kind {
+        payload::UpdateKind::Full => "label.payload_full",
+        payload::UpdateKind::Incremental => "label.payload_incremental",
+    })
 }
 
-fn emit_status(machine: bool, message: &str) {
-    if machine {
-        emit_machine_event(serde_json::json!({
-            "event": "status",
-            "message": message
-        }));
+fn print_payload(
+    payload: Option<&payload::Payload>,
+    verification: Option<&payload::PayloadVerification>,
+) {
+    let Some(payload) = payload else {
+        println!("{}", tr("status.no_payload"));
+        return;
+    };
+    let summary = trf(
+        "label.payload_summary",
+        &[
+            ("{kind}", &update_kind_label(payload.manifest.kind())),
+            ("{major}", &payload.header.version.to_string()),
+            ("{minor}", &payload.manifest.minor_version.to_string()),
+            ("{count}", &payload.manifest.partitions.len().to_string()),
+        ],
+    );
+    println!("{}", trf("label.payload", &[("{value}", &summary)]));
+    for partition in payload.summaries() {
+        let size = partition
+            .size
+            .map_or_else(|| "-".to_string(), |size| size.to_string());
+        println!(
+            "{}",
+            trf(
+                "label.payload_partition",
+                &[
+                    ("{name}", &format!("{:<24}", partition.name)),
+                    ("{size}", &format!("{size:>14}")),
+                    (
+                        "{kind}",
+                        &format!("{:<11}", update_kind_label(partition.kind))
+                    ),
+                    ("{full}", &format!("{:>5}", partition.full_operations)),
+                    (
+                        "{incremental}",
+                        &format!("{:<5}", partition.incremental_operations)
+                    ),
+                    ("{sha256}", partition.sha256.as_deref().unwrap_or("-")),
+                ]
+            )
+        );
+    }
+    if let Some(verification) = verification {
+        println!(
+            "{}",
+            trf(
+                "status.payload_verified",
+                &[("{count}", &verification.operations_checked.to_string())]
+            )
+        );
+    }
+}
+
+fn check_package_signature(machine: bool, path: &Path, allow_unknown_signer: bool) -> Result<()> {
+    emit_status(machine, "status.verifying_signature", &[]);
+    // Warnings are printed below in the terminal's own format.
+    let report = flash::check_package(path, allow_unknown_signer, &sensitivity::observer::Silent)?;
+    let digest = report.digest.to_string();
+    if report.trusted {
+        report_status(
+            machine,
+            "status.signature_trusted",
+            &[("{digest}", &digest), ("{signer}", &report.signer)],
+        );
+    } else if report.checked {
+        print_warning(&trf(
+            "warning.signature_unknown",
+            &[("{signer}", &report.signer)],
+        ));
+    } else {
+        print_warning(&trf(
+            "warning.signature_unchecked",
+            &[("{signer}", &report.signer)],
+        ));
+    }
+    Ok(())
+}
+
+fn reset_control_file(path: Option<&Path>) -> Result<()> {
+    if let Some(path) = path {
+        match std::fs::remove_file(path) {
+            Ok(()) => {}
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+            Err(error) => {
+                return Err(error)
+                    .with_context(|| format!("Resetting control file {}", path.display()));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn emit_machine_event(event: serde_json::Value) {
+    println!("{}", machine::stamp(event));
+    io::stdout().flush().ok();
+}
+
+/// Emits an event whose text is the catalog entry `key` filled with `params`.
+fn emit_message_event(name: &str, key: &str, params: &[(&str, &str)]) {
+    let mut fields = machine::message(key, params);
+    fields.insert("event".into(), name.into());
+    emit_machine_event(fields.into());
+}
+
+/// One row per profile, then which profiles allow each package.
+fn print_probes(probes: &[RegionProbe]) {
+    let rows: Vec<[String; 4]> = probes
+        .iter()
+        .map(|probe| {
+            let outcome = match (&probe.error, &probe.latest) {
+                (Some(error), _) => format!("{}: {error}", tr("error.prefix")),
+                (None, Some(latest)) => latest.summary(),
+                (None, None) => probe.message.clone().unwrap_or_else(|| "-".to_string()),
+            };
+            let wipe = match probe.wipe {
+                Wipe::Required => tr("label.wipe_required"),
+                Wipe::NotRequired => tr("label.wipe_not_required"),
+                Wipe::RegionChange => tr("label.wipe_region_change"),
+                Wipe::Unknown => "?".to_string(),
+            };
+            [probe.profile.clone(), probe.device.clone(), wipe, outcome]
+        })
+        .collect();
+    let header = [
+        tr("label.probe_profile"),
+        tr("label.probe_device"),
+        tr("label.probe_wipe"),
+        tr("label.probe_latest"),
+    ];
+    let width = |column: usize| {
+        rows.iter()
+            .map(|row| row[column].chars().count())
+            .chain([header[column].chars().count()])
+            .max()
+            .unwrap_or(0)
+    };
+    let widths = [width(0), width(1), width(2)];
+    for row in std::iter::once(&header).chain(&rows) {
+        println!(
+            "{:<w0$}  {:<w1$}  {:<w2$}  {}",
+            row[0],
+            row[1],
+            row[2],
+            row[3],
+            w0 = widths[0],
+            w1 = widths[1],
+            w2 = widths[2]
+        );
+    }
+
+    let mut packages: Vec<&str> = probes
+        .iter()
+        .flat_map(|probe| probe.allowed.iter().map(String::as_str))
+        .collect();
+    packages.sort_unstable();
+    packages.dedup();
+    if packages.is_empty() {
+        return;
+    }
+    println!();
+    let name_width = packages
+        .iter()
+        .map(|name| name.chars().count())
+        .chain([tr("label.probe_allowed").chars().count()])
+        .max()
+        .unwrap_or(0);
+    let mut line = format!("{:<name_width$}", tr("label.probe_allowed"));
+    for probe in probes {
+        line.push_str(&format!("  {}", probe.profile));
+    }
+    println!("{line}");
+    for package in packages {
+        let mut line = format!("{package:<name_width$}");
+        for probe in probes {
+            let mark = if probe.allowed.iter().any(|allowed| allowed == package) {
+                "x"
+            } else {
+                "."
+            };
+            line.push_str(&format!("  {mark:<0$}", probe.profile.chars().count()));
+        }
+        println!("{}", line.trim_end());
     }
 }
 
-fn emit_completed(machine: bool, message: &str) {
+fn emit_status(machine: bool, key: &str, params: &[(&str, &str)]) {
+    tracing::info!(target: UI_LOG, key, "{}", trf(key, params));
     if machine {
-        emit_machine_event(serde_json::json!({
-            "event": "completed",
-            "message": message
-        }));
+        emit_message_event("status", key, params);
+    }
+}
+
+/// Prints a status line, or emits it as an event in machine mode.
+fn report_status(machine: bool, key: &str, params: &[(&str, &str)]) {
+    if machine {
+        emit_status(machine, key, params);
+    } else {
+        let text = trf(key, params);
+        tracing::info!(target: UI_LOG, key, "{text}");
+        println!("{text}");
     }
 }
 
+/// Prints a warning for the user and records it in the log.
+fn print_warning(text: &str) {
+    tracing::warn!(target: UI_LOG, "{text}");
+    eprintln!("{text}");
+}
+
+fn emit_completed(machine: bool, key: &str) {
+    if machine {
+        emit_message_event("completed", key, &[]);
+    }
+}
+
+/// What a bundle allows, shown when it is created and again on the bench.
+fn report_authorization(machine: bool, authorization: &Authorization) {
+    let age = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs())
+        .saturating_sub(authorization.created_at);
+    report_status(
+        machine,
+        "status.authorization",
+        &[
+            ("{package}", &authorization.package),
+            ("{serial}", &authorization.serial.to_string()),
+            ("{hours}", &(age / 3600).to_string()),
+        ],
+    );
+    if let Some(message) = &authorization.message {
+        report_status(machine, "status.server_message", &[("{message}", message)]);
+    }
+    if authorization.erase || authorization.force_wipe {
+        report_status(machine, "status.authorization_wipes", &[]);
+    }
+}
+
+/// Prints what the server said about a planned flash.
+fn report_plan(plan: &FlashPlan) {
+    let Some(response) = &plan.response else {
+        return;
+    };
+    if let Some(msg) = response.code_message.as_deref() {
+        println!("{}", trf("status.server_message", &[("{message}", msg)]));
+    }
+    if response.pkgrom_validate.as_ref().is_some_and(Vec::is_empty) {
+        print_warning(
+            "No allowed ROMs reported by server (Validate array empty). Proceeding may fail.",
+        );
+    }
+}
+
+/// Asks for wipes on the terminal, through the supervisor in machine mode,
+/// or not at all under `--yes`.
+struct CliConfirmer<'a> {
+    machine: bool,
+    yes: bool,
+    approval_file: Option<&'a Path>,
+    control: Option<&'a ControlChannel>,
+    cancel: &'a CancelToken,
+}
+
+impl Confirmer for CliConfirmer<'_> {
+    fn step(&mut self, step: FlashStep) {
+        match step {
+            FlashStep::ConfirmingWipe(_) => {}
+            FlashStep::Sideloading => emit_status(self.machine, "status.flashing", &[]),
+            FlashStep::Completed => emit_completed(self.machine, "status.flash_completed"),
+        }
+    }
+
+    fn confirm_wipe(&mut self, reason: WipeReason) -> Result<bool> {
+        if self.yes {
+            return Ok(true);
+        }
+        confirm_data_wipe_supervised(
+            self.machine,
+            self.approval_file,
+            self.control,
+            reason,
+            self.cancel,
+        )
+    }
+}
+
+fn run_flash(
+    plan: &FlashPlan,
+    client: &mut MiClient,
+    source: &mut dyn PackageSource,
+    chunk_size: usize,
+    mut confirmer: CliConfirmer<'_>,
+    observer: &dyn Observer,
+) -> Result<()> {
+    let cancel = confirmer.cancel;
+    match plan.execute(client, source, chunk_size, &mut confirmer, observer, cancel)? {
+        FlashOutcome::Completed { .. } => Ok(()),
+        FlashOutcome::WipeDeclined => Err(WipeDeclined.into()),
+    }
+}
+
+/// Asks on the terminal, or in machine mode waits for the supervisor to
+/// answer on the control channel or by creating `approval_file`.
 fn confirm_data_wipe_supervised(
     machine: bool,
     approval_file: Option<&Path>,
-    cancel: &AtomicBool,
-) -> Result<()> {
+    control: Option<&ControlChannel>,
+    reason: WipeReason,
+    cancel: &CancelToken,
+) -> Result<bool> {
     if !machine {
-        return confirm_data_wipe();
+        confirm_data_wipe()?;
+        return Ok(true);
     }
-    let approval_file = approval_file.ok_or_else(|| anyhow::anyhow!(tr("error.approval_file")))?;
-    emit_machine_event(serde_json::json!({
-        "event": "confirmation_required",
-        "kind": "data_wipe",
-        "message": "Xiaomi requires this flash to permanently erase user data."
-    }));
+    let approval = control
+        .map(ControlChannel::approval)
+        .filter(|approval| approval.is_open());
+    if approval_file.is_none() && approval.is_none() {
+        bail!("{}", tr("error.no_approval_channel"));
+    }
+    if let Some(approval) = approval {
+        approval.request();
+    }
+    let mut fields = machine::message("prompt.erase_warning", &[]);
+    fields.insert("event".into(), "confirmation_required".into());
+    fields.insert("kind".into(), "data_wipe".into());
+    fields.insert("reason".into(), serde_json::json!(reason));
+    emit_machine_event(fields.into());
     loop {
-        if cancel.load(Ordering::Relaxed) {
+        if cancel.is_cancelled() {
             bail!("{}", tr("error.wipe_not_approved"));
         }
-        if approval_file.exists() {
+        let decision = match approval {
+            Some(approval) => approval.wait(Duration::from_millis(100)),
+            None => {
+                std::thread::sleep(Duration::from_millis(100));
+                None
+            }
+        };
+        if let Some(decision) = decision {
+            return Ok(decision);
+        }
+        if let Some(approval_file) = approval_file.filter(|path| path.exists()) {
             let _ = std::fs::remove_file(approval_file);
-            return Ok(());
+            return Ok(true);
         }
-        std::thread::sleep(Duration::from_millis(100));
-    }
-}
-
-#[allow(clippy::too_many_arguments)]
-fn run_sideload(
-    client: &mut MiClient,
-    path: &Path,
-    chunk_size: usize,
-    token: &str,
-    allow_wipe: bool,
-    cancel: &AtomicBool,
-    machine: bool,
-) -> Result<()> {
-    if machine {
-        sideload_zip_with_progress(
-            client,
-            path,
-            chunk_size,
-            token,
-            allow_wipe,
-            cancel,
-            |current, total| {
-                emit_machine_event(serde_json::json!({
-                    "event": "progress",
-                    "current": current,
-                    "total": total
-                }));
-            },
-        )
-    } else {
-        sideload_zip(client, path, chunk_size, token, allow_wipe, cancel)
     }
 }
 
@@ -696,20 +1711,28 @@ fn confirm_data_wipe() -> Result<()> {
     Ok(())
 }
 
-fn install_cancel_handler(cancel_file: Option<&Path>) -> Result<Arc<AtomicBool>> {
-    let cancel = Arc::new(AtomicBool::new(false));
-    let handler_flag = Arc::clone(&cancel);
+/// Cancels the returned token on the first Ctrl-C or when `cancel_file`
+/// appears. A second Ctrl-C exits at once, for steps that cannot be
+/// interrupted cooperatively.
+fn install_cancel_handler(cancel_file: Option<&Path>) -> Result<CancelToken> {
+    let cancel = CancelToken::new();
+    let handler_token = cancel.clone();
+    let pressed = AtomicBool::new(false);
     ctrlc::set_handler(move || {
-        handler_flag.store(true, Ordering::Relaxed);
+        if pressed.swap(true, Ordering::Relaxed) {
+            std::process::exit(130);
+        }
+        handler_token.cancel();
+        tracing::info!(target: UI_LOG, "{}", tr("status.cancel_requested"));
         eprintln!("\n{}", tr("status.cancel_requested"));
     })
     .context(tr("error.install_ctrl_c"))?;
     if let Some(path) = cancel_file {
         let path = path.to_path_buf();
-        let file_flag = Arc::clone(&cancel);
+        let file_token = cancel.clone();
         std::thread::spawn(move || loop {
             if path.exists() {
-                file_flag.store(true, Ordering::Relaxed);
+                file_token.cancel();
                 break;
             }
             std::thread::sleep(Duration::from_millis(100));
@@ -732,13 +1755,750 @@ fn print_usb_device(device: &sensitivity::usb::UsbDeviceInfo) {
     );
 }
 
+fn run_daemon(
+    cli: &Cli,
+    listen: Option<&str>,
+    action: Option<&DaemonAction>,
+    cancel: &CancelToken,
+) -> Result<()> {
+    let tokens = daemon::TokenStore::open_default()?;
+    match action {
+        Some(DaemonAction::Token { action }) => match action {
+            TokenAction::Add { name } => {
+                let token = tokens.issue(name)?;
+                eprintln!("{}", trf("status.token_issued", &[("{name}", name)]));
+                println!("{token}");
+            }
+            TokenAction::List => {
+                let clients = tokens.clients()?;
+                if clients.is_empty() {
+                    println!("{}", tr("status.no_tokens"));
+                }
+                for client in clients {
+                    println!("{client}");
+                }
+            }
+            TokenAction::Revoke { name } => {
+                if !tokens.revoke(name)? {
+                    bail!("{}", trf("error.no_client_token", &[("{name}", name)]));
+                }
+                println!("{}", trf("status.token_revoked", &[("{name}", name)]));
+            }
+        },
+        None => {
+            let endpoint = match listen {
+                Some(listen) => daemon::Endpoint::parse(listen)?,
+                None => daemon::Endpoint::default_for_platform()?,
+            };
+            if tokens.clients()?.is_empty() {
+                print_warning(&tr("warning.no_daemon_tokens"));
+            }
+            let options = daemon::DaemonOptions {
+                server_url: cli.server_url.clone(),
+                debug_usb: cli.debug_usb,
+                tokens,
+            };
+            let server = daemon::Daemon::bind(&endpoint, options, cancel.clone())
+                .context(tr("error.daemon"))?;
+            eprintln!(
+                "{}",
+                trf(
+                    "status.daemon_listening",
+                    &[("{endpoint}", &server.endpoint().to_string())]
+                )
+            );
+            server.run().context(tr("error.daemon"))?;
+        }
+    }
+    Ok(())
+}
+
+/// Loads the settings, records values given on the command line as the flag
+/// layer and fills in everything the command line left out.
+fn apply_config(cli: &mut Cli, matches: &ArgMatches) -> Result<Config> {
+    let cwd = std::env::current_dir().context("Reading the working directory")?;
+    // `config` must still run when a file is broken, so it can show and fix it.
+    let lenient = matches!(cli.command, Commands::Config { .. });
+    let mut config = if lenient {
+        Config::load_lenient(&cwd)
+    } else {
+        Config::load(&cwd)?
+    };
+    for problem in config.warnings() {
+        print_warning(&trf("warning.config", &[("{problem}", problem)]));
+    }
+    let skip = |error: anyhow::Error| -> Result<()> {
+        if !lenient {
+            return Err(error);
+        }
+        print_warning(&trf(
+            "warning.config",
+            &[("{problem}", &format!("{error:#}"))],
+        ));
+        Ok(())
+    };
+    let given = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+    if given("server_url") {
+        config.set_flag("server_url", cli.server_url.clone());
+    }
+    if given("chunk_size") {
+        config.set_flag("chunk_size", cli.chunk_size.to_string());
+    }
+    if given("adb_policy") {
+        config.set_flag("adb_policy", value_name(cli.adb_policy));
+    }
+    if let Some(profile) = &cli.profile {
+        config.set_flag("profile", profile.clone());
+    }
+    if let Commands::DownloadLatest {
+        output_dir: Some(dir),
+        ..
+    }
+    | Commands::FlashFromLatest {
+        output_dir: Some(dir),
+        ..
+    } = &cli.command
+    {
+        config.set_flag("download_dir", dir.display().to_string());
+    }
+
+    if let Some(value) = config.get("server_url") {
+        cli.server_url = value.text;
+    }
+    if let Some(value) = config.get("chunk_size") {
+        match value.text.parse() {
+            Ok(chunk_size) => cli.chunk_size = chunk_size,
+            Err(error) => skip(
+                anyhow::Error::new(error).context(format!("chunk_size from {}", value.origin())),
+            )?,
+        }
+    }
+    if let Some(value) = config.get("adb_policy") {
+        match parse_setting(&value.text) {
+            Ok(policy) => cli.adb_policy = policy,
+            Err(error) => skip(error.context(format!("adb_policy from {}", value.origin())))?,
+        }
+    }
+    if let Some(value) = config.get("profile") {
+        cli.profile = Some(value.text);
+        cli.profile_from_flag = value.layer == Layer::Flag;
+    }
+    if let Some(value) = config.get("language") {
+        match i18n::Language::from_code(&value.text) {
+            Some(language) => i18n::set_language(language),
+            // The environment variable predates the setting and never failed.
+            None if value.layer == Layer::Environment => {}
+            None => skip(anyhow::anyhow!(
+                "Unknown language {:?} from {}",
+                value.text,
+                value.origin()
+            ))?,
+        }
+    }
+    if let Some(value) = config.get("download_dir") {
+        if let Commands::DownloadLatest { output_dir, .. }
+        | Commands::FlashFromLatest {
+            output_dir,
+            stream: false,
+            ..
+        } = &mut cli.command
+        {
+            output_dir.get_or_insert_with(|| PathBuf::from(value.text));
+        }
+    }
+    Ok(config)
+}
+
+fn parse_setting<T: ValueEnum>(text: &str) -> Result<T> {
+    T::from_str(text, true).map_err(|error| anyhow::anyhow!(error))
+}
+
+fn value_name<T: ValueEnum>(value: T) -> String {
+    value
+        .to_possible_value()
+        .map(|value| value.get_name().to_string())
+        .unwrap_or_default()
+}
+
+/// Rejects values the binary would refuse on the next run.
+fn check_setting(config: &Config, key: &str, text: &str) -> Result<()> {
+    if key == "profile" || (key.starts_with("devices.") && key.ends_with(".profile")) {
+        RegionProfile::resolve(text, &config.profiles()?)?;
+    } else if let Some((name, field)) = key
+        .strip_prefix("profiles.")
+        .and_then(|rest| rest.rsplit_once('.'))
+    {
+        match field {
+            "device_name" => RegionProfile::new(name, text, "MI", None, None, None)?,
+            "region_code" => RegionProfile::new(name, "{codename}", text, None, None, None)?,
+            _ => return Ok(()),
+        };
+    } else if key == "adb_policy" {
+        parse_setting::<AdbPolicy>(text)?;
+    } else if key == "language" && i18n::Language::from_code(text).is_none() {
+        bail!("Unknown language {text:?}");
+    }
+    Ok(())
+}
+
+fn manage_config(config: &Config, action: &ConfigAction) -> Result<ExitCode> {
+    const FILE_LAYERS: [Layer; 3] = [Layer::System, Layer::User, Layer::Project];
+    match action {
+        ConfigAction::Show { json } => {
+            let settings = config.effective();
+            if *json {
+                let settings: Vec<_> = settings
+                    .iter()
+                    .map(|(key, value)| {
+                        serde_json::json!({
+                            "key": key,
+                            "value": value.as_ref().map(|value| &value.text),
+                            "layer": value.as_ref().map(|value| value.layer),
+                            "path": value.as_ref().and_then(|value| value.path.as_ref()),
+                        })
+                    })
+                    .collect();
+                let files: serde_json::Map<_, _> = FILE_LAYERS
+                    .iter()
+                    .map(|layer| {
+                        let path = config.paths().get(*layer);
+                        (
+                            layer.to_string(),
+                            serde_json::json!({
+                                "path": path,
+                                "exists": path.is_some_and(Path::is_file),
+                            }),
+                        )
+                    })
+                    .collect();
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&serde_json::json!({
+                        "settings": settings,
+                        "files": files,
+                        "warnings": config.warnings(),
+                    }))?
+                );
+            } else {
+                for (key, value) in &settings {
+                    match value {
+                        Some(value) => println!("{key} = {:?}  # {}", value.text, value.origin()),
+                        None => println!("{key}  # {}", tr("label.config_unset")),
+                    }
+                }
+                println!();
+                println!("{}", tr("label.config_files"));
+                for layer in FILE_LAYERS {
+                    let state = match config.paths().get(layer) {
+                        Some(path) if path.is_file() => path.display().to_string(),
+                        Some(path) => {
+                            format!("{} ({})", path.display(), tr("label.config_missing"))
+                        }
+                        None => "-".to_string(),
+                    };
+                    println!("  {:<8} {state}", layer.to_string());
+                }
+            }
+        }
+        ConfigAction::Get { key } => {
+            if !config::is_key(key) {
+                bail!("Unknown setting `{key}`");
+            }
+            let Some(value) = config.get(key) else {
+                return Ok(ExitCode::FAILURE);
+            };
+            println!("{}", value.text);
+            eprintln!("# {}", value.origin());
+        }
+        ConfigAction::Set {
+            key,
+            value,
+            system,
+            project,
+        } => {
+            check_setting(config, key, value)
+                .with_context(|| format!("Invalid value for {key}"))?;
+            let path = if *system {
+                config.paths().system.clone()
+            } else if *project {
+                match config.paths().project.clone() {
+                    Some(path) => Some(path),
+                    None => Some(std::env::current_dir()?.join(config::PROJECT_FILE)),
+                }
+            } else {
+                config.paths().user.clone()
+            };
+            let path = path.context("This platform has no location for that file")?;
+            config::set(&path, key, value)?;
+            println!(
+                "{}",
+                trf(
+                    "status.config_set",
+                    &[("{key}", key), ("{path}", &path.display().to_string())]
+                )
+            );
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn manage_library(action: &LibraryAction) -> Result<()> {
+    let library = Library::open_default()?;
+    match action {
+        LibraryAction::List { json } => {
+            let mut entries = library.entries().context(tr("error.library"))?;
+            entries.sort_by(|a, b| {
+                (&a.codename, &a.region, &a.version).cmp(&(&b.codename, &b.region, &b.version))
+            });
+            if *json {
+                println!("{}", serde_json::to_string_pretty(&entries)?);
+            } else if entries.is_empty() {
+                println!(
+                    "{}",
+                    trf(
+                        "status.library_empty",
+                        &[("{path}", &library.root().display().to_string())]
+                    )
+                );
+            } else {
+                for entry in &entries {
+                    println!(
+                        "  {} {:<12} {:<4} {:<24} {:>12} {}",
+                        entry.md5,
+                        entry.codename.as_deref().unwrap_or("-"),
+                        entry.region.as_deref().unwrap_or("-"),
+                        entry.version.as_deref().unwrap_or("-"),
+                        entry.size,
+                        entry.file_name
+                    );
+                }
+            }
+        }
+        LibraryAction::Remove { md5 } => {
+            let entry = library.remove(md5).context(tr("error.library"))?;
+            println!(
+                "{}",
+                trf(
+                    "status.library_removed",
+                    &[("{md5}", &entry.md5), ("{file}", &entry.file_name)]
+                )
+            );
+        }
+        LibraryAction::Gc => {
+            let report = library.gc().context(tr("error.library"))?;
+            println!(
+                "{}",
+                trf(
+                    "status.library_gc",
+                    &[
+                        ("{files}", &report.removed_files.to_string()),
+                        ("{entries}", &report.removed_entries.to_string()),
+                        ("{bytes}", &report.freed_bytes.to_string()),
+                    ]
+                )
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Maps a missing `flash` path that names a library entry to its verified copy.
+fn resolve_package(observer: &CliObserver, cancel: &CancelToken, path: PathBuf) -> Result<PathBuf> {
+    if path.exists() {
+        return Ok(path);
+    }
+    let stored = Library::open_default()
+        .and_then(|library| library.verified_path(&path.to_string_lossy(), observer, cancel))
+        .context(tr("error.library"))?;
+    match stored {
+        Some(stored) => {
+            report_library_copy(observer.machine, &stored);
+            Ok(stored)
+        }
+        None => Ok(path),
+    }
+}
+
+fn report_library_copy(machine: bool, path: &Path) {
+    report_status(
+        machine,
+        "status.library_reuse",
+        &[("{path}", &path.display().to_string())],
+    );
+}
+
+/// Returns LatestRom from the library when a verified copy is stored, and
+/// downloads it otherwise. Downloads go into the library unless an output
+/// directory is given, in which case the library gets a hard link when the
+/// directory is on the same filesystem.
+fn fetch_latest(
+    observer: &CliObserver,
+    cancel: &CancelToken,
+    info: &DeviceInfo,
+    latest: &download::LatestInfo,
+    mirrors: &[String],
+    output_dir: Option<&Path>,
+    transfer: &TransferArgs,
+) -> Result<PathBuf> {
+    let library = Library::open_default()?;
+    let metadata = PackageMetadata {
+        file_name: download::package_file_name(&latest.filename).to_string(),
+        codename: info.device.split('_').next().map(str::to_string),
+        version: latest.version.clone(),
+    };
+    if let Some(stored) = library
+        .verified_path(&latest.md5, observer, cancel)
+        .context(tr("error.library"))?
+    {
+        report_library_copy(observer.machine, &stored);
+        let Some(out_dir) = output_dir else {
+            return Ok(stored);
+        };
+        std::fs::create_dir_all(out_dir)?;
+        let path = out_dir.join(&metadata.file_name);
+        place_library_copy(
+            &stored,
+            &path,
+            &latest.md5,
+            &util::hash_cache::HashCache::open_default(),
+            observer,
+            cancel,
+        )?;
+        return Ok(path);
+    }
+    let Some(out_dir) = output_dir else {
+        let path = download_latest(
+            observer,
+            cancel,
+            latest,
+            mirrors,
+            &library.incoming_dir(),
+            transfer,
+        )?;
+        library
+            .adopt(&path, &latest.md5, metadata)
+            .context(tr("error.library"))?;
+        return Ok(library.object_path(&latest.md5));
+    };
+    let path = download_latest(observer, cancel, latest, mirrors, out_dir, transfer)?;
+    if let Err(error) = library.link(&path, &latest.md5, metadata) {
+        print_warning(&trf(
+            "warning.library_link",
+            &[("{error}", &format!("{error:#}"))],
+        ));
+    }
+    Ok(path)
+}
+
+/// Makes `path` a copy of the verified library object `stored`. A file already
+/// there is kept only if it hashes to `md5`; anything else is replaced through
+/// a temporary file, so `path` never holds a partial copy.
+fn place_library_copy(
+    stored: &Path,
+    path: &Path,
+    md5: &str,
+    hashes: &util::hash_cache::HashCache,
+    observer: &dyn Observer,
+    cancel: &CancelToken,
+) -> Result<()> {
+    if path.is_file()
+        && hashes
+            .md5(path, observer, cancel)?
+            .eq_ignore_ascii_case(md5)
+    {
+        return Ok(());
+    }
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temporary = path.with_file_name(format!(".{file_name}.tmp"));
+    let _ = std::fs::remove_file(&temporary);
+    std::fs::hard_link(stored, &temporary)
+        .or_else(|_| std::fs::copy(stored, &temporary).map(|_| ()))
+        .with_context(|| format!("Copying {} to {}", stored.display(), path.display()))?;
+    std::fs::rename(&temporary, path).with_context(|| format!("Writing {}", path.display()))
+}
+
+/// Probes the LatestRom mirrors and returns them healthiest first.
+fn ranked_mirrors(
+    machine: bool,
+    latest: &download::LatestInfo,
+    mirrors: &[String],
+) -> Result<(reqwest::blocking::Client, Vec<download::Mirror>)> {
+    let mirrors = download::mirror_urls(mirrors, &latest.filename);
+    if mirrors.is_empty() {
+        bail!("{}", tr("error.no_mirror"));
+    }
+    let client = download::http_client()?;
+    let probes = download::rank_mirrors(&client, &mirrors);
+    for probe in &probes {
+        if machine {
+            emit_machine_event(serde_json::json!({
+                "event": "mirror_probe",
+                "mirror": probe.mirror.base,
+                "latency_ms": probe.latency_ms,
+                "ranges": probe.ranges,
+                "error": probe.error
+            }));
+        } else if let Some(error) = &probe.error {
+            print_warning(&trf(
+                "warning.mirror_unavailable",
+                &[("{mirror}", &probe.mirror.base), ("{error}", error)],
+            ));
+        }
+    }
+    Ok((
+        client,
+        probes.into_iter().map(|probe| probe.mirror).collect(),
+    ))
+}
+
+/// Downloads LatestRom from the healthiest mirror, failing over as needed.
+fn download_latest(
+    observer: &CliObserver,
+    cancel: &CancelToken,
+    latest: &download::LatestInfo,
+    mirrors: &[String],
+    out_dir: &Path,
+    transfer: &TransferArgs,
+) -> Result<PathBuf> {
+    let (client, ordered) = ranked_mirrors(observer.machine, latest, mirrors)?;
+    let options = download::DownloadOptions::from(transfer);
+    download::download_with_options(
+        &client,
+        &ordered,
+        out_dir,
+        &latest.md5,
+        &options,
+        observer,
+        cancel,
+    )
+    .context(tr("error.download_latest"))
+}
+
+/// Renders library progress: terminal bars for people, JSON events for a
+/// supervising application.
+struct CliObserver {
+    machine: bool,
+    bar: Mutex<Option<(Phase, ProgressBar)>>,
+    /// Last percentage reported per phase in machine mode.
+    reported: Mutex<HashMap<Phase, u64>>,
+}
+
+impl CliObserver {
+    fn new(machine: bool) -> Self {
+        Self {
+            machine,
+            bar: Mutex::new(None),
+            reported: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn bar_for(phase: Phase, total: u64) -> ProgressBar {
+        let bar = ProgressBar::new(total);
+        let template = match phase {
+            Phase::Hash => "{msg} [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec})",
+            _ => "{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({percent}%)",
+        };
+        bar.set_style(
+            ProgressStyle::default_bar()
+                .template(template)
+                .unwrap()
+                .progress_chars("=>-"),
+        );
+        if phase == Phase::Hash {
+            bar.set_message(tr("status.hashing"));
+        }
+        bar
+    }
+
+    /// Prints a line without tearing an active progress bar.
+    fn print_line(&self, line: &str) {
+        let bar = self.bar.lock().unwrap_or_else(|error| error.into_inner());
+        match bar.as_ref() {
+            Some((_, bar)) => bar.suspend(|| eprintln!("{line}")),
+            None => eprintln!("{line}"),
+        }
+    }
+}
+
+impl Observer for CliObserver {
+    fn phase(&self, phase: Phase) {
+        if self.machine {
+            emit_machine_event(serde_json::json!({
+                "event": "phase",
+                "phase": phase
+            }));
+        }
+    }
+
+    fn progress(&self, phase: Phase, current: u64, total: u64) {
+        if self.machine {
+            let event = match phase {
+                Phase::Sideload => "progress",
+                Phase::Hash => "hash_progress",
+                Phase::Download => "download_progress",
+                Phase::Validate => return,
+            };
+            // Sideload reports once per block already; the rest once per percent.
+            let percent = current * 100 / total.max(1);
+            let mut reported = self
+                .reported
+                .lock()
+                .unwrap_or_else(|error| error.into_inner());
+            let last = reported.insert(phase, percent);
+            if phase == Phase::Sideload || last != Some(percent) || current == total {
+                emit_machine_event(serde_json::json!({
+                    "event": event,
+                    "current": current,
+                    "total": total
+                }));
+            }
+            return;
+        }
+        let mut slot = self.bar.lock().unwrap_or_else(|error| error.into_inner());
+        if slot.as_ref().is_none_or(|(active, _)| *active != phase) {
+            if let Some((_, bar)) = slot.take() {
+                bar.finish_and_clear();
+            }
+            *slot = Some((phase, Self::bar_for(phase, total)));
+        }
+        let (_, bar) = slot.as_ref().expect("bar was just created");
+        if total > 0 {
+            bar.set_length(total);
+        }
+        bar.set_position(current);
+        if total > 0 && current >= total {
+            bar.finish_and_clear();
+            *slot = None;
+        }
+    }
+
+    fn message(&self, message: &str) {
+        if self.machine {
+            // Free text from the device, so there is no catalog key.
+            emit_machine_event(serde_json::json!({
+                "event": "status",
+                "key": null,
+                "params": {},
+                "message": message
+            }));
+        } else {
+            self.print_line(message);
+        }
+    }
+
+    fn mirror_segment(&self, segment: &download::MirrorSegment) {
+        if self.machine {
+            emit_machine_event(serde_json::json!({
+                "event": "mirror_segment",
+                "mirror": segment.mirror,
+                "start": segment.start,
+                "end": segment.end
+            }));
+        } else {
+            self.print_line(&trf(
+                "status.mirror_segment",
+                &[
+                    ("{mirror}", &segment.mirror),
+                    ("{start}", &segment.start.to_string()),
+                    ("{end}", &segment.end.to_string()),
+                ],
+            ));
+        }
+    }
+}
+
+/// Shows what is about to be downloaded, before the transfer starts.
+fn show_latest_package(machine: bool, resp: &validate::ValidateResult) {
+    let Some(package) = &resp.latest_rom else {
+        return;
+    };
+    if machine {
+        emit_machine_event(serde_json::json!({
+            "event": "package",
+            "kind": validate::PackageKind::Latest,
+            "package": package
+        }));
+    } else {
+        validate::print_package(validate::PackageKind::Latest, package);
+    }
+}
+
+/// Tells the user when the server's LatestRom is not newer than the device.
+fn report_latest_version(machine: bool, info: &DeviceInfo, latest: Option<&RomVersion>) {
+    let (Ok(installed), Some(latest)) = (info.version.parse::<RomVersion>(), latest) else {
+        return;
+    };
+    if installed.same_track(latest) && *latest <= installed {
+        report_status(
+            machine,
+            "status.already_latest",
+            &[
+                ("{installed}", &installed.to_string()),
+                ("{latest}", &latest.to_string()),
+            ],
+        );
+    }
+}
+
+/// Warns when a package name carries an older build than the device runs.
+fn warn_if_downgrade(info: &DeviceInfo, path: &Path) {
+    let (Ok(installed), Some(package)) = (
+        info.version.parse::<RomVersion>(),
+        RomVersion::from_package_name(&path.to_string_lossy()),
+    ) else {
+        return;
+    };
+    if installed.same_track(&package) && package < installed {
+        print_warning(&trf(
+            "warning.downgrade",
+            &[
+                ("{package}", &package.to_string()),
+                ("{installed}", &installed.to_string()),
+            ],
+        ));
+    }
+}
+
+/// Where a command gets the device from: the phone over USB, or a saved
+/// identity file.
+enum Device {
+    Usb(MiClient),
+    Snapshot(IdentitySnapshot),
+}
+
+impl Device {
+    fn read_all_info(&mut self) -> Result<DeviceInfo> {
+        match self {
+            Self::Usb(client) => client.read_all_info().context(tr("error.fetch_device")),
+            Self::Snapshot(snapshot) => Ok(snapshot.identity.clone()),
+        }
+    }
+
+    fn serial_redacted(&self) -> bool {
+        matches!(self, Self::Snapshot(snapshot) if snapshot.serial_redacted)
+    }
+
+    fn client(&mut self) -> Result<&mut MiClient> {
+        match self {
+            Self::Usb(client) => Ok(client),
+            Self::Snapshot(_) => bail!("{}", tr("error.identity_needs_device")),
+        }
+    }
+}
+
 fn adb_may_own_interface(error: &anyhow::Error) -> bool {
     let message = format!("{error:#}");
     message.contains("Claiming interface") || message.contains("Opening USB device")
 }
 
 struct IdentityOptions {
-    profile: Option<RegionProfile>,
+    profile: Option<String>,
+    /// Whether `profile` came from the command line and so beats a device preset.
+    profile_from_flag: bool,
+    /// Presets by device serial, from the configuration.
+    presets: BTreeMap<String, DevicePreset>,
+    /// Profiles defined in the configuration.
+    profiles: Vec<RegionProfile>,
     codename: Option<String>,
     device: Option<String>,
     version: Option<String>,
@@ -751,7 +2511,10 @@ struct IdentityOptions {
 impl From<&Cli> for IdentityOptions {
     fn from(cli: &Cli) -> Self {
         Self {
-            profile: cli.profile,
+            profile: cli.profile.clone(),
+            profile_from_flag: cli.profile_from_flag,
+            presets: BTreeMap::new(),
+            profiles: Vec::new(),
             codename: cli.codename.clone(),
             device: cli.override_device.clone(),
             version: cli.override_version.clone(),
@@ -764,34 +2527,42 @@ impl From<&Cli> for IdentityOptions {
 }
 
 fn effective_device_info(options: &IdentityOptions, mut info: DeviceInfo) -> Result<DeviceInfo> {
-    if let Some(profile) = options.profile {
-        info = apply_profile(&info, profile, options.codename.as_deref())?;
+    let preset = options
+        .presets
+        .get(info.sn.reveal())
+        .cloned()
+        .unwrap_or_default();
+    let profile = match (&preset.profile, options.profile_from_flag) {
+        (Some(name), false) => Some(name),
+        _ => options.profile.as_ref(),
+    };
+    let codename = options.codename.as_ref().or(preset.codename.as_ref());
+    if let Some(name) = profile {
+        let profile = RegionProfile::resolve(name, &options.profiles)?;
+        info = apply_profile(&info, &profile, codename.map(String::as_str))?;
         eprintln!(
             "{}",
-            trf(
-                "status.profile_applied",
-                &[("{profile}", &format!("{profile:?}"))]
-            )
+            trf("status.profile_applied", &[("{profile}", &profile.name)])
         );
     }
-    if let Some(value) = &options.device {
+    if let Some(value) = options.device.as_ref().or(preset.device.as_ref()) {
         info.device = value.clone();
     }
-    if let Some(value) = &options.version {
+    if let Some(value) = options.version.as_ref().or(preset.version.as_ref()) {
         info.version = value.clone();
     }
-    if let Some(value) = &options.serial {
-        info.sn = value.clone();
-    }
-    if let Some(value) = &options.codebase {
+    if let Some(value) = options.codebase.as_ref().or(preset.codebase.as_ref()) {
         info.codebase = value.clone();
     }
-    if let Some(value) = &options.branch {
+    if let Some(value) = options.branch.as_ref().or(preset.branch.as_ref()) {
         info.branch = value.clone();
     }
-    if let Some(value) = &options.romzone {
+    if let Some(value) = options.romzone.as_ref().or(preset.romzone.as_ref()) {
         info.romzone = value.clone();
     }
+    if let Some(value) = &options.serial {
+        info.sn = value.as_str().into();
+    }
     Ok(info)
 }
 
@@ -805,16 +2576,174 @@ mod cli_tests {
         assert_eq!(cli.adb_policy, AdbPolicy::Keep);
     }
 
+    #[test]
+    fn verbosity_flags_count_and_exclude_each_other() {
+        let cli = Cli::try_parse_from([
+            "sensitivity",
+            "-vv",
+            "detect",
+            "--log-file",
+            "support.log",
+            "--log-format",
+            "json",
+        ])
+        .unwrap();
+        assert_eq!((cli.verbose, cli.quiet), (2, 0));
+        assert_eq!(cli.log_file, Some(PathBuf::from("support.log")));
+        assert_eq!(cli.log_format, LogFormat::Json);
+        assert!(Cli::try_parse_from(["sensitivity", "-v", "-q", "detect"]).is_err());
+    }
+
+    #[test]
+    fn download_transfer_options_parse() {
+        let cli = Cli::try_parse_from([
+            "sensitivity",
+            "download-latest",
+            "--connections",
+            "4",
+            "--limit-rate",
+            "2M",
+        ])
+        .unwrap();
+        let Commands::DownloadLatest { transfer, .. } = cli.command else {
+            panic!("expected download-latest");
+        };
+        let options = download::DownloadOptions::from(&transfer);
+        assert_eq!(options.connections, 4);
+        assert_eq!(options.max_bytes_per_second, Some(2 * 1024 * 1024));
+        assert!(
+            Cli::try_parse_from(["sensitivity", "download-latest", "--connections", "0"]).is_err()
+        );
+    }
+
+    #[test]
+    fn streaming_excludes_an_output_directory() {
+        let cli = Cli::try_parse_from(["sensitivity", "flash-from-latest", "--stream"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Commands::FlashFromLatest { stream: true, .. }
+        ));
+        assert!(Cli::try_parse_from([
+            "sensitivity",
+            "flash-from-latest",
+            "--stream",
+            "--output-dir",
+            "roms"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn check_update_parses_json_flag() {
+        let cli = Cli::try_parse_from(["sensitivity", "check-update", "--json"]).unwrap();
+        assert!(matches!(cli.command, Commands::CheckUpdate { json: true }));
+    }
+
+    #[test]
+    fn library_commands_parse() {
+        let cli = Cli::try_parse_from(["sensitivity", "library", "remove", "5eb63bbb"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Commands::Library { action: LibraryAction::Remove { md5 } } if md5 == "5eb63bbb"
+        ));
+        assert!(Cli::try_parse_from(["sensitivity", "import"]).is_err());
+    }
+
     #[test]
     fn explicit_stop_policy_parses() {
         let cli = Cli::try_parse_from(["sensitivity", "--adb-policy", "stop", "doctor"]).unwrap();
         assert_eq!(cli.adb_policy, AdbPolicy::Stop);
     }
 
+    #[test]
+    fn probe_regions_takes_a_profile_list() {
+        let cli = Cli::try_parse_from([
+            "sensitivity",
+            "probe-regions",
+            "--profiles",
+            "eea,jp",
+            "--interval",
+            "5",
+        ])
+        .unwrap();
+        assert!(matches!(
+            cli.command,
+            Commands::ProbeRegions { profiles, interval: 5, json: false } if profiles == ["eea", "jp"]
+        ));
+    }
+
+    #[test]
+    fn identity_files_stand_in_only_for_identity_commands() {
+        let cli = Cli::try_parse_from([
+            "sensitivity",
+            "download-latest",
+            "--identity",
+            "customer.json",
+        ])
+        .unwrap();
+        assert_eq!(cli.identity.as_deref(), Some(Path::new("customer.json")));
+        assert!(cli.command.needs_identity_only());
+        let cli = Cli::try_parse_from(["sensitivity", "flash", "rom.zip"]).unwrap();
+        assert!(!cli.command.needs_identity_only());
+        assert!(Cli::try_parse_from(["sensitivity", "info", "--redact-serial"]).is_err());
+    }
+
+    #[test]
+    fn authorization_replaces_manual_tokens() {
+        let cli = Cli::try_parse_from([
+            "sensitivity",
+            "flash",
+            "rom.zip",
+            "--authorization",
+            "rom.zip.auth.json",
+        ])
+        .unwrap();
+        assert!(matches!(
+            cli.command,
+            Commands::Flash {
+                authorization: Some(_),
+                token: None,
+                ..
+            }
+        ));
+        for extra in [["--token", "abc"], ["--wipe", "--yes"]] {
+            let mut arguments = vec![
+                "sensitivity",
+                "flash",
+                "rom.zip",
+                "--authorization",
+                "a.json",
+            ];
+            arguments.extend(extra);
+            assert!(Cli::try_parse_from(arguments).is_err());
+        }
+    }
+
     #[test]
     fn read_info_compatibility_alias_parses() {
         let cli = Cli::try_parse_from(["sensitivity", "read-info", "--json"]).unwrap();
-        assert!(matches!(cli.command, Commands::Info { json: true }));
+        assert!(matches!(cli.command, Commands::Info { json: true, .. }));
+    }
+
+    #[test]
+    fn inspect_parses_a_package_path() {
+        let cli = Cli::try_parse_from([
+            "sensitivity",
+            "inspect",
+            "rom.zip",
+            "--json",
+            "--partitions",
+        ])
+        .unwrap();
+        assert!(matches!(
+            cli.command,
+            Commands::Inspect {
+                json: true,
+                partitions: true,
+                verify_payload: false,
+                ..
+            }
+        ));
     }
 
     #[test]
@@ -854,10 +2783,38 @@ mod cli_tests {
         assert_eq!(cli.approval_file, Some(PathBuf::from("approve.flag")));
     }
 
+    #[test]
+    fn device_presets_yield_to_a_profile_flag() {
+        let cli = Cli::try_parse_from(["sensitivity", "--profile", "in", "info"]).unwrap();
+        let mut options = IdentityOptions::from(&cli);
+        options.presets.insert(
+            "serial".into(),
+            DevicePreset {
+                profile: Some("eea".into()),
+                codename: Some("garnet".into()),
+                ..DevicePreset::default()
+            },
+        );
+        let info = DeviceInfo {
+            device: "garnet".into(),
+            sn: "serial".into(),
+            ..DeviceInfo::default()
+        };
+
+        let preset = effective_device_info(&options, info.clone()).unwrap();
+        assert_eq!(preset.device, "garnet_eea_global");
+        options.profile_from_flag = true;
+        let flagged = effective_device_info(&options, info).unwrap();
+        assert_eq!(flagged.device, "garnet_in_global");
+    }
+
     #[test]
     fn supervised_wipe_requires_an_approval_path() {
-        let cancel = AtomicBool::new(true);
-        assert!(confirm_data_wipe_supervised(true, None, &cancel).is_err());
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        assert!(
+            confirm_data_wipe_supervised(true, None, None, WipeReason::Server, &cancel).is_err()
+        );
     }
 
     #[test]
@@ -865,10 +2822,43 @@ mod cli_tests {
         let directory = tempfile::tempdir().unwrap();
         let approval = directory.path().join("approve");
         std::fs::write(&approval, []).unwrap();
-        let cancel = AtomicBool::new(false);
+        let cancel = CancelToken::new();
 
-        confirm_data_wipe_supervised(true, Some(&approval), &cancel).unwrap();
+        assert!(confirm_data_wipe_supervised(
+            true,
+            Some(&approval),
+            None,
+            WipeReason::Server,
+            &cancel
+        )
+        .unwrap());
 
         assert!(!approval.exists());
     }
+
+    #[test]
+    fn library_copies_replace_files_that_do_not_match() {
+        let directory = tempfile::tempdir().unwrap();
+        let stored = directory.path().join("stored.zip");
+        std::fs::write(&stored, b"hello world").unwrap();
+        let path = directory.path().join("rom.zip");
+        std::fs::write(&path, b"hello").unwrap();
+        let hashes = util::hash_cache::HashCache::at(directory.path().join("hashes.json"));
+        let place = || {
+            place_library_copy(
+                &stored,
+                &path,
+                "5eb63bbbe01eeed093cb22bb8f5acdc3",
+                &hashes,
+                &sensitivity::observer::Silent,
+                &CancelToken::new(),
+            )
+        };
+
+        place().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"hello world");
+        place().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"hello world");
+        assert!(!directory.path().join(".rom.zip.tmp").exists());
+    }
 }
@@ -2,16 +2,23 @@
 // Licensed under the GNU AGPL v3.0. See LICENSE file for details.
 // Website: https://chromatic.hu
 
+#[cfg(feature = "usb")]
 use anyhow::{Context, Result};
 
+#[cfg(feature = "usb")]
 use crate::adb::{connect, AdbConnection};
+use crate::secret::Serial;
+#[cfg(feature = "usb")]
 use crate::usb::UsbTransport;
+
 pub mod profile;
+pub mod snapshot;
+pub mod version;
 
-#[derive(Debug, Clone, serde::Serialize)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub struct DeviceInfo {
     pub device: String,
-    pub sn: String,
+    pub sn: Serial,
     pub version: String,
     pub codebase: String,
     pub branch: String,
@@ -20,10 +27,12 @@ pub struct DeviceInfo {
     pub romzone: String,
 }
 
+#[cfg(feature = "usb")]
 pub struct MiClient {
     adb: AdbConnection,
 }
 
+#[cfg(feature = "usb")]
 impl MiClient {
     pub fn new(usb: UsbTransport) -> Result<Self> {
         let adb = connect(usb).context("ADB CONNECT handshake failed")?;
@@ -39,16 +48,18 @@ impl MiClient {
         let language = self.simple_query("getlanguage:")?;
         let region = self.simple_query("getregion:")?;
         let romzone = self.simple_query("getromzone:")?;
-        Ok(DeviceInfo {
+        let info = DeviceInfo {
             device,
-            sn,
+            sn: sn.into(),
             version,
             codebase,
             branch,
             language,
             region,
             romzone,
-        })
+        };
+        tracing::debug!(?info, "device identity");
+        Ok(info)
     }
 
     pub fn simple_query(&mut self, cmd: &str) -> Result<String> {
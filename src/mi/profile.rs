@@ -4,53 +4,115 @@
 
 use anyhow::{bail, Result};
 
-use crate::mi::DeviceInfo;
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
-pub enum RegionProfile {
-    #[value(alias = "mi")]
-    Global,
-    #[value(alias = "eu")]
-    Eea,
-    #[value(alias = "india")]
-    In,
-    #[value(alias = "russia")]
-    Ru,
-    #[value(alias = "indo", alias = "indonesia")]
-    Id,
-    #[value(alias = "turkey")]
-    Tr,
-    #[value(alias = "taiwan")]
-    Tw,
-    #[value(alias = "china")]
-    Cn,
+use crate::mi::{version::RomVersion, DeviceInfo};
+
+/// How to present a device to the validation server as another region's
+/// build. Built-in profiles cover the common regions; more can be defined in
+/// the `[profiles.NAME]` tables of the configuration.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct RegionProfile {
+    pub name: String,
+    /// Device name with `{codename}` in place of the codename, e.g.
+    /// `{codename}_eea_global`.
+    pub device_name: String,
+    /// Two-letter region in the ROM version suffix, e.g. `EU` in `VNREUXM`.
+    pub region_code: String,
+    pub branch: String,
+    pub romzone: Option<String>,
+    pub language: Option<String>,
 }
 
+/// Built-in profiles: name, other accepted names, device-name template and
+/// version region code.
+const BUILTINS: &[(&str, &[&str], &str, &str)] = &[
+    ("global", &["mi"], "{codename}_global", "MI"),
+    ("eea", &["eu"], "{codename}_eea_global", "EU"),
+    ("in", &["india"], "{codename}_in_global", "IN"),
+    ("ru", &["russia"], "{codename}_ru_global", "RU"),
+    ("id", &["indo", "indonesia"], "{codename}_id_global", "ID"),
+    ("tr", &["turkey"], "{codename}_tr_global", "TR"),
+    ("tw", &["taiwan"], "{codename}_tw_global", "TW"),
+    ("cn", &["china"], "{codename}", "CN"),
+];
+
+const DEFAULT_BRANCH: &str = "F";
+
 impl RegionProfile {
-    fn device_name(&self, codename: &str) -> String {
-        match self {
-            RegionProfile::Global => format!("{}_global", codename),
-            RegionProfile::Eea => format!("{}_eea_global", codename),
-            RegionProfile::In => format!("{}_in_global", codename),
-            RegionProfile::Ru => format!("{}_ru_global", codename),
-            RegionProfile::Id => format!("{}_id_global", codename),
-            RegionProfile::Tr => format!("{}_tr_global", codename),
-            RegionProfile::Tw => format!("{}_tw_global", codename),
-            RegionProfile::Cn => codename.to_string(),
+    /// Checks a profile read from data, filling in the default branch.
+    pub fn new(
+        name: &str,
+        device_name: &str,
+        region_code: &str,
+        branch: Option<&str>,
+        romzone: Option<&str>,
+        language: Option<&str>,
+    ) -> Result<Self> {
+        if !device_name.contains("{codename}") {
+            bail!("profile {name}: device_name must contain {{codename}}");
         }
+        if region_code.len() != 2 || !region_code.bytes().all(|byte| byte.is_ascii_uppercase()) {
+            bail!("profile {name}: region_code must be two capital letters, got {region_code:?}");
+        }
+        Ok(Self {
+            name: name.to_string(),
+            device_name: device_name.to_string(),
+            region_code: region_code.to_string(),
+            branch: branch.unwrap_or(DEFAULT_BRANCH).to_string(),
+            romzone: romzone.map(str::to_string),
+            language: language.map(str::to_string),
+        })
+    }
+
+    pub fn builtins() -> Vec<Self> {
+        BUILTINS
+            .iter()
+            .map(|(name, _, device_name, region_code)| Self {
+                name: name.to_string(),
+                device_name: device_name.to_string(),
+                region_code: region_code.to_string(),
+                branch: DEFAULT_BRANCH.to_string(),
+                romzone: None,
+                language: None,
+            })
+            .collect()
     }
 
-    fn version_suffix(&self) -> &'static str {
-        match self {
-            RegionProfile::Global => "MIXM",
-            RegionProfile::Eea => "EUXM",
-            RegionProfile::In => "INXM",
-            RegionProfile::Ru => "RUXM",
-            RegionProfile::Id => "IDXM",
-            RegionProfile::Tr => "TRXM",
-            RegionProfile::Tw => "TWXM",
-            RegionProfile::Cn => "CNXM",
+    /// Finds a profile by name. `custom` profiles come first and may replace
+    /// a built-in one; built-ins also answer to their aliases.
+    pub fn resolve(name: &str, custom: &[Self]) -> Result<Self> {
+        if let Some(profile) = custom
+            .iter()
+            .find(|profile| profile.name.eq_ignore_ascii_case(name))
+        {
+            return Ok(profile.clone());
         }
+        let builtin = BUILTINS.iter().position(|(builtin, aliases, ..)| {
+            builtin.eq_ignore_ascii_case(name)
+                || aliases.iter().any(|alias| alias.eq_ignore_ascii_case(name))
+        });
+        match builtin {
+            Some(index) => Ok(Self::builtins().swap_remove(index)),
+            None => {
+                let mut known: Vec<String> =
+                    BUILTINS.iter().map(|(name, ..)| name.to_string()).collect();
+                known.extend(custom.iter().map(|profile| profile.name.clone()));
+                bail!("unknown profile {name:?}; known: {}", known.join(", "))
+            }
+        }
+    }
+
+    /// Built-ins, with configured profiles replacing or following them.
+    pub fn all(custom: &[Self]) -> Vec<Self> {
+        let mut all: Vec<Self> = Self::builtins()
+            .into_iter()
+            .filter(|builtin| !custom.iter().any(|profile| profile.name == builtin.name))
+            .collect();
+        all.extend(custom.iter().cloned());
+        all
+    }
+
+    fn device_name(&self, codename: &str) -> String {
+        self.device_name.replace("{codename}", codename)
     }
 }
 
@@ -59,22 +121,9 @@ fn derive_codename(device: &str) -> String {
     device.split('_').next().unwrap_or(device).to_string()
 }
 
-fn replace_version_region_suffix(version: &str, new_suffix: &str) -> String {
-    // Expect version like OS2.0.202.0.VNRINXM. Replace last 4 letters with new_suffix.
-    if let Some(dot) = version.rfind('.') {
-        let (head, tail) = version.split_at(dot + 1);
-        // tail like VNRINXM
-        if tail.len() >= 4 {
-            let prefix = &tail[..tail.len().saturating_sub(4)];
-            return format!("{}{}{}", head, prefix, new_suffix);
-        }
-    }
-    version.to_string()
-}
-
 pub fn apply_profile(
     info: &DeviceInfo,
-    profile: RegionProfile,
+    profile: &RegionProfile,
     codename_override: Option<&str>,
 ) -> Result<DeviceInfo> {
     let codename = codename_override
@@ -88,11 +137,19 @@ pub fn apply_profile(
         bail!("invalid device codename: {codename:?}");
     }
     let device = profile.device_name(&codename);
-    let version = replace_version_region_suffix(&info.version, profile.version_suffix());
     let mut out = info.clone();
     out.device = device;
-    out.version = version;
-    out.branch = "F".to_string();
+    // Unrecognized versions are left alone so --override-version can supply one.
+    if let Ok(version) = info.version.parse::<RomVersion>() {
+        out.version = version.with_region(&profile.region_code)?.to_string();
+    }
+    out.branch = profile.branch.clone();
+    if let Some(romzone) = &profile.romzone {
+        out.romzone = romzone.clone();
+    }
+    if let Some(language) = &profile.language {
+        out.language = language.clone();
+    }
     Ok(out)
 }
 
@@ -113,10 +170,14 @@ mod tests {
         }
     }
 
+    fn builtin(name: &str) -> RegionProfile {
+        RegionProfile::resolve(name, &[]).unwrap()
+    }
+
     #[test]
     fn global_profile_changes_only_expected_identity_fields() {
         let original = device_info();
-        let changed = apply_profile(&original, RegionProfile::Global, None).unwrap();
+        let changed = apply_profile(&original, &builtin("global"), None).unwrap();
 
         assert_eq!(changed.device, "garnet_global");
         assert_eq!(changed.version, "OS2.0.202.0.VNRMIXM");
@@ -128,14 +189,54 @@ mod tests {
 
     #[test]
     fn explicit_codename_is_used_for_region_profile() {
-        let changed = apply_profile(&device_info(), RegionProfile::Eea, Some("ruby")).unwrap();
+        let changed = apply_profile(&device_info(), &builtin("eea"), Some("ruby")).unwrap();
         assert_eq!(changed.device, "ruby_eea_global");
         assert!(changed.version.ends_with("EUXM"));
     }
 
+    #[test]
+    fn custom_profiles_fill_the_template_and_overrides() {
+        let japan = RegionProfile::new(
+            "jp",
+            "{codename}_jp_global",
+            "JP",
+            Some("X"),
+            Some("2"),
+            Some("ja"),
+        )
+        .unwrap();
+        let changed = apply_profile(&device_info(), &japan, None).unwrap();
+        assert_eq!(changed.device, "garnet_jp_global");
+        assert_eq!(changed.version, "OS2.0.202.0.VNRJPXM");
+        assert_eq!(
+            (
+                changed.branch.as_str(),
+                changed.romzone.as_str(),
+                changed.language.as_str()
+            ),
+            ("X", "2", "ja")
+        );
+        assert_eq!(
+            RegionProfile::resolve("JP", std::slice::from_ref(&japan)).unwrap(),
+            japan
+        );
+        assert_eq!(builtin("indonesia").name, "id");
+        assert!(RegionProfile::resolve("jp", &[]).is_err());
+        assert!(RegionProfile::new("bad", "garnet_global", "JP", None, None, None).is_err());
+        assert!(RegionProfile::new("bad", "{codename}", "jpn", None, None, None).is_err());
+    }
+
     #[test]
     fn unsafe_or_empty_codename_is_rejected() {
-        assert!(apply_profile(&device_info(), RegionProfile::Global, Some("")).is_err());
-        assert!(apply_profile(&device_info(), RegionProfile::Global, Some("bad value")).is_err());
+        assert!(apply_profile(&device_info(), &builtin("global"), Some("")).is_err());
+        assert!(apply_profile(&device_info(), &builtin("global"), Some("bad value")).is_err());
+    }
+
+    #[test]
+    fn miui_version_region_is_replaced_without_touching_the_device_code() {
+        let mut info = device_info();
+        info.version = "V14.0.8.0.TKHINXM".into();
+        let changed = apply_profile(&info, &builtin("ru"), None).unwrap();
+        assert_eq!(changed.version, "V14.0.8.0.TKHRUXM");
     }
 }
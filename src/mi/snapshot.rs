@@ -0,0 +1,95 @@
+// Copyright (C) 2026 Chromatic
+// Licensed under the GNU AGPL v3.0. See LICENSE file for details.
+// Website: https://chromatic.hu
+
+//! Device identities saved to a file, so server queries and downloads can
+//! run on a machine without the phone.
+
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Result};
+
+use crate::mi::DeviceInfo;
+use crate::secret::{self, Serial};
+
+/// Version of the file layout; older readers refuse newer files.
+pub const FORMAT: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct IdentitySnapshot {
+    pub format: u32,
+    /// Seconds since the Unix epoch.
+    pub captured_at: u64,
+    /// Whether `identity.sn` was masked before saving.
+    pub serial_redacted: bool,
+    pub identity: DeviceInfo,
+}
+
+impl IdentitySnapshot {
+    pub fn capture(mut identity: DeviceInfo, redact: bool) -> Self {
+        if redact {
+            identity.sn = Serial::new(identity.sn.masked());
+        }
+        Self {
+            format: FORMAT,
+            captured_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_secs()),
+            serial_redacted: redact,
+            identity,
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let json = secret::with_revealed(|| serde_json::to_string_pretty(self))?;
+        fs::write(path, json + "\n").with_context(|| format!("Writing {}", path.display()))
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let text =
+            fs::read_to_string(path).with_context(|| format!("Reading {}", path.display()))?;
+        let snapshot: Self = serde_json::from_str(&text)
+            .with_context(|| format!("{} is not an identity file", path.display()))?;
+        if snapshot.format > FORMAT {
+            bail!(
+                "{} uses identity format {}; this build reads up to {FORMAT}",
+                path.display(),
+                snapshot.format
+            );
+        }
+        Ok(snapshot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshots_round_trip_with_a_masked_serial() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("identity.json");
+        let info = DeviceInfo {
+            device: "garnet".into(),
+            sn: "0123456789ab".into(),
+            version: "OS2.0.202.0.VNRINXM".into(),
+            ..DeviceInfo::default()
+        };
+        IdentitySnapshot::capture(info.clone(), true)
+            .save(&path)
+            .unwrap();
+
+        let loaded = IdentitySnapshot::load(&path).unwrap();
+        assert!(loaded.serial_redacted);
+        assert_eq!(loaded.identity.sn.reveal(), "********89ab");
+        assert_eq!(loaded.identity.version, info.version);
+
+        let newer = fs::read_to_string(&path)
+            .unwrap()
+            .replace("\"format\": 1", "\"format\": 2");
+        fs::write(&path, newer).unwrap();
+        assert!(IdentitySnapshot::load(&path).is_err());
+    }
+}
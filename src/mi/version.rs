@@ -0,0 +1,258 @@
+// Copyright (C) 2026 Chromatic
+// Licensed under the GNU AGPL v3.0. See LICENSE file for details.
+// Website: https://chromatic.hu
+
+//! Typed HyperOS and MIUI build versions.
+//!
+//! `OS2.0.202.0.VNRINXM` reads as HyperOS 2.0.202.0 built on Android V for
+//! device code `NR`, region `IN` and channel `XM`; `V14.0.8.0.TKHMIXM` is the
+//! MIUI equivalent. Versions order by family, release numbers and Android
+//! letter; the device, region and channel only break ties so ordering stays
+//! consistent with equality. Use [`RomVersion::same_track`] before treating an
+//! ordering as an upgrade or downgrade.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{bail, Context, Error, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OsFamily {
+    Miui,
+    HyperOs,
+}
+
+impl OsFamily {
+    fn prefix(self) -> &'static str {
+        match self {
+            OsFamily::Miui => "V",
+            OsFamily::HyperOs => "OS",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RomVersion {
+    pub family: OsFamily,
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+    pub build: u32,
+    /// Android release letter, e.g. `V` for Android 15.
+    pub android: char,
+    pub device: String,
+    pub region: String,
+    pub channel: String,
+}
+
+impl RomVersion {
+    /// Region code as it appears in the version, e.g. `MI`, `EU` or `IN`.
+    pub fn region(&self) -> &str {
+        &self.region
+    }
+
+    /// Same version rebuilt for another region code.
+    pub fn with_region(&self, region: &str) -> Result<Self> {
+        if region.len() != 2 || !region.bytes().all(|byte| byte.is_ascii_uppercase()) {
+            bail!("invalid ROM region code: {region:?}");
+        }
+        Ok(Self {
+            region: region.to_string(),
+            ..self.clone()
+        })
+    }
+
+    /// Whether both builds belong to the same device, region and channel,
+    /// the only case where ordering means upgrade or downgrade.
+    pub fn same_track(&self, other: &Self) -> bool {
+        self.device == other.device && self.region == other.region && self.channel == other.channel
+    }
+
+    /// Whether this is a later release, ignoring device, region and channel.
+    pub fn is_newer_than(&self, other: &Self) -> bool {
+        self.release() > other.release()
+    }
+
+    /// Finds the version inside a package name such as
+    /// `miui_GARNETINGlobal_OS2.0.202.0.VNRINXM_4c9d2f1e_15.0.zip`.
+    pub fn from_package_name(name: &str) -> Option<Self> {
+        let name = name.rsplit(['/', '\\']).next().unwrap_or(name);
+        let name = name.split('?').next().unwrap_or(name);
+        name.split('_').find_map(|part| part.parse().ok())
+    }
+
+    fn release(&self) -> (OsFamily, u32, u32, u32, u32, char) {
+        (
+            self.family,
+            self.major,
+            self.minor,
+            self.patch,
+            self.build,
+            self.android,
+        )
+    }
+}
+
+impl FromStr for RomVersion {
+    type Err = Error;
+
+    fn from_str(text: &str) -> Result<Self> {
+        let (family, rest) = if let Some(rest) = text.strip_prefix("OS") {
+            (OsFamily::HyperOs, rest)
+        } else if let Some(rest) = text.strip_prefix('V') {
+            (OsFamily::Miui, rest)
+        } else {
+            bail!("unrecognized ROM version: {text:?}");
+        };
+        let parts: Vec<&str> = rest.split('.').collect();
+        let [major, minor, patch, build, suffix] = parts[..] else {
+            bail!("ROM version {text:?} does not have four numbers and a suffix");
+        };
+        let number = |part: &str| {
+            if part.is_empty() || !part.bytes().all(|byte| byte.is_ascii_digit()) {
+                bail!("ROM version {text:?} has a non-numeric component {part:?}");
+            }
+            part.parse::<u32>()
+                .with_context(|| format!("ROM version {text:?} component {part:?}"))
+        };
+        // Android letter, device code (two or three letters), region, channel.
+        if !(6..=8).contains(&suffix.len()) || !suffix.bytes().all(|byte| byte.is_ascii_uppercase())
+        {
+            bail!("ROM version {text:?} has an unrecognized suffix {suffix:?}");
+        }
+        let (android, rest) = suffix.split_at(1);
+        let (device, rest) = rest.split_at(rest.len() - 4);
+        let (region, channel) = rest.split_at(2);
+        Ok(Self {
+            family,
+            major: number(major)?,
+            minor: number(minor)?,
+            patch: number(patch)?,
+            build: number(build)?,
+            android: android.chars().next().unwrap_or_default(),
+            device: device.to_string(),
+            region: region.to_string(),
+            channel: channel.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for RomVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}{}.{}.{}.{}.{}{}{}{}",
+            self.family.prefix(),
+            self.major,
+            self.minor,
+            self.patch,
+            self.build,
+            self.android,
+            self.device,
+            self.region,
+            self.channel
+        )
+    }
+}
+
+impl Ord for RomVersion {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.release()
+            .cmp(&other.release())
+            .then_with(|| self.device.cmp(&other.device))
+            .then_with(|| self.region.cmp(&other.region))
+            .then_with(|| self.channel.cmp(&other.channel))
+    }
+}
+
+impl PartialOrd for RomVersion {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl serde::Serialize for RomVersion {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(text: &str) -> RomVersion {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn hyperos_and_miui_versions_parse_into_fields() {
+        let hyperos = version("OS2.0.202.0.VNRINXM");
+        assert_eq!(hyperos.family, OsFamily::HyperOs);
+        assert_eq!(
+            (hyperos.major, hyperos.minor, hyperos.patch, hyperos.build),
+            (2, 0, 202, 0)
+        );
+        assert_eq!(hyperos.android, 'V');
+        assert_eq!(hyperos.device, "NR");
+        assert_eq!(hyperos.region(), "IN");
+        assert_eq!(hyperos.channel, "XM");
+        assert_eq!(hyperos.to_string(), "OS2.0.202.0.VNRINXM");
+
+        let miui = version("V14.0.8.0.TKHMIXM");
+        assert_eq!(miui.family, OsFamily::Miui);
+        assert_eq!((miui.major, miui.patch), (14, 8));
+        assert_eq!((miui.android, miui.device.as_str()), ('T', "KH"));
+        assert_eq!(miui.region(), "MI");
+        assert_eq!(miui.to_string(), "V14.0.8.0.TKHMIXM");
+    }
+
+    #[test]
+    fn malformed_versions_are_rejected() {
+        for text in [
+            "",
+            "OS2.0.202.VNRINXM",
+            "OS2.0.x.0.VNRINXM",
+            "OS2.0.202.0.vnrinxm",
+            "OS2.0.202.0.XM",
+            "14.0.8.0.TKHMIXM",
+        ] {
+            assert!(text.parse::<RomVersion>().is_err(), "{text}");
+        }
+    }
+
+    #[test]
+    fn ordering_compares_release_numbers_numerically() {
+        assert!(version("OS2.0.202.0.VNRINXM") > version("OS2.0.31.0.VNRINXM"));
+        assert!(version("OS1.0.1.0.UNRINXM") > version("V14.0.8.0.TNRINXM"));
+        assert!(version("OS2.0.1.0.VNRINXM") > version("OS2.0.1.0.UNRINXM"));
+        assert_eq!(
+            version("OS2.0.1.0.VNRINXM").cmp(&version("OS2.0.1.0.VNRINXM")),
+            Ordering::Equal
+        );
+        assert_ne!(version("OS2.0.1.0.VNRINXM"), version("OS2.0.1.0.VNRMIXM"));
+        assert!(!version("OS2.0.1.0.VNRMIXM").is_newer_than(&version("OS2.0.1.0.VNRINXM")));
+    }
+
+    #[test]
+    fn region_changes_keep_the_release_and_break_the_track() {
+        let india = version("OS2.0.202.0.VNRINXM");
+        let global = india.with_region("MI").unwrap();
+        assert_eq!(global.to_string(), "OS2.0.202.0.VNRMIXM");
+        assert!(!india.same_track(&global));
+        assert!(india.with_region("mi").is_err());
+    }
+
+    #[test]
+    fn version_is_found_in_package_names() {
+        assert_eq!(
+            RomVersion::from_package_name(
+                "https://example.invalid/miui_GARNETINGlobal_OS2.0.202.0.VNRINXM_4c9d2f1e_15.0.zip?t=1"
+            ),
+            Some(version("OS2.0.202.0.VNRINXM"))
+        );
+        assert_eq!(RomVersion::from_package_name("rom.zip"), None);
+    }
+}
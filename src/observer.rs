@@ -0,0 +1,101 @@
+// Copyright (C) 2026 Chromatic
+// Licensed under the GNU AGPL v3.0. See LICENSE file for details.
+// Website: https://chromatic.hu
+
+//! Progress reporting for long-running operations. The library calls an
+//! [`Observer`]; the CLI, GUI and machine mode each decide how to render it.
+
+#[cfg(feature = "download")]
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    Download,
+    Hash,
+    Validate,
+    Sideload,
+}
+
+/// Bytes one mirror delivered into a download.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct MirrorSegment {
+    pub mirror: String,
+    pub start: u64,
+    pub end: u64,
+}
+
+/// Receives progress from library operations. Calls may come from worker
+/// threads, so implementations use interior mutability.
+pub trait Observer: Send + Sync {
+    /// An operation entered `phase`.
+    fn phase(&self, _phase: Phase) {}
+    /// `current` of `total` bytes are done; `total` may be 0 while unknown.
+    fn progress(&self, _phase: Phase, _current: u64, _total: u64) {}
+    fn message(&self, _message: &str) {}
+    /// A mirror delivered a byte range of a download.
+    fn mirror_segment(&self, _segment: &MirrorSegment) {}
+}
+
+/// Ignores everything.
+pub struct Silent;
+
+impl Observer for Silent {}
+
+/// A byte count shared between threads that reports every change.
+#[cfg(feature = "download")]
+pub(crate) struct Counter<'a> {
+    observer: &'a dyn Observer,
+    phase: Phase,
+    current: AtomicU64,
+    total: AtomicU64,
+}
+
+#[cfg(feature = "download")]
+impl<'a> Counter<'a> {
+    pub(crate) fn new(observer: &'a dyn Observer, phase: Phase) -> Self {
+        Self {
+            observer,
+            phase,
+            current: AtomicU64::new(0),
+            total: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn observer(&self) -> &'a dyn Observer {
+        self.observer
+    }
+
+    fn report(&self) {
+        self.observer.progress(
+            self.phase,
+            self.current.load(Ordering::Relaxed),
+            self.total.load(Ordering::Relaxed),
+        );
+    }
+
+    pub(crate) fn set_total(&self, total: u64) {
+        self.total.store(total, Ordering::Relaxed);
+        self.report();
+    }
+
+    pub(crate) fn set(&self, current: u64) {
+        self.current.store(current, Ordering::Relaxed);
+        self.report();
+    }
+
+    pub(crate) fn add(&self, bytes: u64) {
+        self.current.fetch_add(bytes, Ordering::Relaxed);
+        self.report();
+    }
+
+    /// Takes back bytes that will be transferred again.
+    pub(crate) fn sub(&self, bytes: u64) {
+        let _ = self
+            .current
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |current| {
+                Some(current.saturating_sub(bytes))
+            });
+        self.report();
+    }
+}
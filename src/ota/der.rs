@@ -0,0 +1,148 @@
+// Copyright (C) 2026 Chromatic
+// Licensed under the GNU AGPL v3.0. See LICENSE file for details.
+// Website: https://chromatic.hu
+
+//! Minimal DER reader for the PKCS#7 and X.509 structures used by OTA
+//! signatures. Only definite lengths are accepted, as DER requires.
+
+use anyhow::{bail, Result};
+
+pub const SEQUENCE: u8 = 0x30;
+pub const SET: u8 = 0x31;
+pub const INTEGER: u8 = 0x02;
+pub const OCTET_STRING: u8 = 0x04;
+pub const OBJECT_IDENTIFIER: u8 = 0x06;
+pub const CONTEXT_0: u8 = 0xa0;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Tlv<'a> {
+    pub tag: u8,
+    pub content: &'a [u8],
+    /// The complete encoding including tag and length bytes.
+    pub raw: &'a [u8],
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Reader<'a> {
+    input: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(input: &'a [u8]) -> Self {
+        Self { input }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.input.is_empty()
+    }
+
+    pub fn peek_tag(&self) -> Option<u8> {
+        self.input.first().copied()
+    }
+
+    pub fn next(&mut self) -> Result<Tlv<'a>> {
+        let input = self.input;
+        if input.len() < 2 {
+            bail!("DER element truncated");
+        }
+        let tag = input[0];
+        if tag & 0x1f == 0x1f {
+            bail!("DER high-tag-number form is not supported");
+        }
+        let (length, header) = match input[1] {
+            short if short < 0x80 => (short as usize, 2),
+            0x80 => bail!("DER indefinite length is not allowed"),
+            long => {
+                let count = (long & 0x7f) as usize;
+                if count > 4 || input.len() < 2 + count {
+                    bail!("DER length field truncated or too large");
+                }
+                let length = input[2..2 + count]
+                    .iter()
+                    .fold(0usize, |value, byte| (value << 8) | *byte as usize);
+                (length, 2 + count)
+            }
+        };
+        let end = header
+            .checked_add(length)
+            .filter(|end| *end <= input.len())
+            .ok_or_else(|| anyhow::anyhow!("DER element length exceeds its container"))?;
+        self.input = &input[end..];
+        Ok(Tlv {
+            tag,
+            content: &input[header..end],
+            raw: &input[..end],
+        })
+    }
+
+    pub fn expect(&mut self, tag: u8) -> Result<Tlv<'a>> {
+        let element = self.next()?;
+        if element.tag != tag {
+            bail!(
+                "unexpected DER tag {:#04x}; expected {:#04x}",
+                element.tag,
+                tag
+            );
+        }
+        Ok(element)
+    }
+
+    /// Reads an element only when it carries the given tag.
+    pub fn optional(&mut self, tag: u8) -> Result<Option<Tlv<'a>>> {
+        if self.peek_tag() == Some(tag) {
+            self.next().map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+impl<'a> Tlv<'a> {
+    pub fn reader(&self) -> Reader<'a> {
+        Reader::new(self.content)
+    }
+}
+
+/// Encodes dotted object identifier text for comparison with DER content.
+pub fn oid(text: &str) -> Vec<u8> {
+    let arcs: Vec<u64> = text.split('.').filter_map(|arc| arc.parse().ok()).collect();
+    let mut out = Vec::new();
+    if arcs.len() < 2 {
+        return out;
+    }
+    let mut push = |mut value: u64| {
+        let mut bytes = vec![(value & 0x7f) as u8];
+        value >>= 7;
+        while value > 0 {
+            bytes.push(0x80 | (value & 0x7f) as u8);
+            value >>= 7;
+        }
+        out.extend(bytes.iter().rev());
+    };
+    push(arcs[0] * 40 + arcs[1]);
+    for arc in &arcs[2..] {
+        push(*arc);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn object_identifiers_encode_multi_byte_arcs() {
+        assert_eq!(
+            oid("1.2.840.113549.1.7.2"),
+            [0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x07, 0x02]
+        );
+    }
+
+    #[test]
+    fn lengths_beyond_the_container_are_rejected() {
+        assert!(Reader::new(&[SEQUENCE, 0x82, 0xff, 0xff, 0x00])
+            .next()
+            .is_err());
+        assert!(Reader::new(&[SEQUENCE, 0x80]).next().is_err());
+    }
+}
@@ -0,0 +1,436 @@
+// Copyright (C) 2026 Chromatic
+// Licensed under the GNU AGPL v3.0. See LICENSE file for details.
+// Website: https://chromatic.hu
+
+//! Offline verification of the whole-file signature on Android OTA packages.
+//!
+//! Stock recovery checks a PKCS#7 signature stored in the ZIP comment before
+//! installing a package. Checking the same footer locally rejects tampered or
+//! truncated files before they cost a validation request or a sideload.
+
+mod der;
+
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context, Result};
+use base64::{engine::general_purpose, Engine as _};
+use byteorder::{ByteOrder, LittleEndian};
+use rsa::pkcs8::DecodePublicKey;
+use rsa::{Pkcs1v15Sign, RsaPublicKey};
+use sha1::Digest;
+
+use crate::i18n::trf;
+use der::{Reader, Tlv};
+
+const FOOTER_SIZE: usize = 6;
+const EOCD_SIZE: usize = 22;
+const EOCD_MAGIC: &[u8; 4] = b"PK\x05\x06";
+const MAX_TAIL: u64 = (u16::MAX as usize + EOCD_SIZE) as u64;
+
+/// Bundled release certificates; see the notes at the top of the file.
+const BUNDLED_CERTIFICATES: &str = include_str!("otacerts.pem");
+
+const OID_SIGNED_DATA: &str = "1.2.840.113549.1.7.2";
+const OID_SHA1: &str = "1.3.14.3.2.26";
+const OID_SHA256: &str = "2.16.840.1.101.3.4.2.1";
+const OID_COMMON_NAME: &str = "2.5.4.3";
+
+// DER DigestInfo prefixes for RSASSA-PKCS1-v1_5 (RFC 8017, section 9.2).
+const SHA1_DIGEST_INFO: &[u8] = &[
+    0x30, 0x21, 0x30, 0x09, 0x06, 0x05, 0x2b, 0x0e, 0x03, 0x02, 0x1a, 0x05, 0x00, 0x04, 0x14,
+];
+const SHA256_DIGEST_INFO: &[u8] = &[
+    0x30, 0x31, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01, 0x05,
+    0x00, 0x04, 0x20,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DigestAlgorithm {
+    Sha1,
+    Sha256,
+}
+
+impl DigestAlgorithm {
+    fn from_oid(oid: &[u8]) -> Result<Self> {
+        if oid == der::oid(OID_SHA1).as_slice() {
+            Ok(Self::Sha1)
+        } else if oid == der::oid(OID_SHA256).as_slice() {
+            Ok(Self::Sha256)
+        } else {
+            bail!("OTA signature uses an unsupported digest algorithm")
+        }
+    }
+
+    fn scheme(self) -> Pkcs1v15Sign {
+        let (hash_len, prefix) = match self {
+            Self::Sha1 => (20, SHA1_DIGEST_INFO),
+            Self::Sha256 => (32, SHA256_DIGEST_INFO),
+        };
+        Pkcs1v15Sign {
+            hash_len: Some(hash_len),
+            prefix: prefix.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for DigestAlgorithm {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        formatter.write_str(match self {
+            Self::Sha1 => "SHA-1",
+            Self::Sha256 => "SHA-256",
+        })
+    }
+}
+
+/// Location of the signature inside the archive comment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SignatureFooter {
+    pub comment_len: usize,
+    pub signature_start: usize,
+    /// Leading bytes covered by the signature: everything except the comment
+    /// and its two-byte length field.
+    pub signed_len: u64,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct SignatureReport {
+    pub digest: DigestAlgorithm,
+    /// Common name of the certificate whose key verified the signature.
+    pub signer: String,
+    /// Whether that certificate is a trusted release certificate.
+    pub trusted: bool,
+    /// Whether any trusted certificates were available. Without them an
+    /// intact signature says nothing about who made it.
+    pub checked: bool,
+}
+
+impl SignatureReport {
+    /// Refuses a package whose signer was checked and is not a release
+    /// certificate, unless `allow_unknown` overrides that.
+    pub fn require_known_signer(&self, allow_unknown: bool) -> Result<()> {
+        if self.checked && !self.trusted && !allow_unknown {
+            bail!(
+                "{}",
+                trf(
+                    "error.signature_unknown_signer",
+                    &[("{signer}", &self.signer)]
+                )
+            );
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Certificate {
+    pub subject: String,
+    key: RsaPublicKey,
+}
+
+impl Certificate {
+    pub fn from_der(bytes: &[u8]) -> Result<Self> {
+        let certificate = Reader::new(bytes).expect(der::SEQUENCE)?;
+        let mut tbs = certificate.reader().expect(der::SEQUENCE)?.reader();
+        tbs.optional(der::CONTEXT_0)?;
+        tbs.expect(der::INTEGER)?;
+        tbs.expect(der::SEQUENCE)?;
+        tbs.expect(der::SEQUENCE)?;
+        tbs.expect(der::SEQUENCE)?;
+        let subject = common_name(tbs.expect(der::SEQUENCE)?)?;
+        let spki = tbs.expect(der::SEQUENCE)?;
+        let key = RsaPublicKey::from_public_key_der(spki.raw)
+            .map_err(|_| anyhow!("OTA certificate {subject:?} does not carry an RSA key"))?;
+        Ok(Self { subject, key })
+    }
+
+    /// Parses every `CERTIFICATE` block in PEM text, as found in `otacerts`.
+    pub fn from_pem(text: &str) -> Result<Vec<Self>> {
+        let mut certificates = Vec::new();
+        let mut rest = text;
+        while let Some(start) = rest.find("-----BEGIN CERTIFICATE-----") {
+            let body = &rest[start + "-----BEGIN CERTIFICATE-----".len()..];
+            let end = body
+                .find("-----END CERTIFICATE-----")
+                .ok_or_else(|| anyhow!("PEM certificate is missing its END line"))?;
+            let encoded: String = body[..end].split_whitespace().collect();
+            let bytes = general_purpose::STANDARD
+                .decode(encoded)
+                .context("Decoding PEM certificate")?;
+            certificates.push(Self::from_der(&bytes)?);
+            rest = &body[end..];
+        }
+        Ok(certificates)
+    }
+
+    fn verifies(&self, digest: DigestAlgorithm, hashed: &[u8], signature: &[u8]) -> bool {
+        self.key.verify(digest.scheme(), hashed, signature).is_ok()
+    }
+}
+
+/// Bundled release certificates plus any PEM file named by
+/// `SENSITIVITY_OTACERTS`.
+pub fn trusted_certificates() -> Result<Vec<Certificate>> {
+    let mut certificates =
+        Certificate::from_pem(BUNDLED_CERTIFICATES).context("Parsing bundled OTA certificates")?;
+    if let Some(path) = std::env::var_os("SENSITIVITY_OTACERTS") {
+        let path = Path::new(&path);
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Reading OTA certificates from {}", path.display()))?;
+        certificates.extend(Certificate::from_pem(&text)?);
+    }
+    Ok(certificates)
+}
+
+/// Verifies a package against the trusted release certificates.
+pub fn verify_package(path: &Path) -> Result<SignatureReport> {
+    verify_package_with(path, &trusted_certificates()?)
+}
+
+pub fn verify_package_with(path: &Path, trusted: &[Certificate]) -> Result<SignatureReport> {
+    let mut file = File::open(path).with_context(|| format!("Opening {}", path.display()))?;
+    let file_len = file.metadata()?.len();
+    let tail_len = file_len.min(MAX_TAIL);
+    let mut tail = vec![0u8; tail_len as usize];
+    file.seek(SeekFrom::Start(file_len - tail_len))?;
+    file.read_exact(&mut tail)?;
+
+    let footer = parse_footer(&tail, file_len)?;
+    let block = &tail[tail.len() - footer.signature_start..tail.len() - FOOTER_SIZE];
+    let signed = parse_signed_data(block).context("Parsing the OTA signature block")?;
+
+    file.seek(SeekFrom::Start(0))?;
+    let hashed = hash_prefix(&mut file, footer.signed_len, signed.digest)
+        .with_context(|| format!("Hashing {}", path.display()))?;
+
+    if let Some(certificate) = trusted
+        .iter()
+        .find(|certificate| certificate.verifies(signed.digest, &hashed, &signed.signature))
+    {
+        return Ok(SignatureReport {
+            digest: signed.digest,
+            signer: certificate.subject.clone(),
+            trusted: true,
+            checked: true,
+        });
+    }
+    // An intact signature from an unknown key is different from a package
+    // modified after signing, so check the certificate the package carries.
+    for der in &signed.certificates {
+        let Ok(certificate) = Certificate::from_der(der) else {
+            continue;
+        };
+        if certificate.verifies(signed.digest, &hashed, &signed.signature) {
+            return Ok(SignatureReport {
+                digest: signed.digest,
+                signer: certificate.subject,
+                trusted: false,
+                checked: !trusted.is_empty(),
+            });
+        }
+    }
+    bail!("OTA signature does not match the package contents; the file was modified or damaged after signing")
+}
+
+fn parse_footer(tail: &[u8], file_len: u64) -> Result<SignatureFooter> {
+    if tail.len() < EOCD_SIZE + FOOTER_SIZE {
+        bail!("Package is too small to carry an OTA signature");
+    }
+    let footer = &tail[tail.len() - FOOTER_SIZE..];
+    if footer[2] != 0xff || footer[3] != 0xff {
+        bail!("Package has no whole-file OTA signature footer; it is unsigned or truncated");
+    }
+    let signature_start = LittleEndian::read_u16(&footer[0..2]) as usize;
+    let comment_len = LittleEndian::read_u16(&footer[4..6]) as usize;
+    if signature_start <= FOOTER_SIZE || signature_start > comment_len {
+        bail!("OTA signature footer is malformed");
+    }
+    let eocd_len = comment_len + EOCD_SIZE;
+    if eocd_len > tail.len() {
+        bail!("OTA signature footer points outside the package; it may be truncated");
+    }
+    let eocd = &tail[tail.len() - eocd_len..];
+    if &eocd[..4] != EOCD_MAGIC {
+        bail!("ZIP end record is not where the OTA signature footer expects it; the package may be truncated");
+    }
+    // Recovery rejects a second end record hidden inside the signed comment.
+    if eocd[4..].windows(4).any(|window| window == EOCD_MAGIC) {
+        bail!("ZIP comment contains an unexpected end record");
+    }
+    if LittleEndian::read_u16(&eocd[20..22]) as usize != comment_len {
+        bail!("ZIP comment length does not match the OTA signature footer");
+    }
+    let directory_size = LittleEndian::read_u32(&eocd[12..16]) as u64;
+    let directory_offset = LittleEndian::read_u32(&eocd[16..20]) as u64;
+    let eocd_offset = file_len - eocd_len as u64;
+    if directory_offset.saturating_add(directory_size) > eocd_offset {
+        bail!("ZIP central directory extends past its end record; the package is truncated");
+    }
+    Ok(SignatureFooter {
+        comment_len,
+        signature_start,
+        signed_len: file_len - comment_len as u64 - 2,
+    })
+}
+
+struct SignedData {
+    digest: DigestAlgorithm,
+    signature: Vec<u8>,
+    certificates: Vec<Vec<u8>>,
+}
+
+fn parse_signed_data(block: &[u8]) -> Result<SignedData> {
+    let mut content_info = Reader::new(block).expect(der::SEQUENCE)?.reader();
+    let content_type = content_info.expect(der::OBJECT_IDENTIFIER)?;
+    if content_type.content != der::oid(OID_SIGNED_DATA).as_slice() {
+        bail!("signature block is not PKCS#7 signed data");
+    }
+    let explicit = content_info.expect(der::CONTEXT_0)?;
+    let mut signed_data = explicit.reader().expect(der::SEQUENCE)?.reader();
+    signed_data.expect(der::INTEGER)?;
+    signed_data.expect(der::SET)?;
+    signed_data.expect(der::SEQUENCE)?;
+
+    let mut certificates = Vec::new();
+    if let Some(list) = signed_data.optional(der::CONTEXT_0)? {
+        let mut reader = list.reader();
+        while !reader.is_empty() {
+            certificates.push(reader.next()?.raw.to_vec());
+        }
+    }
+    signed_data.optional(0xa1)?;
+
+    let signer_infos = signed_data.expect(der::SET)?;
+    let mut signer_info = signer_infos.reader().expect(der::SEQUENCE)?.reader();
+    signer_info.expect(der::INTEGER)?;
+    signer_info.expect(der::SEQUENCE)?;
+    let digest_algorithm = signer_info.expect(der::SEQUENCE)?;
+    let digest = DigestAlgorithm::from_oid(
+        digest_algorithm
+            .reader()
+            .expect(der::OBJECT_IDENTIFIER)?
+            .content,
+    )?;
+    if signer_info.optional(der::CONTEXT_0)?.is_some() {
+        bail!("signed attributes are not used by OTA whole-file signatures");
+    }
+    signer_info.expect(der::SEQUENCE)?;
+    let signature = signer_info.expect(der::OCTET_STRING)?.content.to_vec();
+    Ok(SignedData {
+        digest,
+        signature,
+        certificates,
+    })
+}
+
+fn common_name(name: Tlv<'_>) -> Result<String> {
+    let mut rdns = name.reader();
+    while !rdns.is_empty() {
+        let mut attributes = rdns.expect(der::SET)?.reader();
+        while !attributes.is_empty() {
+            let mut attribute = attributes.expect(der::SEQUENCE)?.reader();
+            let oid = attribute.expect(der::OBJECT_IDENTIFIER)?;
+            let value = attribute.next()?;
+            if oid.content == der::oid(OID_COMMON_NAME).as_slice() {
+                return Ok(String::from_utf8_lossy(value.content).into_owned());
+            }
+        }
+    }
+    Ok("unnamed certificate".to_string())
+}
+
+fn hash_prefix(reader: &mut impl Read, len: u64, digest: DigestAlgorithm) -> Result<Vec<u8>> {
+    let mut limited = reader.take(len);
+    let mut buf = vec![0u8; 1024 * 1024];
+    let mut sha1 = sha1::Sha1::new();
+    let mut sha256 = sha2::Sha256::new();
+    let mut remaining = len;
+    while remaining > 0 {
+        let n = limited.read(&mut buf)?;
+        if n == 0 {
+            bail!("Package ended before the signed range");
+        }
+        match digest {
+            DigestAlgorithm::Sha1 => sha1.update(&buf[..n]),
+            DigestAlgorithm::Sha256 => sha256.update(&buf[..n]),
+        }
+        remaining -= n as u64;
+    }
+    Ok(match digest {
+        DigestAlgorithm::Sha1 => sha1.finalize().to_vec(),
+        DigestAlgorithm::Sha256 => sha256.finalize().to_vec(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    const SIGNED: &[u8] = include_bytes!("testdata/signed-ota.zip");
+    const TEST_CERTIFICATE: &str = include_str!("testdata/testkey.x509.pem");
+    const OTHER_CERTIFICATE: &str = include_str!("testdata/otherkey.x509.pem");
+
+    fn package(bytes: &[u8]) -> NamedTempFile {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(bytes).unwrap();
+        file
+    }
+
+    #[test]
+    fn signature_from_a_trusted_certificate_is_accepted() {
+        let trusted = Certificate::from_pem(TEST_CERTIFICATE).unwrap();
+        let report = verify_package_with(package(SIGNED).path(), &trusted).unwrap();
+
+        assert!(report.trusted && report.checked);
+        assert_eq!(report.digest, DigestAlgorithm::Sha256);
+        assert_eq!(report.signer, "Sensitivity Test OTA Key");
+    }
+
+    #[test]
+    fn intact_signature_from_an_unknown_key_is_reported_untrusted() {
+        let report = verify_package_with(package(SIGNED).path(), &[]).unwrap();
+        assert!(!report.trusted && !report.checked);
+        assert_eq!(report.signer, "Sensitivity Test OTA Key");
+
+        let other = Certificate::from_pem(OTHER_CERTIFICATE).unwrap();
+        let report = verify_package_with(package(SIGNED).path(), &other).unwrap();
+        assert!(!report.trusted && report.checked);
+        assert!(report.require_known_signer(false).is_err());
+        assert!(report.require_known_signer(true).is_ok());
+    }
+
+    #[test]
+    fn bundled_certificates_parse() {
+        Certificate::from_pem(BUNDLED_CERTIFICATES).unwrap();
+    }
+
+    #[test]
+    fn modified_package_contents_are_rejected() {
+        let mut tampered = SIGNED.to_vec();
+        tampered[100] ^= 0x01;
+        let trusted = Certificate::from_pem(TEST_CERTIFICATE).unwrap();
+
+        let error = verify_package_with(package(&tampered).path(), &trusted).unwrap_err();
+        assert!(error.to_string().contains("does not match"));
+    }
+
+    #[test]
+    fn truncated_package_is_rejected_before_hashing() {
+        let error =
+            verify_package_with(package(&SIGNED[..SIGNED.len() - 40]).path(), &[]).unwrap_err();
+        assert!(error.to_string().contains("footer"));
+    }
+
+    #[test]
+    fn footer_covers_everything_but_the_comment() {
+        let footer = parse_footer(SIGNED, SIGNED.len() as u64).unwrap();
+        assert_eq!(
+            footer.signed_len,
+            (SIGNED.len() - footer.comment_len - 2) as u64
+        );
+    }
+}
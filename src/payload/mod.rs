@@ -0,0 +1,626 @@
+// Copyright (C) 2026 Chromatic
+// Licensed under the GNU AGPL v3.0. See LICENSE file for details.
+// Website: https://chromatic.hu
+
+//! A/B `payload.bin` parsing for update_engine Recovery ROMs.
+//!
+//! A payload starts with a `CrAU` header and a protobuf `DeltaArchiveManifest`
+//! that lists every partition and the operations producing it. Operation
+//! blobs carry SHA-256 hashes, so a payload can be checked offline without
+//! applying it.
+
+mod proto;
+
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use base64::{engine::general_purpose, Engine as _};
+use byteorder::{BigEndian, ByteOrder};
+use sha2::{Digest, Sha256};
+
+use crate::util::zip;
+use proto::Reader;
+
+const MAGIC: &[u8; 4] = b"CrAU";
+const MAX_MANIFEST: u64 = 64 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub struct PayloadHeader {
+    pub version: u64,
+    pub manifest_size: u64,
+    pub metadata_signature_size: u32,
+}
+
+impl PayloadHeader {
+    fn size(&self) -> u64 {
+        // Version 1 payloads have no metadata signature length field.
+        if self.version >= 2 {
+            24
+        } else {
+            20
+        }
+    }
+
+    /// Bytes covered by `METADATA_HASH`: the header and the manifest.
+    pub fn metadata_size(&self) -> u64 {
+        self.size() + self.manifest_size
+    }
+
+    /// Offset of the first operation blob from the start of the payload.
+    pub fn data_offset(&self) -> u64 {
+        self.metadata_size() + self.metadata_signature_size as u64
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UpdateKind {
+    /// Writes the partition from scratch.
+    Full,
+    /// Needs the current partition contents as a source.
+    Incremental,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InstallOperation {
+    pub op_type: u32,
+    pub data_offset: Option<u64>,
+    pub data_length: u64,
+    pub data_sha256: Option<Vec<u8>>,
+}
+
+impl InstallOperation {
+    pub fn type_name(&self) -> &'static str {
+        match self.op_type {
+            0 => "REPLACE",
+            1 => "REPLACE_BZ",
+            2 => "MOVE",
+            3 => "BSDIFF",
+            4 => "SOURCE_COPY",
+            5 => "SOURCE_BSDIFF",
+            6 => "ZERO",
+            7 => "DISCARD",
+            8 => "REPLACE_XZ",
+            9 => "PUFFDIFF",
+            10 => "BROTLI_BSDIFF",
+            11 => "ZUCCHINI",
+            12 => "LZ4DIFF_BSDIFF",
+            13 => "LZ4DIFF_PUFFDIFF",
+            14 => "REPLACE_ZSTD",
+            _ => "UNKNOWN",
+        }
+    }
+
+    pub fn kind(&self) -> UpdateKind {
+        match self.op_type {
+            0 | 1 | 6 | 7 | 8 | 14 => UpdateKind::Full,
+            _ => UpdateKind::Incremental,
+        }
+    }
+
+    fn parse(bytes: &[u8]) -> Result<Self> {
+        let mut out = Self::default();
+        let mut reader = Reader::new(bytes);
+        while let Some((field, value)) = reader.next_field()? {
+            match field {
+                1 => out.op_type = value.as_u64()? as u32,
+                2 => out.data_offset = Some(value.as_u64()?),
+                3 => out.data_length = value.as_u64()?,
+                8 => out.data_sha256 = Some(value.as_bytes()?.to_vec()),
+                _ => {}
+            }
+        }
+        Ok(out)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PartitionInfo {
+    pub size: Option<u64>,
+    pub hash: Option<Vec<u8>>,
+}
+
+impl PartitionInfo {
+    fn parse(bytes: &[u8]) -> Result<Self> {
+        let mut out = Self::default();
+        let mut reader = Reader::new(bytes);
+        while let Some((field, value)) = reader.next_field()? {
+            match field {
+                1 => out.size = Some(value.as_u64()?),
+                2 => out.hash = Some(value.as_bytes()?.to_vec()),
+                _ => {}
+            }
+        }
+        Ok(out)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PartitionUpdate {
+    pub name: String,
+    pub version: Option<String>,
+    pub old_info: Option<PartitionInfo>,
+    pub new_info: Option<PartitionInfo>,
+    pub operations: Vec<InstallOperation>,
+}
+
+impl PartitionUpdate {
+    pub fn kind(&self) -> UpdateKind {
+        if self.old_info.is_some()
+            || self
+                .operations
+                .iter()
+                .any(|operation| operation.kind() == UpdateKind::Incremental)
+        {
+            UpdateKind::Incremental
+        } else {
+            UpdateKind::Full
+        }
+    }
+
+    fn parse(bytes: &[u8]) -> Result<Self> {
+        let mut out = Self::default();
+        let mut reader = Reader::new(bytes);
+        while let Some((field, value)) = reader.next_field()? {
+            match field {
+                1 => out.name = value.as_string()?,
+                6 => out.old_info = Some(PartitionInfo::parse(value.as_bytes()?)?),
+                7 => out.new_info = Some(PartitionInfo::parse(value.as_bytes()?)?),
+                8 => out
+                    .operations
+                    .push(InstallOperation::parse(value.as_bytes()?)?),
+                15 => out.version = Some(value.as_string()?),
+                _ => {}
+            }
+        }
+        Ok(out)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Manifest {
+    pub block_size: u32,
+    pub minor_version: u32,
+    pub max_timestamp: Option<i64>,
+    pub partial_update: bool,
+    pub security_patch_level: Option<String>,
+    pub partitions: Vec<PartitionUpdate>,
+}
+
+impl Default for Manifest {
+    fn default() -> Self {
+        Self {
+            block_size: 4096,
+            minor_version: 0,
+            max_timestamp: None,
+            partial_update: false,
+            security_patch_level: None,
+            partitions: Vec::new(),
+        }
+    }
+}
+
+impl Manifest {
+    pub fn kind(&self) -> UpdateKind {
+        if self
+            .partitions
+            .iter()
+            .any(|partition| partition.kind() == UpdateKind::Incremental)
+        {
+            UpdateKind::Incremental
+        } else {
+            UpdateKind::Full
+        }
+    }
+}
+
+pub fn parse_header(bytes: &[u8]) -> Result<PayloadHeader> {
+    if bytes.len() < 20 || &bytes[..4] != MAGIC {
+        bail!("payload.bin does not start with the CrAU magic");
+    }
+    let version = BigEndian::read_u64(&bytes[4..12]);
+    let manifest_size = BigEndian::read_u64(&bytes[12..20]);
+    let metadata_signature_size = match version {
+        1 => 0,
+        2 => {
+            if bytes.len() < 24 {
+                bail!("payload.bin header is truncated");
+            }
+            BigEndian::read_u32(&bytes[20..24])
+        }
+        other => bail!("Unsupported payload.bin format version {other}"),
+    };
+    if manifest_size > MAX_MANIFEST {
+        bail!("payload.bin manifest size {manifest_size} is implausibly large");
+    }
+    Ok(PayloadHeader {
+        version,
+        manifest_size,
+        metadata_signature_size,
+    })
+}
+
+pub fn parse_manifest(bytes: &[u8]) -> Result<Manifest> {
+    let mut out = Manifest::default();
+    let mut reader = Reader::new(bytes);
+    while let Some((field, value)) = reader.next_field()? {
+        match field {
+            3 => out.block_size = value.as_u64()? as u32,
+            12 => out.minor_version = value.as_u64()? as u32,
+            13 => out
+                .partitions
+                .push(PartitionUpdate::parse(value.as_bytes()?)?),
+            14 => out.max_timestamp = Some(value.as_u64()? as i64),
+            16 => out.partial_update = value.as_u64()? != 0,
+            18 => out.security_patch_level = Some(value.as_string()?),
+            _ => {}
+        }
+    }
+    Ok(out)
+}
+
+/// Hashes published beside the payload in `payload_properties.txt`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PayloadProperties {
+    pub file_hash: Option<Vec<u8>>,
+    pub file_size: Option<u64>,
+    pub metadata_hash: Option<Vec<u8>>,
+    pub metadata_size: Option<u64>,
+}
+
+pub fn parse_properties(text: &str) -> Result<PayloadProperties> {
+    let mut out = PayloadProperties::default();
+    for line in text.lines() {
+        let Some((key, value)) = line.trim().split_once('=') else {
+            continue;
+        };
+        let decode = |value: &str| {
+            general_purpose::STANDARD
+                .decode(value)
+                .with_context(|| format!("Decoding {key} in payload_properties.txt"))
+        };
+        match key {
+            "FILE_HASH" => out.file_hash = Some(decode(value)?),
+            "FILE_SIZE" => out.file_size = Some(value.parse()?),
+            "METADATA_HASH" => out.metadata_hash = Some(decode(value)?),
+            "METADATA_SIZE" => out.metadata_size = Some(value.parse()?),
+            _ => {}
+        }
+    }
+    Ok(out)
+}
+
+#[derive(Debug, Clone)]
+pub struct Payload {
+    pub header: PayloadHeader,
+    pub manifest: Manifest,
+    pub properties: Option<PayloadProperties>,
+    /// Offset of `payload.bin` inside the file it was read from.
+    pub offset: u64,
+    pub size: u64,
+    metadata_sha256: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct PartitionSummary {
+    pub name: String,
+    pub size: Option<u64>,
+    pub sha256: Option<String>,
+    pub kind: UpdateKind,
+    pub full_operations: usize,
+    pub incremental_operations: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub struct PayloadVerification {
+    pub operations_checked: usize,
+    pub file_hash_checked: bool,
+    pub metadata_hash_checked: bool,
+}
+
+impl Payload {
+    pub fn summaries(&self) -> Vec<PartitionSummary> {
+        self.manifest
+            .partitions
+            .iter()
+            .map(|partition| {
+                let full_operations = partition
+                    .operations
+                    .iter()
+                    .filter(|operation| operation.kind() == UpdateKind::Full)
+                    .count();
+                let info = partition.new_info.as_ref();
+                PartitionSummary {
+                    name: partition.name.clone(),
+                    size: info.and_then(|info| info.size),
+                    sha256: info.and_then(|info| info.hash.as_deref()).map(hex::encode),
+                    kind: partition.kind(),
+                    full_operations,
+                    incremental_operations: partition.operations.len() - full_operations,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Reads the payload from a Recovery ROM ZIP or a bare `payload.bin`.
+/// Returns `None` for packages that do not carry an A/B payload.
+pub fn open(path: &Path) -> Result<Option<Payload>> {
+    let mut file = File::open(path).with_context(|| format!("Opening {}", path.display()))?;
+    let file_len = file.metadata()?.len();
+    let mut magic = [0u8; 4];
+    let bare = file.read_exact(&mut magic).is_ok() && &magic == MAGIC;
+    let (offset, size, properties) = if bare {
+        (0, file_len, None)
+    } else {
+        let Some((offset, size)) = zip::find_stored(&mut file, "payload.bin")? else {
+            return Ok(None);
+        };
+        let properties = zip::read_stored(&mut file, "payload_properties.txt")?
+            .map(|bytes| parse_properties(&String::from_utf8_lossy(&bytes)))
+            .transpose()?;
+        (offset, size, properties)
+    };
+
+    let mut header_bytes = [0u8; 24];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut header_bytes[..size.min(24) as usize])
+        .context("Reading payload.bin header")?;
+    let header = parse_header(&header_bytes[..size.min(24) as usize])?;
+    if header.data_offset() > size {
+        bail!("payload.bin is shorter than its own manifest; the package is truncated");
+    }
+    let mut manifest_bytes = vec![0u8; header.manifest_size as usize];
+    file.seek(SeekFrom::Start(offset + header.size()))?;
+    file.read_exact(&mut manifest_bytes)
+        .context("Reading payload.bin manifest")?;
+    let manifest = parse_manifest(&manifest_bytes).context("Parsing payload.bin manifest")?;
+
+    let mut metadata = Sha256::new();
+    metadata.update(&header_bytes[..header.size() as usize]);
+    metadata.update(&manifest_bytes);
+    Ok(Some(Payload {
+        header,
+        manifest,
+        properties,
+        offset,
+        size,
+        metadata_sha256: metadata.finalize().to_vec(),
+    }))
+}
+
+/// Checks every operation blob hash, plus the whole-payload and metadata
+/// hashes from `payload_properties.txt` when present, in one sequential pass.
+pub fn verify(path: &Path, payload: &Payload) -> Result<PayloadVerification> {
+    let properties = payload.properties.clone().unwrap_or_default();
+    let mut metadata_hash_checked = false;
+    if let Some(expected) = &properties.metadata_hash {
+        if properties.metadata_size != Some(payload.header.metadata_size()) {
+            bail!("payload.bin metadata size does not match payload_properties.txt");
+        }
+        if *expected != payload.metadata_sha256 {
+            bail!("payload.bin metadata hash does not match payload_properties.txt");
+        }
+        metadata_hash_checked = true;
+    }
+    if let Some(expected) = properties.file_size {
+        if expected != payload.size {
+            bail!(
+                "payload.bin is {} bytes but payload_properties.txt expects {expected}",
+                payload.size
+            );
+        }
+    }
+
+    let data_start = payload.header.data_offset();
+    let mut blobs = Vec::new();
+    for partition in &payload.manifest.partitions {
+        for (index, operation) in partition.operations.iter().enumerate() {
+            if let (Some(offset), Some(hash)) = (operation.data_offset, &operation.data_sha256) {
+                let start = data_start.saturating_add(offset);
+                if start.saturating_add(operation.data_length) > payload.size {
+                    bail!(
+                        "{} operation {index} points past the end of payload.bin",
+                        partition.name
+                    );
+                }
+                blobs.push((start, operation.data_length, hash, &partition.name, index));
+            }
+        }
+    }
+    blobs.sort_by_key(|blob| blob.0);
+
+    let mut file = File::open(path).with_context(|| format!("Opening {}", path.display()))?;
+    file.seek(SeekFrom::Start(payload.offset))?;
+    let mut reader = BufReader::with_capacity(1024 * 1024, file).take(payload.size);
+    let mut buf = vec![0u8; 1024 * 1024];
+    let mut whole = Sha256::new();
+    let mut position = 0u64;
+    for (start, len, expected, partition, index) in blobs {
+        if start < position {
+            bail!("{partition} operation {index} overlaps another operation blob");
+        }
+        consume(&mut reader, start - position, &mut whole, None, &mut buf)?;
+        let mut blob = Sha256::new();
+        consume(&mut reader, len, &mut whole, Some(&mut blob), &mut buf)?;
+        if blob.finalize().as_slice() != expected.as_slice() {
+            bail!("{partition} operation {index} data does not match its SHA-256 hash");
+        }
+        position = start + len;
+    }
+    consume(
+        &mut reader,
+        payload.size - position,
+        &mut whole,
+        None,
+        &mut buf,
+    )?;
+    let file_hash_checked = match &properties.file_hash {
+        Some(expected) if whole.finalize().as_slice() != expected.as_slice() => {
+            bail!("payload.bin does not match FILE_HASH in payload_properties.txt")
+        }
+        Some(_) => true,
+        None => false,
+    };
+    Ok(PayloadVerification {
+        operations_checked: payload
+            .manifest
+            .partitions
+            .iter()
+            .flat_map(|partition| &partition.operations)
+            .filter(|operation| operation.data_offset.is_some() && operation.data_sha256.is_some())
+            .count(),
+        file_hash_checked,
+        metadata_hash_checked,
+    })
+}
+
+fn consume(
+    reader: &mut impl Read,
+    mut len: u64,
+    whole: &mut Sha256,
+    mut blob: Option<&mut Sha256>,
+    buf: &mut [u8],
+) -> Result<()> {
+    while len > 0 {
+        let want = len.min(buf.len() as u64) as usize;
+        reader
+            .read_exact(&mut buf[..want])
+            .context("payload.bin ended before its last operation")?;
+        whole.update(&buf[..want]);
+        if let Some(blob) = blob.as_deref_mut() {
+            blob.update(&buf[..want]);
+        }
+        len -= want as u64;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    fn varint(mut value: u64, out: &mut Vec<u8>) {
+        while value >= 0x80 {
+            out.push((value as u8) | 0x80);
+            value >>= 7;
+        }
+        out.push(value as u8);
+    }
+
+    fn field(number: u64, value: u64, out: &mut Vec<u8>) {
+        varint(number << 3, out);
+        varint(value, out);
+    }
+
+    fn bytes_field(number: u64, value: &[u8], out: &mut Vec<u8>) {
+        varint((number << 3) | 2, out);
+        varint(value.len() as u64, out);
+        out.extend_from_slice(value);
+    }
+
+    fn operation(op_type: u64, offset: u64, data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        field(1, op_type, &mut out);
+        field(2, offset, &mut out);
+        field(3, data.len() as u64, &mut out);
+        bytes_field(8, &Sha256::digest(data), &mut out);
+        out
+    }
+
+    fn payload_bytes() -> Vec<u8> {
+        let mut info = Vec::new();
+        field(1, 4096, &mut info);
+        bytes_field(2, &[0xab; 32], &mut info);
+
+        let mut boot = Vec::new();
+        bytes_field(1, b"boot", &mut boot);
+        bytes_field(7, &info, &mut boot);
+        bytes_field(8, &operation(0, 0, b"hello"), &mut boot);
+
+        let mut vendor = Vec::new();
+        bytes_field(1, b"vendor_boot", &mut vendor);
+        bytes_field(8, &operation(4, 5, b""), &mut vendor);
+        bytes_field(8, &operation(10, 5, b"world"), &mut vendor);
+
+        let mut manifest = Vec::new();
+        field(3, 4096, &mut manifest);
+        field(12, 8, &mut manifest);
+        bytes_field(13, &boot, &mut manifest);
+        bytes_field(13, &vendor, &mut manifest);
+
+        let mut out = MAGIC.to_vec();
+        out.extend_from_slice(&2u64.to_be_bytes());
+        out.extend_from_slice(&(manifest.len() as u64).to_be_bytes());
+        out.extend_from_slice(&0u32.to_be_bytes());
+        out.extend_from_slice(&manifest);
+        out.extend_from_slice(b"helloworld");
+        out
+    }
+
+    fn write(bytes: &[u8]) -> NamedTempFile {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(bytes).unwrap();
+        file
+    }
+
+    #[test]
+    fn partitions_are_listed_with_full_and_incremental_operations() {
+        let file = write(&payload_bytes());
+        let payload = open(file.path()).unwrap().unwrap();
+        let summaries = payload.summaries();
+
+        assert_eq!(payload.manifest.minor_version, 8);
+        assert_eq!(summaries[0].name, "boot");
+        assert_eq!(summaries[0].size, Some(4096));
+        assert_eq!(summaries[0].kind, UpdateKind::Full);
+        assert_eq!(
+            summaries[0].sha256.as_deref(),
+            Some("ab".repeat(32).as_str())
+        );
+        assert_eq!(summaries[1].kind, UpdateKind::Incremental);
+        assert_eq!(summaries[1].incremental_operations, 2);
+        assert_eq!(payload.manifest.kind(), UpdateKind::Incremental);
+    }
+
+    #[test]
+    fn operation_hashes_are_verified_offline() {
+        let file = write(&payload_bytes());
+        let payload = open(file.path()).unwrap().unwrap();
+        let report = verify(file.path(), &payload).unwrap();
+
+        assert_eq!(report.operations_checked, 3);
+        assert!(!report.file_hash_checked);
+    }
+
+    #[test]
+    fn modified_operation_data_is_detected() {
+        let mut bytes = payload_bytes();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0x20;
+        let file = write(&bytes);
+        let payload = open(file.path()).unwrap().unwrap();
+
+        let error = verify(file.path(), &payload).unwrap_err();
+        assert!(error.to_string().contains("vendor_boot operation 1"));
+    }
+
+    #[test]
+    fn payload_properties_are_decoded() {
+        let properties =
+            parse_properties("FILE_HASH=AAEC\nFILE_SIZE=10\nMETADATA_SIZE=24\n").unwrap();
+        assert_eq!(properties.file_hash, Some(vec![0, 1, 2]));
+        assert_eq!(properties.file_size, Some(10));
+        assert_eq!(properties.metadata_size, Some(24));
+    }
+
+    #[test]
+    fn package_without_payload_is_not_an_error() {
+        let file = write(include_bytes!("../ota/testdata/signed-ota.zip"));
+        assert!(open(file.path()).unwrap().is_none());
+    }
+}
@@ -0,0 +1,117 @@
+// Copyright (C) 2026 Chromatic
+// Licensed under the GNU AGPL v3.0. See LICENSE file for details.
+// Website: https://chromatic.hu
+
+//! Protobuf wire-format reader for the update_engine manifest. Unknown
+//! fields are skipped so newer manifests remain readable.
+
+use anyhow::{bail, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Value<'a> {
+    Varint(u64),
+    Fixed64(u64),
+    Bytes(&'a [u8]),
+    Fixed32(u32),
+}
+
+impl<'a> Value<'a> {
+    pub fn as_u64(self) -> Result<u64> {
+        match self {
+            Self::Varint(value) | Self::Fixed64(value) => Ok(value),
+            Self::Fixed32(value) => Ok(value as u64),
+            Self::Bytes(_) => bail!("protobuf field is length-delimited, expected a number"),
+        }
+    }
+
+    pub fn as_bytes(self) -> Result<&'a [u8]> {
+        match self {
+            Self::Bytes(bytes) => Ok(bytes),
+            _ => bail!("protobuf field is numeric, expected bytes"),
+        }
+    }
+
+    pub fn as_string(self) -> Result<String> {
+        Ok(String::from_utf8_lossy(self.as_bytes()?).into_owned())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Reader<'a> {
+    input: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(input: &'a [u8]) -> Self {
+        Self { input }
+    }
+
+    fn varint(&mut self) -> Result<u64> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let Some((&byte, rest)) = self.input.split_first() else {
+                bail!("protobuf varint truncated");
+            };
+            self.input = rest;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        bail!("protobuf varint is longer than 64 bits")
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if len > self.input.len() {
+            bail!("protobuf field length exceeds its message");
+        }
+        let (head, rest) = self.input.split_at(len);
+        self.input = rest;
+        Ok(head)
+    }
+
+    /// Returns the next field number and value, or `None` at the end.
+    pub fn next_field(&mut self) -> Result<Option<(u32, Value<'a>)>> {
+        if self.input.is_empty() {
+            return Ok(None);
+        }
+        let key = self.varint()?;
+        let field = u32::try_from(key >> 3)?;
+        let value = match key & 0x7 {
+            0 => Value::Varint(self.varint()?),
+            1 => Value::Fixed64(u64::from_le_bytes(self.take(8)?.try_into()?)),
+            2 => {
+                let len = usize::try_from(self.varint()?)?;
+                Value::Bytes(self.take(len)?)
+            }
+            5 => Value::Fixed32(u32::from_le_bytes(self.take(4)?.try_into()?)),
+            wire => bail!("unsupported protobuf wire type {wire}"),
+        };
+        Ok(Some((field, value)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fields_of_every_supported_wire_type_are_read() {
+        let message = [
+            0x08, 0x96, 0x01, // 1: varint 150
+            0x12, 0x02, b'o', b'k', // 2: bytes "ok"
+            0x1d, 0x01, 0x00, 0x00, 0x00, // 3: fixed32 1
+        ];
+        let mut reader = Reader::new(&message);
+
+        assert_eq!(reader.next_field().unwrap(), Some((1, Value::Varint(150))));
+        assert_eq!(reader.next_field().unwrap(), Some((2, Value::Bytes(b"ok"))));
+        assert_eq!(reader.next_field().unwrap(), Some((3, Value::Fixed32(1))));
+        assert_eq!(reader.next_field().unwrap(), None);
+    }
+
+    #[test]
+    fn truncated_length_delimited_field_is_rejected() {
+        assert!(Reader::new(&[0x12, 0x05, b'a']).next_field().is_err());
+    }
+}
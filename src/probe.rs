@@ -0,0 +1,228 @@
+// Copyright (C) 2026 Chromatic
+// Licensed under the GNU AGPL v3.0. See LICENSE file for details.
+// Website: https://chromatic.hu
+
+//! Asks the validation server what it would offer the device under each
+//! region profile.
+
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+
+use crate::cancel::{self, CancelToken};
+use crate::mi::profile::{apply_profile, RegionProfile};
+use crate::mi::{version::RomVersion, DeviceInfo};
+use crate::validate::{RomPackage, ValidateResult};
+#[cfg(feature = "validation-net")]
+use crate::{observer::Observer, validate};
+
+/// Whether flashing the latest ROM under a profile erases user data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Wipe {
+    /// The server asked for an erase.
+    Required,
+    /// The server said no erase is needed.
+    NotRequired,
+    /// The server did not say, but the latest ROM belongs to another region
+    /// than the installed one, and changing region erases data.
+    RegionChange,
+    Unknown,
+}
+
+/// One profile's answer.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct RegionProbe {
+    pub profile: String,
+    /// Identity sent to the server.
+    pub device: String,
+    pub version: String,
+    pub allowed: Vec<String>,
+    pub latest: Option<RomPackage>,
+    pub wipe: Wipe,
+    pub message: Option<String>,
+    /// Why this profile could not be queried; the others still are.
+    pub error: Option<String>,
+}
+
+/// Queries `validate` once per profile, waiting `interval` between requests.
+/// Only cancellation stops the probe; other failures are kept per profile.
+pub fn probe_with(
+    info: &DeviceInfo,
+    profiles: &[RegionProfile],
+    codename: Option<&str>,
+    interval: Duration,
+    cancel: &CancelToken,
+    mut validate: impl FnMut(&DeviceInfo) -> Result<ValidateResult>,
+) -> Result<Vec<RegionProbe>> {
+    let installed = info.version.parse::<RomVersion>().ok();
+    let mut probes = Vec::with_capacity(profiles.len());
+    for (index, profile) in profiles.iter().enumerate() {
+        if index > 0 {
+            pause(interval, cancel)?;
+        }
+        let mut probe = RegionProbe {
+            profile: profile.name.clone(),
+            device: String::new(),
+            version: String::new(),
+            allowed: Vec::new(),
+            latest: None,
+            wipe: Wipe::Unknown,
+            message: None,
+            error: None,
+        };
+        let response = apply_profile(info, profile, codename).and_then(|identity| {
+            probe.device.clone_from(&identity.device);
+            probe.version.clone_from(&identity.version);
+            validate(&identity)
+        });
+        match response {
+            Ok(response) => {
+                probe.wipe = wipe(&response, installed.as_ref());
+                probe.allowed = response.pkgrom_validate.unwrap_or_default();
+                probe.latest = response.latest_rom;
+                probe.message = response.code_message;
+            }
+            Err(error) if cancel::is_cancelled(&error) => return Err(error),
+            Err(error) => probe.error = Some(format!("{error:#}")),
+        }
+        probes.push(probe);
+    }
+    Ok(probes)
+}
+
+/// [`probe_with`] against the validation server at `server_url`.
+#[cfg(feature = "validation-net")]
+pub fn probe_regions(
+    info: &DeviceInfo,
+    profiles: &[RegionProfile],
+    codename: Option<&str>,
+    server_url: &str,
+    interval: Duration,
+    observer: &dyn Observer,
+    cancel: &CancelToken,
+) -> Result<Vec<RegionProbe>> {
+    probe_with(info, profiles, codename, interval, cancel, |identity| {
+        let request = validate::build_request_json(identity, None)?;
+        validate::validate_observed(server_url, &request, observer, cancel)
+    })
+}
+
+fn wipe(response: &ValidateResult, installed: Option<&RomVersion>) -> Wipe {
+    match response.pkgrom_erase {
+        Some(1) => return Wipe::Required,
+        Some(0) => return Wipe::NotRequired,
+        _ => {}
+    }
+    let latest = response
+        .latest_rom
+        .as_ref()
+        .and_then(|package| package.version.as_ref());
+    match (installed, latest) {
+        (Some(installed), Some(latest)) if installed.region != latest.region => Wipe::RegionChange,
+        _ => Wipe::Unknown,
+    }
+}
+
+fn pause(interval: Duration, cancel: &CancelToken) -> Result<()> {
+    let until = Instant::now() + interval;
+    loop {
+        cancel.check()?;
+        let now = Instant::now();
+        if now >= until {
+            return Ok(());
+        }
+        std::thread::sleep((until - now).min(Duration::from_millis(100)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device_info() -> DeviceInfo {
+        DeviceInfo {
+            device: "garnet_in_global".into(),
+            sn: "serial".into(),
+            version: "OS2.0.202.0.VNRINXM".into(),
+            branch: "F".into(),
+            romzone: "1".into(),
+            ..DeviceInfo::default()
+        }
+    }
+
+    fn package(version: &str) -> RomPackage {
+        RomPackage {
+            filename: format!("miui_GARNET_{version}_0000_15.0.zip"),
+            md5: "00".repeat(16),
+            version: version.parse().ok(),
+            size: None,
+            codebase: None,
+            description: None,
+            description_url: None,
+            changelog: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn each_profile_is_queried_with_its_own_identity() {
+        let profiles: Vec<_> = ["in", "eea", "cn"]
+            .iter()
+            .map(|name| RegionProfile::resolve(name, &[]).unwrap())
+            .collect();
+        let mut devices = Vec::new();
+        let probes = probe_with(
+            &device_info(),
+            &profiles,
+            None,
+            Duration::ZERO,
+            &CancelToken::new(),
+            |identity| {
+                devices.push(identity.device.clone());
+                match identity.device.as_str() {
+                    "garnet_in_global" => Ok(ValidateResult {
+                        pkgrom_validate: Some(vec!["a.zip".into()]),
+                        pkgrom_erase: Some(0),
+                        latest_rom: Some(package("OS2.0.203.0.VNRINXM")),
+                        ..ValidateResult::default()
+                    }),
+                    "garnet_eea_global" => Ok(ValidateResult {
+                        latest_rom: Some(package("OS2.0.201.0.VNREUXM")),
+                        code_message: Some("ok".into()),
+                        ..ValidateResult::default()
+                    }),
+                    _ => anyhow::bail!("HTTP 500"),
+                }
+            },
+        )
+        .unwrap();
+
+        assert_eq!(devices, ["garnet_in_global", "garnet_eea_global", "garnet"]);
+        assert_eq!(probes[0].allowed, ["a.zip"]);
+        assert_eq!(probes[0].wipe, Wipe::NotRequired);
+        assert_eq!(probes[1].version, "OS2.0.202.0.VNREUXM");
+        assert_eq!(probes[1].wipe, Wipe::RegionChange);
+        assert_eq!(probes[2].error.as_deref(), Some("HTTP 500"));
+    }
+
+    #[test]
+    fn cancellation_stops_between_profiles() {
+        let cancel = CancelToken::new();
+        let profiles = RegionProfile::builtins();
+        let mut queries = 0;
+        let result = probe_with(
+            &device_info(),
+            &profiles,
+            None,
+            Duration::from_secs(60),
+            &cancel,
+            |_| {
+                queries += 1;
+                cancel.cancel();
+                Ok(ValidateResult::default())
+            },
+        );
+        assert!(result.is_err_and(|error| cancel::is_cancelled(&error)));
+        assert_eq!(queries, 1);
+    }
+}
@@ -0,0 +1,237 @@
+// Copyright (C) 2026 Chromatic
+// Licensed under the GNU AGPL v3.0. See LICENSE file for details.
+// Website: https://chromatic.hu
+
+//! Values that must not leak into logs: validation tokens and device serials.
+//!
+//! [`Secret`] never shows its value through `Debug`. `Display` and serde
+//! follow the process-wide [`Redaction`] policy, which masks by default, so
+//! terminal output, `--json`, machine events and error messages agree. Code
+//! that needs the value, such as the validation request or sideload, asks
+//! for it with [`Secret::reveal`]. Values are zeroed when dropped.
+
+use std::cell::Cell;
+use std::fmt;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use zeroize::Zeroize;
+
+/// Whether secrets are shown in output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Redaction {
+    Mask,
+    Reveal,
+}
+
+static REVEAL: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    static REVEAL_HERE: Cell<u32> = const { Cell::new(0) };
+}
+
+pub fn set_policy(redaction: Redaction) {
+    REVEAL.store(redaction == Redaction::Reveal, Ordering::Relaxed);
+}
+
+/// The policy in effect on this thread.
+pub fn policy() -> Redaction {
+    if REVEAL.load(Ordering::Relaxed) || REVEAL_HERE.with(Cell::get) > 0 {
+        Redaction::Reveal
+    } else {
+        Redaction::Mask
+    }
+}
+
+/// Runs `f` with secrets revealed on this thread, for files whose purpose is
+/// to carry them, such as identity files and authorization bundles.
+pub fn with_revealed<R>(f: impl FnOnce() -> R) -> R {
+    struct Restore;
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            REVEAL_HERE.with(|depth| depth.set(depth.get() - 1));
+        }
+    }
+    REVEAL_HERE.with(|depth| depth.set(depth.get() + 1));
+    let _restore = Restore;
+    f()
+}
+
+/// How one kind of secret is masked.
+pub trait Kind {
+    /// Shown by `Debug`, e.g. `Serial`.
+    const NAME: &'static str;
+    fn mask(value: &str) -> String;
+}
+
+/// A sideload token from the validation server; masked entirely.
+#[derive(Debug)]
+pub enum TokenKind {}
+
+impl Kind for TokenKind {
+    const NAME: &'static str = "Token";
+
+    fn mask(value: &str) -> String {
+        if value.is_empty() {
+            String::new()
+        } else {
+            "[redacted]".to_string()
+        }
+    }
+}
+
+/// A device serial; the last characters stay visible to tell phones apart.
+#[derive(Debug)]
+pub enum SerialKind {}
+
+/// Characters of a masked serial that stay visible.
+const VISIBLE_SERIAL: usize = 4;
+
+impl Kind for SerialKind {
+    const NAME: &'static str = "Serial";
+
+    /// `a1b2c3d4e5` -> `******d4e5`; short serials are masked entirely.
+    fn mask(value: &str) -> String {
+        let count = value.chars().count();
+        let visible = if count > VISIBLE_SERIAL * 2 {
+            VISIBLE_SERIAL
+        } else {
+            0
+        };
+        value
+            .chars()
+            .enumerate()
+            .map(|(index, character)| {
+                if index + visible < count {
+                    '*'
+                } else {
+                    character
+                }
+            })
+            .collect()
+    }
+}
+
+pub type Token = Secret<TokenKind>;
+pub type Serial = Secret<SerialKind>;
+
+pub struct Secret<K: Kind> {
+    value: String,
+    kind: PhantomData<K>,
+}
+
+impl<K: Kind> Secret<K> {
+    pub fn new(value: impl Into<String>) -> Self {
+        Self {
+            value: value.into(),
+            kind: PhantomData,
+        }
+    }
+
+    /// The value itself. Call sites are where secrets leave the process, so
+    /// keep them few and obvious.
+    pub fn reveal(&self) -> &str {
+        &self.value
+    }
+
+    /// The value as output shows it under the masking policy.
+    pub fn masked(&self) -> String {
+        K::mask(&self.value)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.value.is_empty()
+    }
+}
+
+impl<K: Kind> Drop for Secret<K> {
+    fn drop(&mut self) {
+        self.value.zeroize();
+    }
+}
+
+impl<K: Kind> Clone for Secret<K> {
+    fn clone(&self) -> Self {
+        Self::new(self.value.clone())
+    }
+}
+
+impl<K: Kind> Default for Secret<K> {
+    fn default() -> Self {
+        Self::new(String::new())
+    }
+}
+
+impl<K: Kind> PartialEq for Secret<K> {
+    fn eq(&self, other: &Self) -> bool {
+        self.value == other.value
+    }
+}
+
+impl<K: Kind> Eq for Secret<K> {}
+
+impl<K: Kind> From<String> for Secret<K> {
+    fn from(value: String) -> Self {
+        Self::new(value)
+    }
+}
+
+impl<K: Kind> From<&str> for Secret<K> {
+    fn from(value: &str) -> Self {
+        Self::new(value)
+    }
+}
+
+impl<K: Kind> fmt::Debug for Secret<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}({:?})", K::NAME, K::mask(&self.value))
+    }
+}
+
+impl<K: Kind> fmt::Display for Secret<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match policy() {
+            Redaction::Mask => f.write_str(&K::mask(&self.value)),
+            Redaction::Reveal => f.write_str(&self.value),
+        }
+    }
+}
+
+impl<K: Kind> serde::Serialize for Secret<K> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match policy() {
+            Redaction::Mask => serializer.serialize_str(&K::mask(&self.value)),
+            Redaction::Reveal => serializer.serialize_str(&self.value),
+        }
+    }
+}
+
+impl<'de, K: Kind> serde::Deserialize<'de> for Secret<K> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Self::new)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secrets_are_masked_unless_revealed() {
+        let serial = Serial::new("0123456789ab");
+        let token = Token::new("abc+/=");
+        assert_eq!(format!("{serial:?}"), "Serial(\"********89ab\")");
+        assert_eq!(
+            format!("{token:?} {token}"),
+            "Token(\"[redacted]\") [redacted]"
+        );
+        assert_eq!(serde_json::to_string(&serial).unwrap(), "\"********89ab\"");
+        assert_eq!(Serial::new("short").masked(), "*****");
+        assert_eq!(token.reveal(), "abc+/=");
+
+        let revealed = with_revealed(|| serde_json::to_string(&(&serial, &token)).unwrap());
+        assert_eq!(revealed, r#"["0123456789ab","abc+/="]"#);
+        assert_eq!(policy(), Redaction::Mask);
+        assert_eq!(format!("{token:?}"), "Token(\"[redacted]\")");
+    }
+}
@@ -3,16 +3,62 @@
 // Website: https://chromatic.hu
 
 use std::fs::File;
-use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::io::{Read, Seek, SeekFrom};
 use std::path::Path;
-use std::sync::atomic::{AtomicBool, Ordering};
 
-use anyhow::{bail, Context, Result};
-use indicatif::{ProgressBar, ProgressStyle};
+use anyhow::{Context, Result};
 
-use crate::adb::{AdbStream, A_CLSE, A_OKAY, A_WRTE};
-use crate::mi::MiClient;
+// Everything past the package sources drives a device over USB.
+#[cfg(feature = "usb")]
+use {
+    crate::adb::{AdbStream, A_CLSE, A_OKAY, A_WRTE},
+    crate::cancel::CancelToken,
+    crate::mi::MiClient,
+    crate::observer::{Observer, Phase},
+    crate::secret::Token,
+    anyhow::bail,
+    zeroize::Zeroizing,
+};
 
+/// Random-access bytes of a package. Recovery asks for blocks in any order.
+pub trait PackageSource {
+    fn size(&self) -> u64;
+    /// Fills `buf` with the bytes starting at `offset`.
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<()>;
+    /// Called after the device has received the whole package; sources that
+    /// verify what they served report a mismatch here.
+    fn finish(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// A package on local disk.
+pub struct FileSource {
+    file: File,
+    size: u64,
+}
+
+impl FileSource {
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = File::open(path).with_context(|| format!("Opening {}", path.display()))?;
+        let size = file.metadata()?.len();
+        Ok(Self { file, size })
+    }
+}
+
+impl PackageSource for FileSource {
+    fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.read_exact(buf)?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "usb")]
 fn block_window(total: u64, chunk_size: usize, index: u64) -> Option<(u64, usize)> {
     let offset = index.checked_mul(chunk_size as u64)?;
     if offset >= total {
@@ -22,6 +68,7 @@ fn block_window(total: u64, chunk_size: usize, index: u64) -> Option<(u64, usize
     Some((offset, length))
 }
 
+#[cfg(feature = "usb")]
 fn sideload_host_service(
     total: u64,
     chunk_size: usize,
@@ -43,59 +90,50 @@ fn sideload_host_service(
     ))
 }
 
+/// Sideloads a package from local disk.
+#[cfg(feature = "usb")]
 pub fn sideload_zip(
     client: &mut MiClient,
     path: &Path,
     chunk_size: usize,
-    validate_token: &str,
+    validate_token: &Token,
     allow_wipe: bool,
-    cancel: &AtomicBool,
+    observer: &dyn Observer,
+    cancel: &CancelToken,
 ) -> Result<()> {
-    let total = std::fs::metadata(path)
-        .with_context(|| format!("Reading {}", path.display()))?
-        .len();
-    let progress_bar = ProgressBar::new(total);
-    progress_bar.set_style(
-        ProgressStyle::default_bar()
-            .template(
-                "{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({percent}%)",
-            )
-            .unwrap()
-            .progress_chars("=>-"),
-    );
-    let result = sideload_zip_with_progress(
+    let mut source = FileSource::open(path)?;
+    if source.size() == 0 {
+        bail!("ROM package is empty: {}", path.display());
+    }
+    sideload_source(
         client,
-        path,
+        &mut source,
         chunk_size,
         validate_token,
         allow_wipe,
+        observer,
         cancel,
-        |sent, _| progress_bar.set_position(sent),
-    );
-    if cancel.load(Ordering::Relaxed) {
-        progress_bar.abandon_with_message("Cancelled");
-    } else {
-        progress_bar.finish_and_clear();
-    }
-    result
+    )
 }
 
-pub fn sideload_zip_with_progress<F>(
+/// Answers the device's block requests from `source`, reporting
+/// [`Phase::Sideload`] progress. Cancellation closes the stream after the
+/// current block.
+#[cfg(feature = "usb")]
+#[tracing::instrument(name = "sideload", skip_all, fields(chunk_size = chunk_size, wipe = allow_wipe, total))]
+pub fn sideload_source(
     client: &mut MiClient,
-    path: &Path,
+    source: &mut dyn PackageSource,
     chunk_size: usize,
-    validate_token: &str,
+    validate_token: &Token,
     allow_wipe: bool,
-    cancel: &AtomicBool,
-    mut progress: F,
-) -> Result<()>
-where
-    F: FnMut(u64, u64),
-{
-    let file = File::open(path).with_context(|| format!("Opening {}", path.display()))?;
-    let total = file.metadata()?.len();
+    observer: &dyn Observer,
+    cancel: &CancelToken,
+) -> Result<()> {
+    let total = source.size();
+    tracing::Span::current().record("total", total);
     if total == 0 {
-        bail!("ROM package is empty: {}", path.display());
+        bail!("ROM package is empty");
     }
     if chunk_size == 0 || chunk_size > 1024 * 1024 {
         bail!("Invalid chunk size: {}", chunk_size);
@@ -103,7 +141,12 @@ where
 
     // The last field is the wipe flag. Some cross-region updates require data wipe.
     // When server indicates Erase==1, we must send ":1"; otherwise ":0" will make recovery abort.
-    let host_str = sideload_host_service(total, chunk_size, validate_token, allow_wipe)?;
+    let host_str = Zeroizing::new(sideload_host_service(
+        total,
+        chunk_size,
+        validate_token.reveal(),
+        allow_wipe,
+    )?);
     let (mut stream, pending) = client
         .open_sideload(&host_str)
         .context("Opening sideload-host service")?;
@@ -111,9 +154,9 @@ where
     // (some recoveries take >5s before first WRTE)
     stream.set_timeout(std::time::Duration::from_secs(30));
 
-    progress(0, total);
+    observer.phase(Phase::Sideload);
+    observer.progress(Phase::Sideload, 0, total);
 
-    let mut reader = BufReader::new(file);
     let mut send_block =
         |index: u64, s: &mut AdbStream<'_>, pkt_arg0: u32, pkt_arg1: u32| -> Result<u64> {
             let Some((offset, to_send)) = block_window(total, chunk_size, index) else {
@@ -123,13 +166,15 @@ where
                 return Ok(total);
             };
             let mut buf = vec![0u8; to_send];
-            reader.seek(SeekFrom::Start(offset))?;
-            reader.read_exact(&mut buf)?;
+            source
+                .read_at(offset, &mut buf)
+                .with_context(|| format!("Reading package bytes at {offset}"))?;
+            tracing::trace!(index, offset, bytes = to_send, "block");
             // C tool: send WRTE(arg1,arg0) with data, then OKAY(arg1,arg0)
             s.send_wrte_mirror(pkt_arg0, pkt_arg1, buf)?;
             s.send_okay_mirror(pkt_arg0, pkt_arg1)?;
             let end = offset + to_send as u64;
-            progress(end, total);
+            observer.progress(Phase::Sideload, end, total);
             Ok(end)
         };
 
@@ -138,9 +183,9 @@ where
     let mut final_status: Option<String> = None;
     // Handle pending first packet if WRTE arrived during open
     if let Some(pkt) = pending {
-        if cancel.load(Ordering::Relaxed) {
+        if let Err(cancelled) = cancel.check() {
             let _ = stream.close();
-            bail!("Sideload cancelled by user");
+            return Err(cancelled).context("Sideload cancelled");
         }
         if pkt.cmd == A_WRTE {
             if let Ok(idx) = String::from_utf8_lossy(&pkt.payload).trim().parse::<u64>() {
@@ -152,9 +197,9 @@ where
         }
     }
     loop {
-        if cancel.load(Ordering::Relaxed) {
+        if let Err(cancelled) = cancel.check() {
             let _ = stream.close();
-            bail!("Sideload cancelled by user");
+            return Err(cancelled).context("Sideload cancelled");
         }
         // Read next packet; if the device disconnected after sending final status,
         // treat it as end-of-session instead of surfacing a transport error.
@@ -183,8 +228,9 @@ where
                     bytes_sent = bytes_sent.max(end);
                 } else {
                     // Treat as final status message. Ack it, record, and proactively end the session.
+                    tracing::info!(status = trimmed, bytes_sent, "recovery finished");
                     final_status = Some(trimmed.to_string());
-                    eprintln!("{}", trimmed);
+                    observer.message(trimmed);
                     // Acknowledge the device's status WRTE
                     stream.send_okay_mirror(pkt.arg0, pkt.arg1)?;
                     // Break out and close from host side to avoid waiting on a CLSE that may never arrive.
@@ -192,6 +238,7 @@ where
                 }
             }
             x if x == A_CLSE => {
+                tracing::debug!(bytes_sent, "recovery closed the stream");
                 // Device closed the stream; exit loop and mirror close after loop.
                 break;
             }
@@ -206,6 +253,9 @@ where
     if bytes_sent < total {
         bail!("Sideload ended after {bytes_sent} of {total} bytes");
     }
+    source
+        .finish()
+        .context("Verifying the package sent to the device")?;
     // Evaluate final status message (if any) and treat failures as errors.
     if let Some(status) = final_status.as_deref() {
         let s = status.to_ascii_lowercase();
@@ -221,7 +271,7 @@ where
     Ok(())
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "usb"))]
 mod tests {
     use super::*;
 
@@ -0,0 +1,149 @@
+// Copyright (C) 2026 Chromatic
+// Licensed under the GNU AGPL v3.0. See LICENSE file for details.
+// Website: https://chromatic.hu
+
+//! Compares the running build with the packages a validation response offers.
+
+use anyhow::{bail, Context, Result};
+
+use crate::mi::version::RomVersion;
+use crate::validate::{rom_package, RomPackage};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UpdateStatus {
+    UpToDate,
+    UpdateAvailable,
+    /// The server offered a build for another device, region or channel, so
+    /// installing it would be a region change rather than an update.
+    RegionChange,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct UpdateCheck {
+    pub status: UpdateStatus,
+    pub installed: RomVersion,
+    /// Full package from `LatestRom`.
+    pub latest: Option<RomPackage>,
+    /// Incremental package from `IncrementRom`, applicable to `installed` only.
+    pub incremental: Option<RomPackage>,
+}
+
+impl UpdateCheck {
+    /// Newest version offered by either package, or the off-track one for a
+    /// region change.
+    pub fn target(&self) -> Option<&RomVersion> {
+        let mut offered = [&self.latest, &self.incremental]
+            .into_iter()
+            .flatten()
+            .filter_map(|package| package.version.as_ref());
+        match self.status {
+            UpdateStatus::RegionChange => {
+                offered.find(|version| !version.same_track(&self.installed))
+            }
+            _ => offered.max(),
+        }
+    }
+}
+
+/// Decides whether `response_json` offers something newer than `installed`,
+/// the version the device reports. An offer on another track is a region
+/// change whatever its release number.
+pub fn check_update(installed: &str, response_json: &str) -> Result<UpdateCheck> {
+    let installed: RomVersion = installed
+        .parse()
+        .context("Reading the installed ROM version")?;
+    let response: serde_json::Value =
+        serde_json::from_str(response_json).context("Parsing the validation response")?;
+    let latest = rom_package(&response, "LatestRom");
+    let incremental = rom_package(&response, "IncrementRom");
+    let mut status = UpdateStatus::UpToDate;
+    for package in [&latest, &incremental].into_iter().flatten() {
+        let Some(version) = &package.version else {
+            bail!(
+                "The server offered {} without a recognizable version",
+                package.filename
+            );
+        };
+        if !version.same_track(&installed) {
+            status = UpdateStatus::RegionChange;
+        } else if version.is_newer_than(&installed) && status == UpdateStatus::UpToDate {
+            status = UpdateStatus::UpdateAvailable;
+        }
+    }
+    Ok(UpdateCheck {
+        status,
+        installed,
+        latest,
+        incremental,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INSTALLED: &str = "OS2.0.202.0.VNRINXM";
+
+    #[test]
+    fn newer_latest_rom_is_an_update() {
+        let check = check_update(
+            INSTALLED,
+            r#"{
+                "LatestRom": {"filename": "rom.zip", "md5": "a", "version": "OS2.0.203.0.VNRINXM"},
+                "IncrementRom": {"filename": "inc.zip", "md5": "b", "version": "OS2.0.203.0.VNRINXM"}
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(check.status, UpdateStatus::UpdateAvailable);
+        assert_eq!(check.target().unwrap().to_string(), "OS2.0.203.0.VNRINXM");
+        assert!(check.incremental.is_some());
+    }
+
+    #[test]
+    fn same_or_missing_latest_rom_is_up_to_date() {
+        let same = check_update(
+            INSTALLED,
+            r#"{"LatestRom": {"filename": "rom.zip", "md5": "a", "version": "OS2.0.202.0.VNRINXM"}}"#,
+        )
+        .unwrap();
+        assert_eq!(same.status, UpdateStatus::UpToDate);
+
+        let none = check_update(INSTALLED, r#"{"Code": {"message": "ok"}}"#).unwrap();
+        assert_eq!(none.status, UpdateStatus::UpToDate);
+        assert!(none.target().is_none());
+    }
+
+    #[test]
+    fn offers_on_another_track_are_region_changes() {
+        let global = check_update(
+            INSTALLED,
+            r#"{"LatestRom": {"filename": "rom.zip", "md5": "a", "version": "OS2.0.203.0.VNRMIXM"}}"#,
+        )
+        .unwrap();
+        assert_eq!(global.status, UpdateStatus::RegionChange);
+        assert_eq!(global.target().unwrap().to_string(), "OS2.0.203.0.VNRMIXM");
+
+        let older = check_update(
+            INSTALLED,
+            r#"{
+                "LatestRom": {"filename": "rom.zip", "md5": "a", "version": "OS2.0.201.0.VNREUXM"},
+                "IncrementRom": {"filename": "inc.zip", "md5": "b", "version": "OS2.0.203.0.VNRINXM"}
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(older.status, UpdateStatus::RegionChange);
+        assert_eq!(older.target().unwrap().to_string(), "OS2.0.201.0.VNREUXM");
+    }
+
+    #[test]
+    fn unrecognized_versions_are_errors() {
+        assert!(check_update("unknown", "{}").is_err());
+        assert!(check_update(
+            INSTALLED,
+            r#"{"LatestRom": {"filename": "rom.zip", "md5": "a"}}"#
+        )
+        .is_err());
+    }
+}
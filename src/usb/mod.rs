@@ -163,7 +163,7 @@ impl UsbTransport {
                 bail!("USB bulk write returned 0 bytes (stall or timeout)");
             }
             if self.debug_usb {
-                eprintln!("usb out: {} bytes", n);
+                tracing::debug!(bytes = n, "usb out");
             }
             written += n;
         }
@@ -181,7 +181,7 @@ impl UsbTransport {
                 bail!("USB bulk read returned 0 bytes (stall or timeout)");
             }
             if self.debug_usb {
-                eprintln!("usb in: {} bytes", n);
+                tracing::debug!(bytes = n, "usb in");
             }
             read += n;
         }
@@ -0,0 +1,63 @@
+// Copyright (C) 2026 Chromatic
+// Licensed under the GNU AGPL v3.0. See LICENSE file for details.
+// Website: https://chromatic.hu
+
+//! Per-user directories, following XDG on Linux and platform conventions on
+//! Windows and macOS.
+
+use std::env;
+use std::path::PathBuf;
+
+const APP: &str = "sensitivity";
+
+fn env_path(name: &str) -> Option<PathBuf> {
+    env::var_os(name)
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+}
+
+/// Directory for persistent application data, such as the ROM library.
+pub fn data_dir() -> Option<PathBuf> {
+    if let Some(dir) = env_path("XDG_DATA_HOME") {
+        return Some(dir.join(APP));
+    }
+    #[cfg(windows)]
+    {
+        env_path("LOCALAPPDATA").map(|dir| dir.join(APP))
+    }
+    #[cfg(target_os = "macos")]
+    {
+        env_path("HOME").map(|home| home.join("Library/Application Support").join(APP))
+    }
+    #[cfg(not(any(windows, target_os = "macos")))]
+    {
+        env_path("HOME").map(|home| home.join(".local/share").join(APP))
+    }
+}
+
+/// Directory for user settings.
+pub fn config_dir() -> Option<PathBuf> {
+    if let Some(dir) = env_path("XDG_CONFIG_HOME") {
+        return Some(dir.join(APP));
+    }
+    #[cfg(windows)]
+    {
+        env_path("APPDATA").map(|dir| dir.join(APP))
+    }
+    #[cfg(target_os = "macos")]
+    {
+        env_path("HOME").map(|home| home.join("Library/Application Support").join(APP))
+    }
+    #[cfg(not(any(windows, target_os = "macos")))]
+    {
+        env_path("HOME").map(|home| home.join(".config").join(APP))
+    }
+}
+
+/// Directory for sockets and other per-session files. Falls back to the data
+/// directory where the platform has no runtime directory.
+pub fn runtime_dir() -> Option<PathBuf> {
+    env_path("XDG_RUNTIME_DIR")
+        .map(|dir| dir.join(APP))
+        .or_else(data_dir)
+}
@@ -0,0 +1,266 @@
+// Copyright (C) 2026 Chromatic
+// Licensed under the GNU AGPL v3.0. See LICENSE file for details.
+// Website: https://chromatic.hu
+
+//! Persistent MD5 cache for large packages.
+//!
+//! An entry is reused only while the file's canonical path, size, mtime and,
+//! on Unix, device, inode and ctime all match what was recorded. ctime cannot
+//! be set back by a user, so rewriting a file and restoring its mtime still
+//! invalidates it. A file whose timestamps fall within a few seconds of the
+//! hash is not cached at all, because a write in the same timestamp tick would
+//! leave the key unchanged.
+
+use std::collections::BTreeMap;
+use std::fs::{self, Metadata};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+
+use super::md5::md5_file_observed;
+use crate::cancel::CancelToken;
+use crate::observer::Observer;
+
+const CACHE_FILE: &str = "hash-cache.json";
+const MAX_ENTRIES: usize = 512;
+const RACY_WINDOW: Duration = Duration::from_secs(2);
+
+/// What identifies one version of a file.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+struct FileKey {
+    size: u64,
+    mtime_ns: u128,
+    #[serde(default)]
+    ctime_ns: i128,
+    #[serde(default)]
+    dev: u64,
+    #[serde(default)]
+    inode: u64,
+}
+
+impl FileKey {
+    fn of(metadata: &Metadata) -> Self {
+        #[cfg(unix)]
+        use std::os::unix::fs::MetadataExt;
+        Self {
+            size: metadata.len(),
+            mtime_ns: metadata
+                .modified()
+                .ok()
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |elapsed| elapsed.as_nanos()),
+            #[cfg(unix)]
+            ctime_ns: i128::from(metadata.ctime()) * 1_000_000_000
+                + i128::from(metadata.ctime_nsec()),
+            #[cfg(not(unix))]
+            ctime_ns: 0,
+            #[cfg(unix)]
+            dev: metadata.dev(),
+            #[cfg(not(unix))]
+            dev: 0,
+            #[cfg(unix)]
+            inode: metadata.ino(),
+            #[cfg(not(unix))]
+            inode: 0,
+        }
+    }
+
+    /// Latest change time, in nanoseconds since the epoch.
+    fn changed_ns(&self) -> i128 {
+        (self.mtime_ns as i128).max(self.ctime_ns)
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+struct CacheEntry {
+    key: FileKey,
+    md5: String,
+    /// Seconds since the epoch, for eviction.
+    used: u64,
+}
+
+pub struct HashCache {
+    path: Option<PathBuf>,
+    racy_window: Duration,
+}
+
+impl HashCache {
+    /// The cache in the per-user data directory, or a pass-through cache
+    /// when there is none.
+    pub fn open_default() -> Self {
+        Self {
+            path: super::dirs::data_dir().map(|dir| dir.join(CACHE_FILE)),
+            racy_window: RACY_WINDOW,
+        }
+    }
+
+    pub fn at(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: Some(path.into()),
+            racy_window: RACY_WINDOW,
+        }
+    }
+
+    fn load(&self) -> BTreeMap<PathBuf, CacheEntry> {
+        self.path
+            .as_ref()
+            .and_then(|path| fs::read(path).ok())
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self, entries: &BTreeMap<PathBuf, CacheEntry>) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let temporary = path.with_extension("json.tmp");
+        fs::write(&temporary, serde_json::to_vec(entries)?)?;
+        fs::rename(&temporary, path)?;
+        Ok(())
+    }
+
+    /// Returns the MD5 of `path`, hashing it unless an entry for this exact
+    /// version of the file is cached.
+    pub fn md5(
+        &self,
+        path: &Path,
+        observer: &dyn Observer,
+        cancel: &CancelToken,
+    ) -> Result<String> {
+        let canonical =
+            fs::canonicalize(path).with_context(|| format!("Opening {}", path.display()))?;
+        let before = FileKey::of(&fs::metadata(&canonical)?);
+        let mut entries = self.load();
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        if let Some(entry) = entries.get_mut(&canonical) {
+            if entry.key == before {
+                entry.used = now.as_secs();
+                let md5 = entry.md5.clone();
+                tracing::debug!(path = %canonical.display(), %md5, "MD5 from cache");
+                let _ = self.save(&entries);
+                return Ok(md5);
+            }
+        }
+        let md5 = md5_file_observed(&canonical, observer, cancel)?;
+        let after = FileKey::of(&fs::metadata(&canonical)?);
+        let settled =
+            (now.saturating_sub(self.racy_window).as_nanos() as i128) > after.changed_ns();
+        if after == before && settled {
+            entries.insert(
+                canonical,
+                CacheEntry {
+                    key: after,
+                    md5: md5.clone(),
+                    used: now.as_secs(),
+                },
+            );
+            while entries.len() > MAX_ENTRIES {
+                let Some(oldest) = entries
+                    .iter()
+                    .min_by_key(|(_, entry)| entry.used)
+                    .map(|(path, _)| path.clone())
+                else {
+                    break;
+                };
+                entries.remove(&oldest);
+            }
+            // A cache that cannot be written only costs a rehash next time.
+            let _ = self.save(&entries);
+        }
+        Ok(md5)
+    }
+}
+
+/// Hashes through the default cache.
+pub fn md5_cached(path: &Path, observer: &dyn Observer, cancel: &CancelToken) -> Result<String> {
+    HashCache::open_default().md5(path, observer, cancel)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cache(dir: &Path) -> HashCache {
+        HashCache {
+            path: Some(dir.join(CACHE_FILE)),
+            racy_window: Duration::ZERO,
+        }
+    }
+
+    /// Records whether any hashing progress was reported.
+    #[derive(Default)]
+    struct Hashed(std::sync::atomic::AtomicBool);
+
+    impl Observer for Hashed {
+        fn progress(&self, _phase: crate::observer::Phase, _current: u64, _total: u64) {
+            self.0.store(true, std::sync::atomic::Ordering::Relaxed);
+        }
+    }
+
+    fn hashed(cache: &HashCache, path: &Path) -> (String, bool) {
+        let hashed = Hashed::default();
+        let md5 = cache.md5(path, &hashed, &CancelToken::new()).unwrap();
+        (md5, hashed.0.into_inner())
+    }
+
+    #[test]
+    fn unchanged_files_are_served_from_the_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = cache(dir.path());
+        let rom = dir.path().join("rom.zip");
+        fs::write(&rom, b"hello world").unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+
+        assert_eq!(
+            hashed(&cache, &rom),
+            ("5eb63bbbe01eeed093cb22bb8f5acdc3".into(), true)
+        );
+        assert_eq!(
+            hashed(&cache, &rom),
+            ("5eb63bbbe01eeed093cb22bb8f5acdc3".into(), false)
+        );
+    }
+
+    #[test]
+    fn rewritten_files_are_rehashed_even_with_the_old_mtime() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = cache(dir.path());
+        let rom = dir.path().join("rom.zip");
+        fs::write(&rom, b"hello world").unwrap();
+        let mtime = fs::metadata(&rom).unwrap().modified().unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        hashed(&cache, &rom);
+
+        std::thread::sleep(Duration::from_millis(20));
+        fs::write(&rom, b"hello there").unwrap();
+        fs::File::options()
+            .write(true)
+            .open(&rom)
+            .unwrap()
+            .set_modified(mtime)
+            .unwrap();
+
+        let (md5, rehashed) = hashed(&cache, &rom);
+        assert!(rehashed || cfg!(not(unix)));
+        if rehashed {
+            assert_eq!(md5, "161bc25962da8fed6d2f59922fb642aa");
+        }
+    }
+
+    #[test]
+    fn freshly_written_files_are_not_cached() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = HashCache::at(dir.path().join(CACHE_FILE));
+        let rom = dir.path().join("rom.zip");
+        fs::write(&rom, b"hello world").unwrap();
+
+        hashed(&cache, &rom);
+        assert!(hashed(&cache, &rom).1);
+    }
+}
@@ -2,26 +2,81 @@
 // Licensed under the GNU AGPL v3.0. See LICENSE file for details.
 // Website: https://chromatic.hu
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use md5::{Digest, Md5};
 use std::fs::File;
-use std::io::{BufReader, Read};
+use std::io::Read;
 use std::path::Path;
+use std::sync::mpsc;
+use std::thread;
+
+use crate::cancel::CancelToken;
+use crate::observer::{Observer, Phase, Silent};
+
+/// Large reads keep the disk busy; two buffers in flight let reading and
+/// hashing overlap.
+const BLOCK_SIZE: usize = 4 * 1024 * 1024;
+const BLOCKS_IN_FLIGHT: usize = 2;
 
 pub fn md5_file(path: &Path) -> Result<String> {
-    let file = File::open(path)?;
-    let mut reader = BufReader::new(file);
+    md5_file_observed(path, &Silent, &CancelToken::new())
+}
+
+/// Hashes `path`, reporting [`Phase::Hash`] progress after each block and
+/// stopping early when `cancel` fires.
+#[tracing::instrument(name = "hash", skip_all, fields(path = %path.display()))]
+pub fn md5_file_observed(
+    path: &Path,
+    observer: &dyn Observer,
+    cancel: &CancelToken,
+) -> Result<String> {
+    let mut file = File::open(path)?;
+    let total = file.metadata()?.len();
+    let (sender, receiver) = mpsc::sync_channel::<std::io::Result<Vec<u8>>>(BLOCKS_IN_FLIGHT);
+    let (recycle, recycled) = mpsc::channel::<Vec<u8>>();
+    let reader = thread::spawn(move || loop {
+        let mut block = recycled
+            .try_recv()
+            .unwrap_or_else(|_| vec![0u8; BLOCK_SIZE]);
+        block.resize(BLOCK_SIZE, 0);
+        let mut filled = 0;
+        while filled < BLOCK_SIZE {
+            match file.read(&mut block[filled..]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(error) if error.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(error) => {
+                    let _ = sender.send(Err(error));
+                    return;
+                }
+            }
+        }
+        block.truncate(filled);
+        let last = filled < BLOCK_SIZE;
+        if sender.send(Ok(block)).is_err() || last {
+            return;
+        }
+    });
+    observer.phase(Phase::Hash);
     let mut hasher = Md5::new();
-    let mut buf = [0u8; 64 * 1024];
-    loop {
-        let n = reader.read(&mut buf)?;
-        if n == 0 {
+    let mut done = 0u64;
+    for block in receiver {
+        cancel.check()?;
+        let block = block?;
+        if block.is_empty() {
             break;
         }
-        hasher.update(&buf[..n]);
+        hasher.update(&block);
+        done += block.len() as u64;
+        observer.progress(Phase::Hash, done, total);
+        let _ = recycle.send(block);
     }
-    let digest = hasher.finalize();
-    Ok(hex::encode(digest))
+    reader
+        .join()
+        .map_err(|_| anyhow!("MD5 reader thread panicked"))?;
+    let md5 = hex::encode(hasher.finalize());
+    tracing::debug!(%md5, bytes = done, "hashed");
+    Ok(md5)
 }
 
 #[cfg(test)]
@@ -37,4 +92,42 @@ mod tests {
         let got = md5_file(tf.path()).unwrap();
         assert_eq!(got, "5eb63bbbe01eeed093cb22bb8f5acdc3");
     }
+
+    #[derive(Default)]
+    struct Reports(std::sync::Mutex<Vec<(u64, u64)>>);
+
+    impl Observer for Reports {
+        fn progress(&self, _phase: Phase, current: u64, total: u64) {
+            self.0.lock().unwrap().push((current, total));
+        }
+    }
+
+    #[test]
+    fn progress_covers_files_larger_than_one_block() {
+        let mut tf = NamedTempFile::new().unwrap();
+        let data = vec![0x5au8; BLOCK_SIZE * 2 + 17];
+        tf.write_all(&data).unwrap();
+        let reports = Reports::default();
+
+        let got = md5_file_observed(tf.path(), &reports, &CancelToken::new()).unwrap();
+
+        assert_eq!(got, hex::encode(Md5::digest(&data)));
+        let total = data.len() as u64;
+        assert_eq!(
+            reports.0.into_inner().unwrap(),
+            [
+                (BLOCK_SIZE as u64, total),
+                (2 * BLOCK_SIZE as u64, total),
+                (total, total)
+            ]
+        );
+    }
+
+    #[test]
+    fn cancelled_hashing_stops() {
+        let tf = NamedTempFile::new().unwrap();
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        assert!(md5_file_observed(tf.path(), &Silent, &cancel).is_err());
+    }
 }
@@ -3,4 +3,7 @@
 // Website: https://chromatic.hu
 
 pub mod adb_server;
+pub mod dirs;
+pub mod hash_cache;
 pub mod md5;
+pub mod zip;
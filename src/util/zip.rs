@@ -0,0 +1,226 @@
+// Copyright (C) 2026 Chromatic
+// Licensed under the GNU AGPL v3.0. See LICENSE file for details.
+// Website: https://chromatic.hu
+
+//! Just enough ZIP reading to locate stored entries inside multi-gigabyte
+//! Recovery ROMs without decompressing or buffering them. ZIP64 records are
+//! supported because current HyperOS packages exceed 4 GiB.
+
+use std::io::{Read, Seek, SeekFrom};
+
+use anyhow::{bail, Context, Result};
+use byteorder::{ByteOrder, LittleEndian};
+
+const EOCD_MAGIC: u32 = 0x0605_4b50;
+const ZIP64_LOCATOR_MAGIC: u32 = 0x0706_4b50;
+const ZIP64_EOCD_MAGIC: u32 = 0x0606_4b50;
+const CENTRAL_MAGIC: u32 = 0x0201_4b50;
+const LOCAL_MAGIC: u32 = 0x0403_4b50;
+const EOCD_SIZE: usize = 22;
+const CENTRAL_SIZE: usize = 46;
+const LOCAL_SIZE: usize = 30;
+const MAX_CENTRAL_DIRECTORY: u64 = 64 * 1024 * 1024;
+
+pub const METHOD_STORED: u16 = 0;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ZipEntry {
+    pub name: String,
+    pub method: u16,
+    pub compressed_size: u64,
+    pub uncompressed_size: u64,
+    local_header_offset: u64,
+}
+
+/// Lists the central directory of an archive.
+pub fn list_entries<R: Read + Seek>(reader: &mut R) -> Result<Vec<ZipEntry>> {
+    let file_len = reader.seek(SeekFrom::End(0))?;
+    if file_len < EOCD_SIZE as u64 {
+        bail!("Not a ZIP archive: {file_len} bytes is too short for an end of central directory");
+    }
+    let tail_len = file_len.min(u16::MAX as u64 + EOCD_SIZE as u64);
+    let mut tail = vec![0u8; tail_len as usize];
+    reader.seek(SeekFrom::Start(file_len - tail_len))?;
+    reader.read_exact(&mut tail)?;
+    let eocd_index = (0..=tail.len().saturating_sub(EOCD_SIZE))
+        .rev()
+        .find(|index| {
+            LittleEndian::read_u32(&tail[*index..]) == EOCD_MAGIC
+                && index + EOCD_SIZE + LittleEndian::read_u16(&tail[index + 20..]) as usize
+                    == tail.len()
+        })
+        .ok_or_else(|| anyhow::anyhow!("ZIP end of central directory not found"))?;
+    let eocd = &tail[eocd_index..];
+    let eocd_offset = file_len - tail_len + eocd_index as u64;
+    let mut entries = LittleEndian::read_u16(&eocd[10..12]) as u64;
+    let mut directory_size = LittleEndian::read_u32(&eocd[12..16]) as u64;
+    let mut directory_offset = LittleEndian::read_u32(&eocd[16..20]) as u64;
+
+    if (entries == 0xffff || directory_size == 0xffff_ffff || directory_offset == 0xffff_ffff)
+        && eocd_offset >= 20
+    {
+        let mut locator = [0u8; 20];
+        reader.seek(SeekFrom::Start(eocd_offset - 20))?;
+        reader.read_exact(&mut locator)?;
+        if LittleEndian::read_u32(&locator) == ZIP64_LOCATOR_MAGIC {
+            let mut record = [0u8; 56];
+            reader.seek(SeekFrom::Start(LittleEndian::read_u64(&locator[8..16])))?;
+            reader.read_exact(&mut record)?;
+            if LittleEndian::read_u32(&record) != ZIP64_EOCD_MAGIC {
+                bail!("ZIP64 end of central directory record is damaged");
+            }
+            entries = LittleEndian::read_u64(&record[32..40]);
+            directory_size = LittleEndian::read_u64(&record[40..48]);
+            directory_offset = LittleEndian::read_u64(&record[48..56]);
+        }
+    }
+    if directory_size > MAX_CENTRAL_DIRECTORY
+        || directory_offset.saturating_add(directory_size) > file_len
+    {
+        bail!("ZIP central directory lies outside the archive; it may be truncated");
+    }
+
+    let mut directory = vec![0u8; directory_size as usize];
+    reader.seek(SeekFrom::Start(directory_offset))?;
+    reader
+        .read_exact(&mut directory)
+        .context("Reading ZIP central directory")?;
+    let mut out = Vec::new();
+    let mut cursor = 0usize;
+    for _ in 0..entries {
+        let header = directory
+            .get(cursor..cursor + CENTRAL_SIZE)
+            .filter(|header| LittleEndian::read_u32(header) == CENTRAL_MAGIC)
+            .ok_or_else(|| anyhow::anyhow!("ZIP central directory entry is damaged"))?;
+        let method = LittleEndian::read_u16(&header[10..12]);
+        let mut compressed_size = LittleEndian::read_u32(&header[20..24]) as u64;
+        let mut uncompressed_size = LittleEndian::read_u32(&header[24..28]) as u64;
+        let name_len = LittleEndian::read_u16(&header[28..30]) as usize;
+        let extra_len = LittleEndian::read_u16(&header[30..32]) as usize;
+        let comment_len = LittleEndian::read_u16(&header[32..34]) as usize;
+        let mut local_header_offset = LittleEndian::read_u32(&header[42..46]) as u64;
+        let name_start = cursor + CENTRAL_SIZE;
+        let extra_start = name_start + name_len;
+        let next = extra_start + extra_len + comment_len;
+        if next > directory.len() {
+            bail!("ZIP central directory entry overruns the directory");
+        }
+        let name = String::from_utf8_lossy(&directory[name_start..extra_start]).into_owned();
+        let mut extra = &directory[extra_start..extra_start + extra_len];
+        while extra.len() >= 4 {
+            let id = LittleEndian::read_u16(&extra[0..2]);
+            let size = (LittleEndian::read_u16(&extra[2..4]) as usize).min(extra.len() - 4);
+            if id == 0x0001 {
+                // ZIP64 values appear only for fields saturated in the header.
+                let mut values = extra[4..4 + size]
+                    .chunks_exact(8)
+                    .map(LittleEndian::read_u64);
+                if uncompressed_size == 0xffff_ffff {
+                    uncompressed_size = values.next().unwrap_or(uncompressed_size);
+                }
+                if compressed_size == 0xffff_ffff {
+                    compressed_size = values.next().unwrap_or(compressed_size);
+                }
+                if local_header_offset == 0xffff_ffff {
+                    local_header_offset = values.next().unwrap_or(local_header_offset);
+                }
+            }
+            extra = &extra[4 + size..];
+        }
+        out.push(ZipEntry {
+            name,
+            method,
+            compressed_size,
+            uncompressed_size,
+            local_header_offset,
+        });
+        cursor = next;
+    }
+    Ok(out)
+}
+
+/// Returns the absolute offset of an entry's data.
+pub fn data_offset<R: Read + Seek>(reader: &mut R, entry: &ZipEntry) -> Result<u64> {
+    let mut header = [0u8; LOCAL_SIZE];
+    reader.seek(SeekFrom::Start(entry.local_header_offset))?;
+    reader.read_exact(&mut header)?;
+    if LittleEndian::read_u32(&header) != LOCAL_MAGIC {
+        bail!("ZIP local header for {} is damaged", entry.name);
+    }
+    let name_len = LittleEndian::read_u16(&header[26..28]) as u64;
+    let extra_len = LittleEndian::read_u16(&header[28..30]) as u64;
+    Ok(entry.local_header_offset + LOCAL_SIZE as u64 + name_len + extra_len)
+}
+
+/// Locates an uncompressed entry and returns its data offset and length.
+pub fn find_stored<R: Read + Seek>(reader: &mut R, name: &str) -> Result<Option<(u64, u64)>> {
+    let Some(entry) = list_entries(reader)?
+        .into_iter()
+        .find(|entry| entry.name == name)
+    else {
+        return Ok(None);
+    };
+    if entry.method != METHOD_STORED {
+        bail!("ZIP entry {name} is compressed; OTA packages store it uncompressed");
+    }
+    let offset = data_offset(reader, &entry)?;
+    Ok(Some((offset, entry.uncompressed_size)))
+}
+
+/// Reads a small stored entry into memory.
+pub fn read_stored<R: Read + Seek>(reader: &mut R, name: &str) -> Result<Option<Vec<u8>>> {
+    let Some((offset, len)) = find_stored(reader, name)? else {
+        return Ok(None);
+    };
+    if len > MAX_CENTRAL_DIRECTORY {
+        bail!("ZIP entry {name} is too large to read into memory");
+    }
+    let mut data = vec![0u8; len as usize];
+    reader.seek(SeekFrom::Start(offset))?;
+    reader.read_exact(&mut data)?;
+    Ok(Some(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const PACKAGE: &[u8] = include_bytes!("../ota/testdata/signed-ota.zip");
+
+    #[test]
+    fn stored_entries_are_found_through_a_signed_comment() {
+        let mut reader = Cursor::new(PACKAGE);
+        let names: Vec<_> = list_entries(&mut reader)
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "META-INF/com/google/android/update-binary",
+                "payload_properties.txt"
+            ]
+        );
+        assert_eq!(
+            read_stored(&mut reader, "payload_properties.txt").unwrap(),
+            Some(b"FILE_HASH=test\n".to_vec())
+        );
+        assert_eq!(read_stored(&mut reader, "payload.bin").unwrap(), None);
+    }
+
+    #[test]
+    fn truncated_archive_is_rejected() {
+        let mut reader = Cursor::new(&PACKAGE[..PACKAGE.len() / 2]);
+        assert!(list_entries(&mut reader).is_err());
+    }
+
+    #[test]
+    fn files_shorter_than_a_directory_record_are_not_zips() {
+        for bytes in [&b"PK"[..], b"PK\x05\x06abcdef", b""] {
+            let error = list_entries(&mut Cursor::new(bytes)).unwrap_err();
+            assert!(error.to_string().contains("Not a ZIP"));
+        }
+    }
+}
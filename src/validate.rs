@@ -2,15 +2,29 @@
 // Licensed under the GNU AGPL v3.0. See LICENSE file for details.
 // Website: https://chromatic.hu
 
-use crate::i18n::tr;
+use crate::i18n::{tr, trf};
 use anyhow::{anyhow, bail, Context, Result};
 use base64::{engine::general_purpose, Engine as _};
 use cbc::cipher::{block_padding::Pkcs7, BlockModeDecrypt, BlockModeEncrypt, KeyIvInit};
+#[cfg(feature = "validation-net")]
 use reqwest::blocking::Client;
 use serde::Deserialize;
+use std::collections::BTreeMap;
+#[cfg(feature = "validation-net")]
 use std::time::Duration;
 
-use crate::mi::DeviceInfo;
+#[cfg(feature = "validation-net")]
+use crate::cancel::CancelToken;
+use crate::mi::{version::RomVersion, DeviceInfo};
+#[cfg(feature = "validation-net")]
+use crate::observer::{Observer, Phase, Silent};
+use crate::secret::Token;
+
+/// Xiaomi's validation endpoint.
+pub const DEFAULT_SERVER_URL: &str = "https://update.miui.com/updates/miotaV3.php";
+
+#[cfg(feature = "validation-net")]
+const VALIDATE_TIMEOUT: Duration = Duration::from_secs(30);
 
 type Aes128CbcEnc = cbc::Encryptor<aes::Aes128>;
 type Aes128CbcDec = cbc::Decryptor<aes::Aes128>;
@@ -50,8 +64,143 @@ pub struct ValidateResult {
     pub pkgrom_validate: Option<Vec<String>>,
     pub pkgrom_erase: Option<i32>,
     pub code_message: Option<String>,
-    pub validate_token: Option<String>,
+    pub validate_token: Option<Token>,
+    /// The decrypted response, with the token shown as the redaction policy
+    /// allows.
     pub full_json: Option<String>,
+    pub latest_rom: Option<RomPackage>,
+    pub increment_rom: Option<RomPackage>,
+    /// Changelog section name to icon URL, from the response's `Icon` map.
+    pub icons: BTreeMap<String, String>,
+}
+
+impl ValidateResult {
+    /// Offered packages, the full one first.
+    pub fn packages(&self) -> impl Iterator<Item = (PackageKind, &RomPackage)> {
+        [
+            (PackageKind::Latest, self.latest_rom.as_ref()),
+            (PackageKind::Incremental, self.increment_rom.as_ref()),
+        ]
+        .into_iter()
+        .filter_map(|(kind, package)| Some((kind, package?)))
+    }
+}
+
+/// Which offer a [`RomPackage`] is; front ends pick their own label for it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PackageKind {
+    /// `LatestRom`, a full package.
+    Latest,
+    /// `IncrementRom`, applicable to the installed build only.
+    Incremental,
+}
+
+/// A ROM the server offers, such as `LatestRom` or `IncrementRom`.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct RomPackage {
+    pub filename: String,
+    pub md5: String,
+    pub version: Option<RomVersion>,
+    /// Size as the server reports it, e.g. `6.2G`.
+    pub size: Option<String>,
+    pub codebase: Option<String>,
+    pub description: Option<String>,
+    pub description_url: Option<String>,
+    pub changelog: Vec<ChangelogSection>,
+}
+
+impl RomPackage {
+    /// Version and size for one-line display, falling back to the file name.
+    pub fn summary(&self) -> String {
+        let mut summary = self
+            .version
+            .as_ref()
+            .map_or_else(|| self.filename.clone(), ToString::to_string);
+        if let Some(size) = &self.size {
+            summary.push_str(&format!(" ({size})"));
+        }
+        summary
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct ChangelogSection {
+    pub title: String,
+    pub items: Vec<String>,
+    pub icon: Option<String>,
+}
+
+/// Reads the `Icon` map that names an image for each changelog section.
+pub fn icon_map(response: &serde_json::Value) -> BTreeMap<String, String> {
+    response
+        .get("Icon")
+        .and_then(|icons| icons.as_object())
+        .map(|icons| {
+            icons
+                .iter()
+                .filter_map(|(name, url)| Some((name.clone(), url.as_str()?.to_string())))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Reads one package object from a decrypted validation response.
+pub fn rom_package(response: &serde_json::Value, key: &str) -> Option<RomPackage> {
+    let package = response.get(key)?.as_object()?;
+    let text = |name: &str| match package.get(name)? {
+        serde_json::Value::String(value) if !value.is_empty() => Some(value.clone()),
+        serde_json::Value::Number(value) => Some(value.to_string()),
+        _ => None,
+    };
+    let filename = text("filename")?;
+    let version = text("version")
+        .and_then(|version| version.parse().ok())
+        .or_else(|| RomVersion::from_package_name(&filename));
+    let icons = icon_map(response);
+    let changelog = package
+        .get("changelog")
+        .and_then(|changelog| changelog.as_object())
+        .map(|sections| {
+            sections
+                .iter()
+                .map(|(title, section)| ChangelogSection {
+                    title: title.clone(),
+                    items: changelog_items(section),
+                    icon: icons.get(title).cloned(),
+                })
+                .filter(|section| !section.items.is_empty())
+                .collect()
+        })
+        .unwrap_or_default();
+    Some(RomPackage {
+        md5: text("md5").unwrap_or_default(),
+        size: text("filesize"),
+        codebase: text("codebase"),
+        description: text("description"),
+        description_url: text("descriptionUrl"),
+        filename,
+        version,
+        changelog,
+    })
+}
+
+fn changelog_items(section: &serde_json::Value) -> Vec<String> {
+    // Sections are either {"txt": [...]} or a bare list of lines.
+    let lines = section.get("txt").unwrap_or(section);
+    match lines {
+        serde_json::Value::Array(lines) => lines
+            .iter()
+            .filter_map(|line| line.as_str())
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(str::to_string)
+            .collect(),
+        serde_json::Value::String(line) if !line.trim().is_empty() => {
+            vec![line.trim().to_string()]
+        }
+        _ => Vec::new(),
+    }
 }
 
 pub fn build_request_json(info: &DeviceInfo, md5_opt: Option<String>) -> Result<String> {
@@ -65,7 +214,7 @@ pub fn build_request_json(info: &DeviceInfo, md5_opt: Option<String>) -> Result<
         esc(&info.version),
         esc(&info.codebase),
         esc(&info.branch),
-        esc(&info.sn),
+        esc(info.sn.reveal()),
         zone_field,
         esc(&md5),
     );
@@ -144,21 +293,52 @@ struct ResponseRoot {
     code: Option<ResponseCode>,
 }
 
+/// The form value `q` carrying an encrypted request body.
+pub fn encode_request(json_body: &str) -> Result<String> {
+    aes128_cbc_encrypt_b64(json_body.as_bytes())
+}
+
+#[cfg(feature = "validation-net")]
 pub fn validate(server_url: &str, json_body: &str) -> Result<ValidateResult> {
-    let enc = aes128_cbc_encrypt_b64(json_body.as_bytes())?;
+    validate_observed(server_url, json_body, &Silent, &CancelToken::new())
+}
+
+/// Like [`validate`], bounding the request by the token's deadline and
+/// returning [`Cancelled`](crate::cancel::Cancelled) when it fires.
+#[cfg(feature = "validation-net")]
+#[tracing::instrument(name = "validate", skip_all, fields(server = server_url))]
+pub fn validate_observed(
+    server_url: &str,
+    json_body: &str,
+    observer: &dyn Observer,
+    cancel: &CancelToken,
+) -> Result<ValidateResult> {
+    observer.phase(Phase::Validate);
+    cancel.check()?;
+    let enc = encode_request(json_body)?;
     let form = [("q", enc.as_str()), ("t", ""), ("s", "1")];
-    let client = Client::builder().timeout(Duration::from_secs(30)).build()?;
+    let timeout = cancel.remaining().map_or(VALIDATE_TIMEOUT, |remaining| {
+        remaining.min(VALIDATE_TIMEOUT)
+    });
+    let client = Client::builder().timeout(timeout).build()?;
+    tracing::debug!(?timeout, "sending validation request");
     let resp = client
         .post(server_url)
         .header("User-Agent", "MiTunes_UserAgent_v3.0")
         .form(&form)
         .send();
+    cancel.check()?;
     let resp = match resp {
         Ok(r) => r,
         Err(e) => bail!("HTTP request failed: {}", e),
     };
     let status = resp.status();
     let text = resp.text().unwrap_or_default();
+    tracing::debug!(
+        status = status.as_u16(),
+        bytes = text.len(),
+        "validation response"
+    );
     if !status.is_success() {
         let head = text.bytes().take(200).collect::<Vec<_>>();
         let head_hex = hex::encode(&head);
@@ -169,10 +349,15 @@ pub fn validate(server_url: &str, json_body: &str) -> Result<ValidateResult> {
             head_hex
         );
     }
+    parse_response(&text)
+}
+
+/// Decrypts and interprets a validation server response body.
+pub fn parse_response(text: &str) -> Result<ValidateResult> {
     if text.trim().is_empty() {
         bail!("Validation failed: empty response body");
     }
-    let plain = aes128_cbc_decrypt_b64(&text).context("Decrypting server response")?;
+    let plain = aes128_cbc_decrypt_b64(text).context("Decrypting server response")?;
     let preview = String::from_utf8_lossy(&plain);
     let json_text = extract_json_braces(&preview)
         .ok_or_else(|| anyhow!("No JSON object found in plaintext (len {})", plain.len()))?;
@@ -183,12 +368,12 @@ pub fn validate(server_url: &str, json_body: &str) -> Result<ValidateResult> {
         if let Some(v) = pkg.validate {
             match v {
                 ValidateField::Arr(list) => out.pkgrom_validate = Some(list),
-                ValidateField::Str(s) => out.validate_token = Some(s),
+                ValidateField::Str(s) => out.validate_token = Some(s.into()),
             }
         }
         if out.validate_token.is_none() {
             if let Some(tok) = pkg.token {
-                out.validate_token = Some(tok);
+                out.validate_token = Some(tok.into());
             }
         }
         out.pkgrom_erase = pkg.erase;
@@ -198,7 +383,24 @@ pub fn validate(server_url: &str, json_body: &str) -> Result<ValidateResult> {
             out.code_message = Some(code.message);
         }
     }
-    out.full_json = Some(json_text.clone());
+    // Package details are informational; a response without them still validates.
+    match serde_json::from_str::<serde_json::Value>(&json_text) {
+        Ok(mut response) => {
+            out.latest_rom = rom_package(&response, "LatestRom");
+            out.increment_rom = rom_package(&response, "IncrementRom");
+            out.icons = icon_map(&response);
+            redact_tokens(&mut response);
+            out.full_json = Some(response.to_string());
+        }
+        Err(_) => out.full_json = Some(json_text.clone()),
+    }
+    tracing::info!(
+        allowed = out.pkgrom_validate.as_ref().map(Vec::len),
+        erase = out.pkgrom_erase,
+        token = out.validate_token.is_some(),
+        message = out.code_message.as_deref(),
+        "server answered"
+    );
     if out.pkgrom_validate.is_none() && out.code_message.is_none() {
         bail!(
             "Validation response missing expected keys (PkgRom.Validate or Code.message); decrypted payload was {} bytes",
@@ -208,6 +410,20 @@ pub fn validate(server_url: &str, json_body: &str) -> Result<ValidateResult> {
     Ok(out)
 }
 
+/// Replaces the token strings under `PkgRom` by their redacted form.
+fn redact_tokens(response: &mut serde_json::Value) {
+    let Some(pkg_rom) = response.get_mut("PkgRom") else {
+        return;
+    };
+    for key in ["Validate", "Token"] {
+        if let Some(value) = pkg_rom.get_mut(key) {
+            if let Some(token) = value.as_str() {
+                *value = Token::new(token).to_string().into();
+            }
+        }
+    }
+}
+
 pub fn print_allowed(res: &ValidateResult) {
     // Prefer explicit allowed list (PkgRom.Validate)
     if let Some(list) = &res.pkgrom_validate {
@@ -260,6 +476,45 @@ pub fn print_allowed(res: &ValidateResult) {
     }
 }
 
+/// Prints the offered packages with their descriptions and changelog.
+pub fn print_packages(res: &ValidateResult) {
+    for (kind, package) in res.packages() {
+        print_package(kind, package);
+    }
+}
+
+pub fn print_package(kind: PackageKind, package: &RomPackage) {
+    let label = match kind {
+        PackageKind::Latest => "label.latest",
+        PackageKind::Incremental => "label.incremental",
+    };
+    println!("{}", trf(label, &[("{value}", &package.summary())]));
+    println!("  {}", trf("label.file", &[("{value}", &package.filename)]));
+    if !package.md5.is_empty() {
+        println!("  {}", trf("label.md5", &[("{value}", &package.md5)]));
+    }
+    for description in [&package.description, &package.description_url]
+        .into_iter()
+        .flatten()
+    {
+        println!("  {description}");
+    }
+    print_changelog(&package.changelog);
+}
+
+pub fn print_changelog(changelog: &[ChangelogSection]) {
+    if changelog.is_empty() {
+        return;
+    }
+    println!("{}", tr("label.changelog"));
+    for section in changelog {
+        println!("  {}", section.title);
+        for item in &section.items {
+            println!("  - {item}");
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -272,6 +527,88 @@ mod tests {
         assert_eq!(d, msg);
     }
 
+    #[test]
+    fn rom_package_reads_version_size_and_changelog() {
+        let response = serde_json::json!({
+            "LatestRom": {
+                "filename": "miui_GARNETINGlobal_OS2.0.203.0.VNRINXM_0123abcd_15.0.zip",
+                "md5": "0123456789abcdef0123456789abcdef",
+                "filesize": "6.2G",
+                "descriptionUrl": "https://example.invalid/notes",
+                "changelog": {
+                    "System": {"txt": ["Improved stability", " "]},
+                    "Camera": ["Faster shutter"],
+                    "Empty": {"txt": []}
+                }
+            },
+            "Icon": {"System": "https://example.invalid/system.png"}
+        });
+        let package = rom_package(&response, "LatestRom").unwrap();
+
+        assert_eq!(package.version.unwrap().to_string(), "OS2.0.203.0.VNRINXM");
+        assert_eq!(package.size.as_deref(), Some("6.2G"));
+        assert_eq!(package.changelog.len(), 2);
+        assert!(package
+            .changelog
+            .iter()
+            .any(|section| section.title == "System"
+                && section.items == ["Improved stability"]
+                && section.icon.as_deref() == Some("https://example.invalid/system.png")));
+        assert_eq!(
+            package.description_url.as_deref(),
+            Some("https://example.invalid/notes")
+        );
+        assert!(rom_package(&response, "IncrementRom").is_none());
+    }
+
+    #[test]
+    fn response_json_masks_the_token() {
+        let body = r#"{"PkgRom":{"Validate":"secret+token","Erase":0},"Code":{"message":"ok"}}"#;
+        let response = parse_response(&aes128_cbc_encrypt_b64(body.as_bytes()).unwrap()).unwrap();
+        assert_eq!(
+            response.validate_token.as_ref().map(Token::reveal),
+            Some("secret+token")
+        );
+        let json = response.full_json.unwrap();
+        assert!(!json.contains("secret+token"));
+        assert!(json.contains("[redacted]"));
+    }
+
+    #[test]
+    fn response_fills_offered_packages() {
+        let body = include_str!("testdata/miota_v3_response.json");
+        let response = parse_response(&aes128_cbc_encrypt_b64(body.as_bytes()).unwrap()).unwrap();
+
+        let kinds: Vec<_> = response.packages().map(|(kind, _)| kind).collect();
+        assert_eq!(kinds, [PackageKind::Latest, PackageKind::Incremental]);
+
+        let latest = response.latest_rom.as_ref().unwrap();
+        assert_eq!(latest.md5, "0123456789abcdef0123456789abcdef");
+        assert_eq!(latest.summary(), "OS2.0.203.0.VNRINXM (6.2G)");
+        assert_eq!(latest.codebase.as_deref(), Some("15.0"));
+        let camera = latest
+            .changelog
+            .iter()
+            .find(|section| section.title == "Camera")
+            .unwrap();
+        assert_eq!(camera.items, ["Faster shutter", "Sharper night shots"]);
+        assert_eq!(
+            camera.icon.as_deref(),
+            Some("https://example.invalid/camera.png")
+        );
+
+        let incremental = response.increment_rom.as_ref().unwrap();
+        assert_eq!(incremental.md5, "fedcba9876543210fedcba9876543210");
+        assert_eq!(incremental.size.as_deref(), Some("412M"));
+        assert_eq!(incremental.changelog.len(), 1);
+
+        assert_eq!(response.icons.len(), 2);
+        assert_eq!(
+            response.icons.get("System").map(String::as_str),
+            Some("https://example.invalid/system.png")
+        );
+    }
+
     #[test]
     fn test_extract_json() {
         let s = "garbage { \"a\": 1 } trailing";